@@ -0,0 +1,36 @@
+//! Runs a script inside the program and reads back its output and variables
+
+use rush::{Builtin, Context, Shell};
+
+/// Builtin provided by the host application
+struct Greet;
+
+impl Builtin for Greet {
+    fn name(&self) -> &str {
+        "greet"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        let greeting = ctx.shell.var("GREETING").unwrap_or_default();
+        let _ = writeln!(ctx.stdout, "{} {}", greeting, args.join(" "));
+
+        0
+    }
+}
+
+fn main() {
+    let mut shell = Shell::new(false);
+    shell.export_var("GREETING", "hello");
+    shell.register_builtin(Greet);
+
+    let result = shell.run_str(r#"
+        greet from rush
+        name=world
+        ls /nonexistent
+    "#);
+
+    println!("status: {}", result.status);
+    println!("stdout: {:?}", result.stdout);
+    println!("stderr: {:?}", result.stderr);
+    println!("name: {:?}", shell.var("name"));
+}
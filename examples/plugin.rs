@@ -0,0 +1,48 @@
+//! Plugin adding a `hello` builtin and a prompt segment, build it with
+//! `cargo build --example plugin` and copy the library into the plugin
+//! directory
+
+use std::ffi::{c_char, c_int, CStr};
+
+use rush::plugins::{PluginOutput, PluginRegistrar, PLUGIN_ABI_VERSION};
+
+fn write(out: *const PluginOutput, text: &str) {
+    // SAFETY: the shell passes a valid output
+    let out = unsafe { &*out };
+    (out.write)(out.data, text.as_ptr(), text.len());
+}
+
+extern "C" fn hello(stdout: *const PluginOutput, _stderr: *const PluginOutput, argc: usize, argv: *const *const c_char) -> c_int {
+    // SAFETY: the shell passes argc valid C strings
+    let args = unsafe { std::slice::from_raw_parts(argv, argc) };
+    let name = match args.first() {
+        Some(x) => unsafe { CStr::from_ptr(*x) }.to_string_lossy().into_owned(),
+        None => "world".to_string(),
+    };
+
+    write(stdout, &format!("hello {}\n", name));
+
+    0
+}
+
+extern "C" fn complete_hello(out: *const PluginOutput, _argc: usize, _argv: *const *const c_char) {
+    write(out, "world\nrush\n");
+}
+
+extern "C" fn segment(out: *const PluginOutput) {
+    write(out, "(plugin) ");
+}
+
+#[no_mangle]
+pub extern "C" fn rush_plugin_abi_version() -> u32 {
+    PLUGIN_ABI_VERSION
+}
+
+/// # Safety
+/// Called by the shell with a valid registrar
+#[no_mangle]
+pub unsafe extern "C" fn rush_plugin_init(registrar: *const PluginRegistrar) {
+    let registrar = unsafe { &*registrar };
+    (registrar.register_builtin)(registrar.data, c"hello".as_ptr(), hello, Some(complete_hello));
+    (registrar.register_prompt_segment)(registrar.data, segment);
+}
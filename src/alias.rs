@@ -0,0 +1,141 @@
+//! Replacing the names of commands before the line is parsed, for aliases and
+//! the abbreviations of the configuration
+//!
+//! Only plain unquoted names at the start of a command are replaced, after
+//! assignments like `A=1` too. As in other shells a replacement ending with a
+//! blank means the word after it is the start of a command as well.
+
+use std::rc::Rc;
+
+use crate::symbol::Symbol;
+use crate::table::Table;
+use crate::tokenizer::{Lexer, Token, TokenWithInfo};
+
+/// Whether commands start after the token
+fn starts_command(token: &Token) -> bool {
+    match token {
+        Token::Newline(_) => true,
+        Token::Paren(ch) => matches!(ch, '(' | '{'),
+        Token::Symbol(x) => matches!(x.as_str(), ";" | "|" | "&" | "&&" | "||" | "!"),
+        _ => false,
+    }
+}
+
+/// Whether the token ends the word before it even without a blank between
+fn separates(token: &Token) -> bool {
+    starts_command(token) || matches!(token, Token::Paren(_) | Token::Comment(_))
+}
+
+/// Replaces each name at the start of a command with what `replace` returns
+/// for it, the rest of the line is kept as it is, including whatever comes
+/// after an error
+pub fn replace_commands(line: &str, posix: bool, replace: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    let buffer = Rc::new(line.to_string());
+    let tokens: Vec<TokenWithInfo> = Lexer::new(buffer, posix).map_while(|x| x.ok()).collect();
+
+    let mut out = String::new();
+    let mut copied = 0;
+    let mut command = true;
+
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        if separates(&token.token) {
+            match &token.token {
+                Token::Comment(_) => {},
+                x => command = starts_command(x),
+            }
+
+            i += 1;
+            continue;
+        }
+
+        // words are tokens without blanks between them
+        let mut end = i + 1;
+        while tokens.get(end).is_some_and(|x| x.start == tokens[end - 1].end && !separates(&x.token)) {
+            end += 1;
+        }
+
+        let word = &tokens[i..end];
+        let assignment = matches!(word, [TokenWithInfo { token: Token::Identifier(_), .. }, TokenWithInfo { token: Token::Symbol(x), .. }, ..] if x == "=" && word[1].start == word[0].end);
+
+        if command && !assignment {
+            command = false;
+            if let [TokenWithInfo { token: Token::Identifier(name), start, end, .. }] = word {
+                if let Some(replaced) = replace(name.as_str()) {
+                    out.push_str(&line[copied..*start]);
+                    out.push_str(&replaced);
+                    copied = *end;
+
+                    command = replaced.ends_with([' ', '\t']);
+                }
+            }
+        }
+
+        i = end;
+    }
+
+    out.push_str(&line[copied..]);
+    out
+}
+
+/// Expands the aliases in the line, the value of an alias is expanded too but
+/// not with itself so `alias ls='ls -F'` does not loop
+pub fn expand(aliases: &Table<Symbol, String>, line: &str, posix: bool) -> String {
+    expand_except(aliases, line, posix, &mut vec![])
+}
+
+fn expand_except(aliases: &Table<Symbol, String>, line: &str, posix: bool, expanding: &mut Vec<Symbol>) -> String {
+    if aliases.is_empty() {
+        return line.to_string();
+    }
+
+    replace_commands(line, posix, &mut |name| {
+        let name = Symbol::get(name).filter(|x| !expanding.contains(x))?;
+        let value = aliases.get(&name)?;
+
+        expanding.push(name);
+        let expanded = expand_except(aliases, value, posix, expanding);
+        expanding.pop();
+
+        Some(expanded)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(pairs: &[(&str, &str)]) -> Table<Symbol, String> {
+        let mut table = Table::new();
+        for (name, value) in pairs {
+            table.insert(Symbol::intern(name), value.to_string());
+        }
+
+        table
+    }
+
+    #[test]
+    fn only_command_names() {
+        let aliases = aliases(&[("ll", "ls -l")]);
+        assert_eq!(expand(&aliases, "ll ll; x | ll && ll", false), "ls -l ll; x | ls -l && ls -l");
+        assert_eq!(expand(&aliases, "A=1 ll", false), "A=1 ls -l");
+        assert_eq!(expand(&aliases, "'ll' \\ll ll=1 llx", false), "'ll' \\ll ll=1 llx");
+        assert_eq!(expand(&aliases, "echo # ll\nll", false), "echo # ll\nls -l");
+    }
+
+    #[test]
+    fn recursive() {
+        let aliases = aliases(&[("ls", "ls -F"), ("l", "ls"), ("a", "b"), ("b", "a")]);
+        assert_eq!(expand(&aliases, "l", false), "ls -F");
+        assert_eq!(expand(&aliases, "a", false), "a");
+    }
+
+    #[test]
+    fn trailing_blank() {
+        let blank = aliases(&[("sudo", "sudo "), ("ll", "ls -l")]);
+        assert_eq!(expand(&blank, "sudo ll", false), "sudo  ls -l");
+
+        let no_blank = aliases(&[("sudo", "sudo"), ("ll", "ls -l")]);
+        assert_eq!(expand(&no_blank, "sudo ll", false), "sudo ll");
+    }
+}
@@ -0,0 +1,767 @@
+//! Builtin commands, they run inside the shell process
+//!
+//! Host applications can add their own by implementing [`Builtin`] and
+//! registering it with [`Shell::register_builtin`]
+
+use std::cell::LazyCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::error::Error;
+use crate::exec::{quote, Shell, Variable, STATUS_USAGE};
+use crate::features::{has_feature, FEATURES};
+use crate::parser::is_name;
+use crate::symbol::Symbol;
+use crate::table::Table;
+
+/// What the builtin has access to while running
+pub struct Context<'a> {
+    pub shell: &'a mut Shell,
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+
+    /// Builtin is part of a pipeline, where it would run in a subshell so it
+    /// should not change the shell
+    pub in_pipeline: bool,
+}
+
+impl Context<'_> {
+    /// Prints the error of the builtin `name` to its stderr
+    pub fn error(&mut self, name: &str, message: impl Into<String>) {
+        let text = self.shell.render_error(&Error::builtin(name, message));
+        let _ = write!(self.stderr, "{}", text);
+    }
+}
+
+pub trait Builtin {
+    fn name(&self) -> &str;
+
+    /// Usage on the first line followed by a short description, shown by
+    /// editors on hover
+    fn help(&self) -> &str {
+        ""
+    }
+
+    /// Runs the builtin, `args` do not include the name, returns the status
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32;
+
+    /// Candidates for the next argument, `args` are the ones already typed
+    fn complete(&self, _shell: &Shell, _args: &[String]) -> Vec<String> {
+        vec![]
+    }
+}
+
+type Builtins = HashMap<Symbol, Rc<dyn Builtin>>;
+
+/// Builtins loaded when they are first needed
+type Deferred = LazyCell<Builtins, Box<dyn FnOnce() -> Builtins>>;
+
+/// Builtins by name
+#[derive(Default, Clone)]
+pub struct Registry {
+    /// Shared with the registries of subshells until one of them changes
+    builtins: Rc<Builtins>,
+
+    /// Loaded the first time a name is not in `builtins`, shared with the
+    /// subshells so they are only loaded once
+    deferred: Option<Rc<Deferred>>,
+}
+
+impl Registry {
+    /// Registry with all the builtins of the shell, the ones `sh` does not
+    /// have are left out in POSIX mode
+    pub fn with_defaults(posix: bool) -> Self {
+        let mut registry = Self::default();
+        registry.register(Alias);
+        registry.register(Exit);
+        registry.register(Export);
+        registry.register(Return);
+        registry.register(Set);
+        registry.register(Unalias);
+        registry.register(Unset);
+
+        if !posix {
+            registry.register(Caller);
+            registry.register(Features);
+        }
+
+        registry
+    }
+
+    /// Adds the builtin, replacing the one with the same name
+    pub fn register(&mut self, builtin: impl Builtin + 'static) {
+        Rc::make_mut(&mut self.builtins).insert(Symbol::intern(builtin.name()), Rc::new(builtin));
+    }
+
+    /// Builtins that are only loaded when they could be needed, like the
+    /// ones of plugins, they do not replace the ones already registered
+    pub fn defer(&mut self, load: impl FnOnce() -> Vec<Rc<dyn Builtin>> + 'static) {
+        let load = move || load().into_iter().map(|x| (Symbol::intern(x.name()), x)).collect();
+        self.deferred = Some(Rc::new(LazyCell::new(Box::new(load))));
+    }
+
+    fn loaded(&self) -> Option<&Builtins> {
+        self.deferred.as_ref().map(|x| LazyCell::force(x))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Builtin>> {
+        if let Some(builtin) = self.get_registered(name) {
+            return Some(builtin);
+        }
+
+        // the names are interned when they are loaded
+        let loaded = self.loaded()?;
+        loaded.get(&Symbol::get(name)?).cloned()
+    }
+
+    /// Only the registered builtins, without loading the deferred ones
+    pub fn get_registered(&self, name: &str) -> Option<Rc<dyn Builtin>> {
+        Symbol::get(name).and_then(|x| self.builtins.get(&x)).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        let loaded = self.loaded().into_iter().flat_map(|x| x.keys()).filter(|x| !self.builtins.contains_key(x));
+        self.builtins.keys().chain(loaded).map(|x| x.as_str())
+    }
+}
+
+/// Names of the variables, as completion of builtins that take them
+fn var_names(shell: &Shell) -> Vec<String> {
+    let mut names: Vec<String> = shell.vars.keys().map(|x| x.to_string()).collect();
+    names.sort();
+
+    names
+}
+
+/// Names of the aliases, as completion of builtins that take them
+fn alias_names(shell: &Shell) -> Vec<String> {
+    let mut names: Vec<String> = shell.aliases.keys().map(|x| x.to_string()).collect();
+    names.sort();
+
+    names
+}
+
+/// `alias [name[=value]...]`, without arguments prints all the aliases
+struct Alias;
+
+impl Builtin for Alias {
+    fn name(&self) -> &str {
+        "alias"
+    }
+
+    fn help(&self) -> &str {
+        "alias [name[=value]...]\n\nDefines aliases, replacing the name at the start of commands typed interactively with the value, names alone print their alias and without any all are printed"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        if args.is_empty() {
+            let mut aliases: Vec<_> = ctx.shell.aliases.iter().collect();
+            aliases.sort_by_key(|(name, _)| name.as_str());
+
+            for (name, value) in aliases {
+                let _ = writeln!(ctx.stdout, "alias {}={}", name, quote(value));
+            }
+
+            return 0;
+        }
+
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            // anything else would not be a single word for the lexer
+            if !is_name(name) {
+                ctx.error("alias", format!("{}: invalid alias name", name));
+                status = 1;
+                continue;
+            }
+
+            match value {
+                Some(value) => ctx.shell.aliases.insert(Symbol::intern(name), value.to_string()),
+                None => match Symbol::get(name).and_then(|x| ctx.shell.aliases.get(&x)) {
+                    Some(value) => {
+                        let _ = writeln!(ctx.stdout, "alias {}={}", name, quote(value));
+                    },
+                    None => {
+                        ctx.error("alias", format!("{}: not found", name));
+                        status = 1;
+                    },
+                },
+            }
+        }
+
+        status
+    }
+
+    fn complete(&self, shell: &Shell, _args: &[String]) -> Vec<String> {
+        alias_names(shell)
+    }
+}
+
+/// `caller [n]`, prints where the function was called from, with `n` where
+/// the function `n` levels up was called from and its name
+struct Caller;
+
+impl Builtin for Caller {
+    fn name(&self) -> &str {
+        "caller"
+    }
+
+    fn help(&self) -> &str {
+        "caller [n]\n\nPrints the line and file of the call of the current function, with n the line, function and file of the call n levels up"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        let level = match args {
+            [] => None,
+            [x] => match x.parse::<usize>() {
+                Ok(x) => Some(x),
+                Err(_) => {
+                    ctx.error("caller", format!("{}: invalid number", x));
+                    return STATUS_USAGE;
+                },
+            },
+            _ => {
+                ctx.error("caller", "too many arguments");
+                return STATUS_USAGE;
+            },
+        };
+
+        // the first entry is the current function, its caller is next
+        let trace = ctx.shell.backtrace();
+        let Some(frame) = trace.get(level.unwrap_or(0) + 1).filter(|_| !ctx.shell.stack().is_empty()) else {
+            return 1;
+        };
+
+        let _ = match level {
+            Some(_) => writeln!(ctx.stdout, "{} {} {}", frame.line, frame.function, frame.source.name),
+            None => writeln!(ctx.stdout, "{} {}", frame.line, frame.source.name),
+        };
+
+        0
+    }
+}
+
+/// `exit [n]`, in a pipeline it only sets the status like in a subshell
+struct Exit;
+
+impl Builtin for Exit {
+    fn name(&self) -> &str {
+        "exit"
+    }
+
+    fn help(&self) -> &str {
+        "exit [n]\n\nExits the shell with status n, or the status of the last command"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        let status = match args {
+            [] => ctx.shell.status,
+            [x] => match x.parse::<i64>() {
+                Ok(x) => x.rem_euclid(256) as i32,
+                Err(_) => {
+                    ctx.error("exit", format!("{}: numeric argument required", x));
+                    STATUS_USAGE
+                },
+            },
+            _ => {
+                ctx.error("exit", "too many arguments");
+                return 1;
+            },
+        };
+
+        if !ctx.in_pipeline {
+            ctx.shell.exit = Some(status);
+        }
+
+        status
+    }
+}
+
+/// `export [name[=value]...]`, without arguments prints the exported
+/// variables
+struct Export;
+
+impl Builtin for Export {
+    fn name(&self) -> &str {
+        "export"
+    }
+
+    fn help(&self) -> &str {
+        "export [-p] [name[=value]...]\n\nPasses the variables to the environment of commands, without names prints the exported variables"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        if args.is_empty() || args == ["-p"] {
+            let mut exported: Vec<_> = ctx.shell.exported().collect();
+            exported.sort();
+
+            for (name, value) in exported {
+                let _ = writeln!(ctx.stdout, "export {}='{}'", name, value.replace('\'', "'\\''"));
+            }
+
+            return 0;
+        }
+
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+
+            if !is_name(name) {
+                ctx.error("export", format!("{}: not a valid identifier", name));
+                status = 1;
+                continue;
+            }
+
+            let var = ctx.shell.vars.get_or_insert_with(Symbol::intern(name), || Variable {
+                value: String::new(),
+                exported: true,
+            });
+
+            var.exported = true;
+            if let Some(value) = value {
+                var.value = value.to_string();
+            }
+        }
+
+        status
+    }
+
+    fn complete(&self, shell: &Shell, _args: &[String]) -> Vec<String> {
+        var_names(shell)
+    }
+}
+
+/// `features [-q] [name...]`, lists the features or checks if they are all
+/// available
+struct Features;
+
+impl Builtin for Features {
+    fn name(&self) -> &str {
+        "features"
+    }
+
+    fn help(&self) -> &str {
+        "features [-q] [name...]\n\nLists the features of the shell, with names prints if each is available and fails if any is not"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        let (quiet, names) = match args.first().map(|x| x.as_str()) {
+            Some("-q") => (true, &args[1..]),
+            _ => (false, args),
+        };
+
+        if names.is_empty() {
+            for feature in FEATURES {
+                let _ = writeln!(ctx.stdout, "{}", feature);
+            }
+
+            return 0;
+        }
+
+        let mut status = 0;
+        for name in names {
+            let available = has_feature(name);
+            if !available {
+                status = 1;
+            }
+
+            if !quiet {
+                let _ = writeln!(ctx.stdout, "{}\t{}", name, if available { "on" } else { "off" });
+            }
+        }
+
+        status
+    }
+
+    fn complete(&self, _shell: &Shell, _args: &[String]) -> Vec<String> {
+        FEATURES.iter().map(|x| x.to_string()).collect()
+    }
+}
+
+/// `return [n]`, in a pipeline it only sets the status like in a subshell
+struct Return;
+
+impl Builtin for Return {
+    fn name(&self) -> &str {
+        "return"
+    }
+
+    fn help(&self) -> &str {
+        "return [n]\n\nReturns from the function with status n, or the status of the last command"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        if ctx.shell.stack().is_empty() {
+            ctx.error("return", "can only return from a function");
+            return 1;
+        }
+
+        let status = match args {
+            [] => ctx.shell.status,
+            [x] => match x.parse::<i64>() {
+                Ok(x) => x.rem_euclid(256) as i32,
+                Err(_) => {
+                    ctx.error("return", format!("{}: numeric argument required", x));
+                    STATUS_USAGE
+                },
+            },
+            _ => {
+                ctx.error("return", "too many arguments");
+                return 1;
+            },
+        };
+
+        if !ctx.in_pipeline {
+            ctx.shell.returning = true;
+        }
+
+        status
+    }
+}
+
+/// Options of `set` by name and letter
+pub(crate) const OPTIONS: &[(&str, char)] = &[("errexit", 'e'), ("noglob", 'f'), ("xtrace", 'x')];
+
+/// Sets the option, returns false if there is no such option
+pub(crate) fn set_option(shell: &mut Shell, name: &str, on: bool) -> bool {
+    match name {
+        "errexit" => shell.errexit = on,
+        "noglob" => shell.noglob = on,
+        "xtrace" => shell.xtrace = on,
+        _ => return false,
+    }
+
+    true
+}
+
+fn option(shell: &Shell, name: &str) -> bool {
+    match name {
+        "errexit" => shell.errexit,
+        "noglob" => shell.noglob,
+        "xtrace" => shell.xtrace,
+        _ => false,
+    }
+}
+
+/// `set [-efx|+efx] [-o name|+o name] [--] [arg...]`, without arguments prints the
+/// variables, the arguments replace the positional parameters
+struct Set;
+
+impl Builtin for Set {
+    fn name(&self) -> &str {
+        "set"
+    }
+
+    fn help(&self) -> &str {
+        "set [-efx|+efx] [-o option|+o option] [--] [arg...]\n\nChanges options of the shell, -o without a name lists them, arguments replace the positional parameters and without any the variables are printed"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        if args.is_empty() {
+            let mut vars: Vec<_> = ctx.shell.vars.iter().collect();
+            vars.sort_by_key(|(name, _)| name.as_str());
+
+            for (name, var) in vars {
+                let _ = writeln!(ctx.stdout, "{}={}", name, quote(&var.value));
+            }
+
+            return 0;
+        }
+
+        let mut iter = args.iter().peekable();
+        let mut positional = None;
+        while let Some(arg) = iter.next() {
+            if arg == "--" {
+                positional = Some(iter.by_ref().cloned().collect::<Vec<_>>());
+                break;
+            }
+
+            let on = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => {
+                    positional = Some(std::iter::once(arg).chain(iter.by_ref()).cloned().collect());
+                    break;
+                },
+            };
+
+            // -o name, or listing of the options with -o alone
+            if arg.len() == 2 && arg.ends_with('o') {
+                match iter.next() {
+                    Some(name) => {
+                        if !set_option(ctx.shell, name, on) {
+                            ctx.error("set", format!("{}: invalid option name", name));
+                            return STATUS_USAGE;
+                        }
+                    },
+                    None => for (name, _) in OPTIONS {
+                        let state = if option(ctx.shell, name) { "on" } else { "off" };
+                        let _ = writeln!(ctx.stdout, "{}\t{}", name, state);
+                    },
+                }
+
+                continue;
+            }
+
+            for letter in arg.chars().skip(1) {
+                let found = OPTIONS.iter().find(|(_, x)| *x == letter);
+                if !found.is_some_and(|(name, _)| set_option(ctx.shell, name, on)) {
+                    ctx.error("set", format!("{}{}: invalid option", if on { '-' } else { '+' }, letter));
+                    return STATUS_USAGE;
+                }
+            }
+        }
+
+        if let Some(positional) = positional {
+            let arg0 = ctx.shell.args[0].clone();
+            ctx.shell.args = std::iter::once(arg0).chain(positional).collect();
+        }
+
+        0
+    }
+
+    fn complete(&self, _shell: &Shell, args: &[String]) -> Vec<String> {
+        if args.last().is_some_and(|x| x == "-o" || x == "+o") {
+            return OPTIONS.iter().map(|(name, _)| name.to_string()).collect();
+        }
+
+        vec![]
+    }
+}
+
+/// `unalias [-a] name...`, `-a` removes all of them
+struct Unalias;
+
+impl Builtin for Unalias {
+    fn name(&self) -> &str {
+        "unalias"
+    }
+
+    fn help(&self) -> &str {
+        "unalias [-a] name...\n\nRemoves the aliases, or all of them with -a"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        if args == ["-a"] {
+            ctx.shell.aliases = Table::new();
+            return 0;
+        }
+
+        if args.is_empty() {
+            ctx.error("unalias", "usage: unalias [-a] name...");
+            return STATUS_USAGE;
+        }
+
+        let mut status = 0;
+        for name in args {
+            let found = Symbol::get(name).is_some_and(|x| ctx.shell.aliases.remove(&x));
+            if !found {
+                ctx.error("unalias", format!("{}: not found", name));
+                status = 1;
+            }
+        }
+
+        status
+    }
+
+    fn complete(&self, shell: &Shell, _args: &[String]) -> Vec<String> {
+        alias_names(shell)
+    }
+}
+
+/// `unset [-f] name...`, `-f` removes functions instead of variables
+struct Unset;
+
+impl Builtin for Unset {
+    fn name(&self) -> &str {
+        "unset"
+    }
+
+    fn help(&self) -> &str {
+        "unset [-f] name...\n\nRemoves the variables, or the functions with -f"
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        let (functions, names) = match args.first().map(|x| x.as_str()) {
+            Some("-f") => (true, &args[1..]),
+            Some("-v") => (false, &args[1..]),
+            _ => (false, args),
+        };
+
+        let mut status = 0;
+        for name in names {
+            if !is_name(name) {
+                ctx.error("unset", format!("{}: not a valid identifier", name));
+                status = 1;
+                continue;
+            }
+
+            // nothing can be named by a name that was never seen
+            let Some(name) = Symbol::get(name) else {
+                continue;
+            };
+
+            if functions {
+                ctx.shell.functions.remove(&name);
+            } else {
+                ctx.shell.vars.remove(&name);
+            }
+        }
+
+        status
+    }
+
+    fn complete(&self, shell: &Shell, args: &[String]) -> Vec<String> {
+        if args.first().is_some_and(|x| x == "-f") {
+            let mut names: Vec<String> = shell.functions.keys().map(|x| x.to_string()).collect();
+            names.sort();
+
+            return names;
+        }
+
+        var_names(shell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn run(source: &str) -> (i32, String, String) {
+        let result = Shell::new(false).run_str(source);
+        (result.status, result.stdout, result.stderr)
+    }
+
+    struct Hello;
+
+    impl Builtin for Hello {
+        fn name(&self) -> &str {
+            "hello"
+        }
+
+        fn invoke(&self, ctx: &mut Context, _args: &[String]) -> i32 {
+            let _ = writeln!(ctx.stdout, "hello");
+            0
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let names = |posix| {
+            let mut names: Vec<String> = Registry::with_defaults(posix).names().map(|x| x.to_string()).collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(false), ["alias", "caller", "exit", "export", "features", "return", "set", "unalias", "unset"]);
+        assert_eq!(names(true), ["alias", "exit", "export", "return", "set", "unalias", "unset"]);
+    }
+
+    #[test]
+    fn deferred() {
+        let loaded = Rc::new(Cell::new(0));
+        let counter = loaded.clone();
+
+        let mut registry = Registry::with_defaults(false);
+        registry.defer(move || {
+            counter.set(counter.get() + 1);
+            vec![Rc::new(Hello), Rc::new(Hello) as Rc<dyn Builtin>]
+        });
+
+        // the registered ones do not load the rest
+        assert_eq!(registry.get("set").map(|x| x.name().to_string()), Some("set".to_string()));
+        assert_eq!(loaded.get(), 0);
+
+        let clone = registry.clone();
+        assert!(registry.get("hello").is_some());
+        assert!(clone.get("hello").is_some());
+        assert!(registry.get("rush-no-such-builtin").is_none());
+        assert_eq!(loaded.get(), 1);
+
+        assert_eq!(registry.names().filter(|x| *x == "hello").count(), 1);
+    }
+
+    #[test]
+    fn set() {
+        let mut shell = Shell::new(false);
+        assert_eq!(shell.run_str("set -ef +f -o xtrace").status, 0);
+        assert!(shell.errexit && !shell.noglob && shell.xtrace);
+
+        let result = shell.run_str("set +x +o errexit\nset -o\n");
+        assert_eq!(result.stdout, "errexit\toff\nnoglob\toff\nxtrace\toff\n");
+
+        assert_eq!(run("set -z"), (STATUS_USAGE, String::new(), "rush: set: -z: invalid option\n".to_string()));
+        assert_eq!(run("set -o nope").0, STATUS_USAGE);
+        assert_eq!(run("set -- a 'b c'\necho $# \"$2\"\nset x\necho $# $1\n").1, "2 b c\n1 x\n");
+    }
+
+    #[test]
+    fn unset() {
+        let (_, stdout, _) = run("x=1\nf() { echo f; }\nunset x\nunset -f f\necho \"[$x]\"\nf 2>/dev/null\necho $?\n");
+        assert_eq!(stdout, "[]\n127\n");
+
+        let (status, _, stderr) = run("unset 1x");
+        assert_eq!(status, 1);
+        assert!(stderr.contains("1x: not a valid identifier"), "{}", stderr);
+    }
+
+    #[test]
+    fn export() {
+        let (_, stdout, _) = run("x=1\nexport x y=\"a'b\"\nexport -p\nsh -c 'echo $x'\n");
+        assert!(stdout.contains("export x='1'\n"), "{}", stdout);
+        assert!(stdout.contains("export y='a'\\''b'\n"), "{}", stdout);
+        assert!(stdout.ends_with("1\n"), "{}", stdout);
+
+        assert_eq!(run("export a-b").0, 1);
+    }
+
+    #[test]
+    fn aliases() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str("alias ll='ls -l' la=ls\nalias ll\nalias\n");
+        assert_eq!(result.stdout, "alias ll='ls -l'\nalias la=ls\nalias ll='ls -l'\n");
+
+        assert_eq!(shell.run_str("unalias la\nalias la").status, 1);
+        assert_eq!(shell.run_str("unalias -a\nalias").stdout, "");
+        assert_eq!(shell.run_str("unalias").status, STATUS_USAGE);
+        assert_eq!(shell.run_str("alias 'a b=c'").status, 1);
+    }
+
+    #[test]
+    fn returns() {
+        let (status, _, stderr) = run("return 1");
+        assert_eq!(status, 1);
+        assert!(stderr.contains("can only return from a function"), "{}", stderr);
+
+        assert_eq!(run("f() { return 3; echo no; }\nf\necho $?\n").1, "3\n");
+        assert_eq!(run("f() { false; return; }\nf\necho $?\n").1, "1\n");
+    }
+
+    #[test]
+    fn exit() {
+        assert_eq!(run("exit 300").0, 44);
+        assert_eq!(run("exit -1").0, 255);
+        assert_eq!(run("exit x").0, STATUS_USAGE);
+        assert_eq!(run("exit 1 2; echo $?").1, "1\n");
+
+        // in a pipeline it is like exiting a subshell
+        assert_eq!(run("exit 5 | true\necho $?\n").1, "0\n");
+    }
+
+    #[test]
+    fn features() {
+        let (status, stdout, _) = run("features -q rush-no-such-feature\necho $?\nfeatures\n");
+        assert_eq!(status, 0);
+        assert_eq!(stdout, format!("1\n{}\n", FEATURES.join("\n")));
+    }
+}
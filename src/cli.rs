@@ -0,0 +1,154 @@
+//! Command line argument parsing
+
+use std::path::Path;
+
+/// Tools that run instead of the shell, like `rush lint`
+///
+/// Only when their name is the first argument that is not an option and there
+/// is no file by that name, otherwise it is the script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Lint,
+    Fmt,
+}
+
+/// Options the shell was started with
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Strict POSIX mode, set by `--posix` or when invoked as `sh`
+    pub posix: bool,
+
+    /// Print version and features then exit
+    pub version: bool,
+
+    /// Print features one per line then exit
+    pub features: bool,
+
+    /// Print commands before running them, like `set -x`
+    pub xtrace: bool,
+
+    /// Descriptor JSON trace events are written to
+    pub trace_fd: Option<u32>,
+
+    /// Run the script in the debugger
+    pub debug: bool,
+
+    /// Print a profile of the script when it finishes
+    pub profile: bool,
+
+    /// Run the language server on stdin and stdout
+    pub lsp: bool,
+
+    /// Start with an empty environment, like `env -i`
+    pub clean_env: bool,
+
+    /// Variables inherited even with a clean environment
+    pub keep_env: Vec<String>,
+
+    /// Script given inline using `-c`
+    pub command: Option<String>,
+
+    /// Path to the script file
+    pub script: Option<String>,
+
+    /// Tool to run, its options are in `args`
+    pub tool: Option<Tool>,
+
+    /// Rest of the arguments, passed to the script
+    pub args: Vec<String>,
+}
+
+pub fn parse_args(mut argv: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args::default();
+
+    // act like sh when invoked as one
+    if let Some(arg0) = argv.next() {
+        if Path::new(&arg0).file_name().is_some_and(|x| x == "sh") {
+            args.posix = true;
+        }
+    }
+
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--posix" => args.posix = true,
+            "--version" => args.version = true,
+            "--features" => args.features = true,
+            "--clean-env" => args.clean_env = true,
+            "--lsp" => args.lsp = true,
+            "--debug" => args.debug = true,
+            "--profile" => args.profile = true,
+            "-x" => args.xtrace = true,
+
+            "--trace-fd" => match argv.next().map(|x| x.parse::<u32>()) {
+                Some(Ok(fd)) => args.trace_fd = Some(fd),
+                Some(Err(_)) => return Err("--trace-fd: invalid descriptor".to_string()),
+                None => return Err("--trace-fd: option requires an argument".to_string()),
+            },
+
+            "--keep-env" => match argv.next() {
+                Some(name) => args.keep_env.push(name),
+                None => return Err("--keep-env: option requires an argument".to_string()),
+            },
+
+            "-c" => match argv.next() {
+                Some(command) => {
+                    args.command = Some(command);
+                    break;
+                },
+                None => return Err("-c: option requires an argument".to_string()),
+            },
+
+            // end of options
+            "--" => {
+                args.script = argv.next();
+                break;
+            },
+
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("{}: invalid option", arg)),
+
+            // a script with the same name wins so `sh lint` still runs it
+            "lint" if !Path::new(&arg).is_file() => {
+                args.tool = Some(Tool::Lint);
+                break;
+            },
+
+            "fmt" if !Path::new(&arg).is_file() => {
+                args.tool = Some(Tool::Fmt);
+                break;
+            },
+
+            _ => {
+                args.script = Some(arg);
+                break;
+            },
+        }
+    }
+
+    args.args = argv.collect();
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Args {
+        parse_args(argv.iter().map(|x| x.to_string())).unwrap()
+    }
+
+    #[test]
+    fn tools() {
+        let args = parse(&["rush", "--posix", "lint", "--json", "a.sh"]);
+        assert_eq!((args.tool, args.posix), (Some(Tool::Lint), true));
+        assert_eq!(args.args, ["--json", "a.sh"]);
+
+        let args = parse(&["rush", "fmt", "--check"]);
+        assert_eq!((args.tool, args.script), (Some(Tool::Fmt), None));
+
+        // only as the first word, after it they are arguments of the script
+        let args = parse(&["rush", "a.sh", "lint"]);
+        assert_eq!((args.tool, args.script.as_deref()), (None, Some("a.sh")));
+        assert_eq!(args.args, ["lint"]);
+    }
+}
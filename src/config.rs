@@ -0,0 +1,628 @@
+//! Declarative configuration of interactive shells, read from `config.toml`
+//! in the configuration directory before the rc file so the rc file can still
+//! change anything set here
+//!
+//! ```toml
+//! [options]
+//! errexit = true
+//!
+//! [aliases]
+//! ll = "ls -l"
+//!
+//! [abbreviations]
+//! gco = "git checkout"
+//!
+//! [keybindings]
+//! ctrl-r = "history-search"
+//!
+//! [prompt]
+//! format = "{dir} {status}$ "
+//! color = "green"
+//! error_color = "red"
+//!
+//! [history]
+//! file = "~/.rush_history"
+//! size = 10000
+//! ignore_space = true
+//! ignore_duplicates = true
+//! ```
+//!
+//! Entries that do not fit the schema are reported with where they are and
+//! skipped, so a mistake in one of them does not lose the rest
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use strum::{EnumString, VariantNames};
+
+use crate::alias;
+use crate::builtins::{set_option, OPTIONS};
+use crate::diagnostic::Diagnostic;
+use crate::dirs;
+use crate::exec::Shell;
+use crate::parser::is_name;
+use crate::symbol::Symbol;
+use crate::toml::{self, Entry, Value};
+
+/// Tables of the file
+const TABLES: &[&str] = &["options", "aliases", "abbreviations", "keybindings", "prompt", "history"];
+
+const PROMPT_KEYS: &[&str] = &["format", "color", "error_color"];
+
+const HISTORY_KEYS: &[&str] = &["enabled", "file", "size", "ignore_space", "ignore_duplicates"];
+
+/// Placeholders of the prompt format
+const PLACEHOLDERS: &[&str] = &["cwd", "dir", "user", "status", "plugins"];
+
+/// Keys besides single characters that can be bound
+const KEYS: &[&str] = &[
+    "backspace", "delete", "down", "end", "enter", "escape", "home", "insert", "left", "pagedown", "pageup", "right", "space", "tab", "up",
+    "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, VariantNames)]
+#[strum(serialize_all = "lowercase")]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+impl Color {
+    /// Escape sequence that starts the color
+    fn escape(self) -> String {
+        format!("\x1b[{}m", 30 + self as u8)
+    }
+}
+
+/// What a key can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, VariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Action {
+    AcceptLine,
+    BackwardChar,
+    BackwardDeleteChar,
+    BackwardKillWord,
+    BackwardWord,
+    BeginningOfLine,
+    Cancel,
+    ClearScreen,
+    Complete,
+    DeleteChar,
+    EndOfLine,
+    ForwardChar,
+    ForwardWord,
+    HistorySearch,
+    KillLine,
+    KillWord,
+    NextHistory,
+    PreviousHistory,
+    Undo,
+    Yank,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keybinding {
+    /// Modifiers in the order `ctrl`, `alt`, `shift` then the key, joined
+    /// with `-` like `ctrl-alt-f`
+    pub key: String,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Cwd,
+    Dir,
+    User,
+    Status,
+    Plugins,
+}
+
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    format: Vec<Segment>,
+    pub color: Option<Color>,
+
+    /// Used instead of `color` after a command failed
+    pub error_color: Option<Color>,
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self {
+            format: vec![Segment::Plugins, Segment::Text("$ ".to_string())],
+            color: None,
+            error_color: None,
+        }
+    }
+}
+
+impl Prompt {
+    /// Prompt for the next line, `plugins` gives what the prompt segments
+    /// of the plugins print, only called if the format has them
+    pub fn render(&self, shell: &Shell, plugins: impl Fn() -> String) -> String {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut prompt = String::new();
+        for segment in &self.format {
+            match segment {
+                Segment::Text(x) => prompt.push_str(x),
+                Segment::Cwd => {
+                    // home is shortened to `~` like in other shells
+                    let home = shell.var("HOME").filter(|x| !x.is_empty()).map(PathBuf::from);
+                    match home.as_ref().and_then(|x| cwd.strip_prefix(x).ok()) {
+                        Some(rest) if rest.as_os_str().is_empty() => prompt.push('~'),
+                        Some(rest) => prompt.push_str(&format!("~/{}", rest.display())),
+                        None => prompt.push_str(&cwd.display().to_string()),
+                    }
+                },
+                Segment::Dir => match cwd.file_name() {
+                    Some(x) => prompt.push_str(&x.to_string_lossy()),
+                    None => prompt.push_str(&cwd.display().to_string()),
+                },
+                Segment::User => prompt.push_str(&shell.var("USER").unwrap_or_default()),
+                Segment::Status => prompt.push_str(&shell.status.to_string()),
+                Segment::Plugins => prompt.push_str(&plugins()),
+            }
+        }
+
+        let color = match shell.status {
+            0 => self.color,
+            _ => self.error_color.or(self.color),
+        };
+
+        match color {
+            Some(color) => format!("{}{}\x1b[0m", color.escape(), prompt),
+            None => prompt,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    pub enabled: bool,
+
+    /// Instead of the one in the state directory
+    pub file: Option<PathBuf>,
+
+    /// Most lines kept in the file, older ones are dropped at startup
+    pub size: Option<usize>,
+
+    /// Lines starting with a blank are not saved
+    pub ignore_space: bool,
+
+    /// Lines that are the same as the one before are not saved
+    pub ignore_duplicates: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: None,
+            size: None,
+            ignore_space: false,
+            ignore_duplicates: false,
+        }
+    }
+}
+
+impl History {
+    /// File the lines are saved to
+    pub fn path(&self) -> Option<PathBuf> {
+        self.file.clone().or_else(dirs::history_file)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Options of `set` by name
+    pub options: Vec<(&'static str, bool)>,
+
+    /// Defined like the `alias` builtin does
+    pub aliases: Vec<(String, String)>,
+
+    /// Replaced in the typed line like aliases but before it is saved to the
+    /// history, and the line is printed again so it is seen what runs
+    pub abbreviations: HashMap<String, String>,
+
+    /// Only checked, the prompt reads whole lines from the terminal so
+    /// nothing uses them yet
+    pub keybindings: Vec<Keybinding>,
+
+    pub prompt: Prompt,
+    pub history: History,
+}
+
+impl Config {
+    /// Reads the configuration, along with what did not fit the schema and
+    /// was skipped and warnings about what is not used, nothing is read if it
+    /// is not valid TOML
+    pub fn parse(source: &str) -> (Config, Vec<Diagnostic>) {
+        let mut config = Config::default();
+        let tables = match toml::parse(source) {
+            Ok(x) => x,
+            Err(err) => return (config, vec![err]),
+        };
+
+        let mut errors = vec![];
+        for table in &tables {
+            if !table.name.is_empty() && !TABLES.contains(&table.name.as_str()) {
+                errors.push(unknown("table", &table.name, TABLES, table.span));
+                continue;
+            }
+
+            // until the prompt has a line editor
+            if table.name == "keybindings" {
+                errors.push(Diagnostic::warning("keybindings are not used yet, lines are read whole from the terminal", table.span.0, table.span.1));
+            }
+
+            for entry in &table.entries {
+                let result = match table.name.as_str() {
+                    "options" => config.option(entry),
+                    "aliases" => alias_entry(entry).map(|x| config.aliases.push(x)),
+                    "abbreviations" => alias_entry(entry).map(|(name, value)| {
+                        config.abbreviations.insert(name, value);
+                    }),
+                    "keybindings" => config.keybinding(entry),
+                    "prompt" => config.prompt(entry),
+                    "history" => config.history(entry),
+                    _ => Err(Diagnostic::error(format!("`{}` has to be in a table like `[options]`", entry.key), entry.key_span.0, entry.key_span.1)),
+                };
+
+                errors.extend(result.err());
+            }
+        }
+
+        (config, errors)
+    }
+
+    /// Sets the options and defines the aliases in the shell
+    pub fn apply(&self, shell: &mut Shell) {
+        for (name, on) in &self.options {
+            set_option(shell, name, *on);
+        }
+
+        for (name, value) in &self.aliases {
+            shell.aliases.insert(Symbol::intern(name), value.clone());
+        }
+    }
+
+    /// Line with the abbreviations at the start of its commands replaced
+    pub fn abbreviate(&self, line: &str, posix: bool) -> String {
+        if self.abbreviations.is_empty() {
+            return line.to_string();
+        }
+
+        alias::replace_commands(line, posix, &mut |name| self.abbreviations.get(name).cloned())
+    }
+
+    fn option(&mut self, entry: &Entry) -> Result<(), Diagnostic> {
+        let names: Vec<&str> = OPTIONS.iter().map(|(name, _)| *name).collect();
+        let Some(name) = names.iter().find(|x| **x == entry.key) else {
+            return Err(unknown("option", &entry.key, &names, entry.key_span));
+        };
+
+        self.options.push((name, boolean(entry)?));
+        Ok(())
+    }
+
+    fn keybinding(&mut self, entry: &Entry) -> Result<(), Diagnostic> {
+        let key = parse_key(&entry.key).map_err(|x| Diagnostic::error(x, entry.key_span.0, entry.key_span.1))?;
+        if self.keybindings.iter().any(|x| x.key == key) {
+            return Err(Diagnostic::error(format!("`{}` is bound twice", key), entry.key_span.0, entry.key_span.1));
+        }
+
+        let name = string(entry)?;
+        let action = Action::from_str(name).map_err(|_| unknown("action", name, Action::VARIANTS, entry.value_span))?;
+
+        self.keybindings.push(Keybinding { key, action });
+        Ok(())
+    }
+
+    fn prompt(&mut self, entry: &Entry) -> Result<(), Diagnostic> {
+        match entry.key.as_str() {
+            "format" => {
+                let format = parse_format(string(entry)?).map_err(|x| Diagnostic::error(x, entry.value_span.0, entry.value_span.1))?;
+                self.prompt.format = format;
+            },
+            "color" => self.prompt.color = Some(color(entry)?),
+            "error_color" => self.prompt.error_color = Some(color(entry)?),
+            name => return Err(unknown("key", name, PROMPT_KEYS, entry.key_span)),
+        }
+
+        Ok(())
+    }
+
+    fn history(&mut self, entry: &Entry) -> Result<(), Diagnostic> {
+        match entry.key.as_str() {
+            "enabled" => self.history.enabled = boolean(entry)?,
+            "file" => {
+                let path = string(entry)?;
+                let home = dirs::home_dir().filter(|_| path.starts_with("~/"));
+                let file = match home {
+                    Some(home) => home.join(&path[2..]),
+                    None => PathBuf::from(path),
+                };
+
+                if !file.is_absolute() {
+                    return Err(Diagnostic::error("history file has to be an absolute path or start with `~/`", entry.value_span.0, entry.value_span.1));
+                }
+
+                self.history.file = Some(file);
+            },
+            "size" => match integer(entry)? {
+                x if x > 0 => self.history.size = Some(x as usize),
+                _ => return Err(Diagnostic::error("history size has to be more than zero, use `enabled = false` to turn it off", entry.value_span.0, entry.value_span.1)),
+            },
+            "ignore_space" => self.history.ignore_space = boolean(entry)?,
+            "ignore_duplicates" => self.history.ignore_duplicates = boolean(entry)?,
+            name => return Err(unknown("key", name, HISTORY_KEYS, entry.key_span)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Name and value of an alias or abbreviation
+fn alias_entry(entry: &Entry) -> Result<(String, String), Diagnostic> {
+    // anything else would not be a single word for the lexer
+    if !is_name(&entry.key) {
+        return Err(Diagnostic::error(format!("`{}` is not a valid name, they are letters, digits and underscores", entry.key), entry.key_span.0, entry.key_span.1));
+    }
+
+    Ok((entry.key.clone(), string(entry)?.to_string()))
+}
+
+fn mismatch(entry: &Entry, expected: &str) -> Diagnostic {
+    Diagnostic::error(format!("`{}` has to be {}, not {}", entry.key, expected, entry.value.kind()), entry.value_span.0, entry.value_span.1)
+}
+
+fn boolean(entry: &Entry) -> Result<bool, Diagnostic> {
+    match entry.value {
+        Value::Boolean(x) => Ok(x),
+        _ => Err(mismatch(entry, "a boolean")),
+    }
+}
+
+fn integer(entry: &Entry) -> Result<i64, Diagnostic> {
+    match entry.value {
+        Value::Integer(x) => Ok(x),
+        _ => Err(mismatch(entry, "an integer")),
+    }
+}
+
+fn string(entry: &Entry) -> Result<&str, Diagnostic> {
+    match &entry.value {
+        Value::String(x) => Ok(x),
+        _ => Err(mismatch(entry, "a string")),
+    }
+}
+
+fn color(entry: &Entry) -> Result<Color, Diagnostic> {
+    let name = string(entry)?;
+    Color::from_str(name).map_err(|_| unknown("color", name, Color::VARIANTS, entry.value_span))
+}
+
+/// Edit distance, for suggestions of misspelled names
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, y) in b.iter().enumerate() {
+            let substituted = diagonal + (x != *y) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Name in `expected` that `name` looks like a typo of
+fn closest<'a>(name: &str, expected: &[&'a str]) -> Option<&'a str> {
+    expected.iter()
+        .map(|x| (distance(name, x), *x))
+        .min()
+        .filter(|(distance, _)| *distance <= (name.chars().count() / 3).max(1))
+        .map(|(_, x)| x)
+}
+
+/// Message for a name that is not one of `expected`, suggesting the closest
+/// one if there is one
+fn unknown_message(what: &str, name: &str, expected: &[&str]) -> String {
+    match closest(name, expected) {
+        Some(x) => format!("unknown {} `{}`, did you mean `{}`?", what, name, x),
+        None => format!("unknown {} `{}`, expected one of {}", what, name, expected.join(", ")),
+    }
+}
+
+fn unknown(what: &str, name: &str, expected: &[&str], (start, end): (usize, usize)) -> Diagnostic {
+    Diagnostic::error(unknown_message(what, name, expected), start, end)
+}
+
+/// Key like `ctrl-r`, with the modifiers put in order
+fn parse_key(key: &str) -> Result<String, String> {
+    let key = key.to_lowercase();
+    let mut rest = key.as_str();
+    let mut modifiers = vec![];
+
+    // the key itself can be a `-`
+    while let Some((modifier, after)) = rest.split_once('-').filter(|(_, after)| !after.is_empty()) {
+        if !matches!(modifier, "ctrl" | "alt" | "shift") {
+            return Err(format!("unknown modifier `{}` in `{}`, expected ctrl, alt or shift", modifier, key));
+        }
+
+        if modifiers.contains(&modifier) {
+            return Err(format!("`{}` is given twice in `{}`", modifier, key));
+        }
+
+        modifiers.push(modifier);
+        rest = after;
+    }
+
+    if rest.chars().count() != 1 && !KEYS.contains(&rest) {
+        return Err(match closest(rest, KEYS) {
+            Some(x) => format!("unknown key `{}` in `{}`, did you mean `{}`?", rest, key, x),
+            None => format!("unknown key `{}` in `{}`, expected a single character or one of {}", rest, key, KEYS.join(", ")),
+        });
+    }
+
+    let mut ordered: Vec<&str> = ["ctrl", "alt", "shift"].into_iter().filter(|x| modifiers.contains(x)).collect();
+    ordered.push(rest);
+
+    Ok(ordered.join("-"))
+}
+
+/// Splits the prompt format into its text and placeholders, `{{` and `}}`
+/// are literal braces
+fn parse_format(format: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            },
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(x) => name.push(x),
+                        None => return Err("unterminated placeholder, literal braces are written as `{{`".to_string()),
+                    }
+                }
+
+                let segment = match name.as_str() {
+                    "cwd" => Segment::Cwd,
+                    "dir" => Segment::Dir,
+                    "user" => Segment::User,
+                    "status" => Segment::Status,
+                    "plugins" => Segment::Plugins,
+                    _ => return Err(unknown_message("placeholder", &name, PLACEHOLDERS)),
+                };
+
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+
+                segments.push(segment);
+            },
+            '}' => return Err("unmatched `}`, literal braces are written as `}}`".to_string()),
+            _ => text.push(ch),
+        }
+    }
+
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Severity;
+
+    fn errors(source: &str) -> Vec<String> {
+        Config::parse(source).1.into_iter().filter(|x| x.severity == Severity::Error).map(|x| x.message).collect()
+    }
+
+    #[test]
+    fn valid() {
+        let (config, errors) = Config::parse(concat!(
+            "[options]\nerrexit = true\n",
+            "[aliases]\nll = \"ls -l\"\n",
+            "[abbreviations]\ngco = \"git checkout\"\n",
+            "[keybindings]\nAlt-Ctrl-R = \"history-search\"\n",
+            "[prompt]\nformat = \"{dir} {{x}} {status}$ \"\ncolor = \"green\"\n",
+            "[history]\nsize = 10\nignore_space = true\n",
+        ));
+
+        assert!(errors.iter().all(|x| x.severity == Severity::Warning), "{:?}", errors);
+        assert_eq!(config.options, [("errexit", true)]);
+        assert_eq!(config.aliases, [("ll".to_string(), "ls -l".to_string())]);
+        assert_eq!(config.abbreviate("gco main; gco", false), "git checkout main; git checkout");
+        assert_eq!(config.keybindings, [Keybinding { key: "ctrl-alt-r".to_string(), action: Action::HistorySearch }]);
+        assert_eq!(config.prompt.format, [
+            Segment::Dir,
+            Segment::Text(" {x} ".to_string()),
+            Segment::Status,
+            Segment::Text("$ ".to_string()),
+        ]);
+        assert_eq!(config.prompt.color, Some(Color::Green));
+        assert_eq!(config.history.size, Some(10));
+        assert!(config.history.ignore_space);
+    }
+
+    #[test]
+    fn invalid_entries_are_skipped() {
+        let source = "[options]\nerrexti = true\nnoglob = true\nxtrace = 1\n";
+        assert_eq!(Config::parse(source).0.options, [("noglob", true)]);
+        assert_eq!(errors(source), [
+            "unknown option `errexti`, did you mean `errexit`?",
+            "`xtrace` has to be a boolean, not an integer",
+        ]);
+    }
+
+    #[test]
+    fn schema_errors() {
+        assert_eq!(errors("a = 1"), ["`a` has to be in a table like `[options]`"]);
+        assert_eq!(errors("[prompts]"), ["unknown table `prompts`, did you mean `prompt`?"]);
+        assert_eq!(errors("[aliases]\n\"a b\" = \"x\""), ["`a b` is not a valid name, they are letters, digits and underscores"]);
+        assert_eq!(errors("[prompt]\ncolor = \"gren\""), ["unknown color `gren`, did you mean `green`?"]);
+        assert_eq!(errors("[prompt]\nformat = \"{cwdd}\""), ["unknown placeholder `cwdd`, did you mean `cwd`?"]);
+        assert_eq!(errors("[prompt]\nformat = \"}\""), ["unmatched `}`, literal braces are written as `}}`"]);
+        assert_eq!(errors("[history]\nsize = 0"), ["history size has to be more than zero, use `enabled = false` to turn it off"]);
+        assert_eq!(errors("[history]\nfile = \"relative\""), ["history file has to be an absolute path or start with `~/`"]);
+        assert_eq!(errors("[keybindings]\nhyper-a = \"undo\""), ["unknown modifier `hyper` in `hyper-a`, expected ctrl, alt or shift"]);
+        assert_eq!(errors("[keybindings]\nctrl-tabb = \"undo\""), ["unknown key `tabb` in `ctrl-tabb`, did you mean `tab`?"]);
+        assert_eq!(errors("[keybindings]\nctrl-a = \"undo\"\nCtrl-A = \"yank\""), ["`ctrl-a` is bound twice"]);
+
+        // not valid TOML so nothing is read
+        assert_eq!(errors("[options]\nerrexit = yes"), ["`yes` is not a value, strings have to be quoted"]);
+    }
+
+    #[test]
+    fn keybindings_are_not_used() {
+        let (_, diagnostics) = Config::parse("[keybindings]\nctrl-r = \"history-search\"\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!((diagnostics[0].start, diagnostics[0].end), (0, 13));
+
+        assert!(Config::parse("[options]\n").1.is_empty());
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_key("ctrl--"), Ok("ctrl--".to_string()));
+        assert_eq!(parse_key("Shift-Alt-F1"), Ok("alt-shift-f1".to_string()));
+        assert!(parse_key("ctrl-ctrl-a").is_err());
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(closest("histroy", TABLES), Some("history"));
+        assert_eq!(closest("xyz", TABLES), None);
+    }
+}
@@ -0,0 +1,373 @@
+//! Interactive debugger for scripts, started with `rush --debug script`
+//!
+//! It stops before the first command and then at breakpoints, commands are
+//! read from the terminal so the script keeps its stdin
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::exec::{Hook, Shell};
+use crate::parser::{is_name, SimpleCommand};
+
+const HELP: &str = "\
+break [file:]line | break function   add a breakpoint
+delete n                            remove breakpoint n
+breakpoints                         list the breakpoints
+continue, c                         run until the next breakpoint
+step, s                             run the next command, entering functions
+next, n                             run the next command, stepping over functions
+print name, p name                  print a variable
+set name=value                      change a variable
+eval command                        run a command in the shell
+backtrace, bt                       print the call stack
+list, l                             show the source around the command
+quit, q                             stop the script
+help, h                             show this help";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Breakpoint {
+    /// Line in a file, any file if it has none
+    Line(Option<String>, usize),
+    Function(String),
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line(Some(file), line) => write!(f, "{}:{}", file, line),
+            Breakpoint::Line(None, line) => write!(f, "line {}", line),
+            Breakpoint::Function(name) => write!(f, "function {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    Step,
+
+    /// Stop at the next command at this depth of the stack or above
+    Next(usize),
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+
+    /// Entered a function with a breakpoint, stops before its first command
+    entered: bool,
+
+    /// Location of the last stop, a line breakpoint stops only once for a
+    /// line with multiple commands
+    last: Option<(String, usize)>,
+
+    input: Box<dyn BufRead>,
+
+    /// Where the locations and answers are printed, stderr unless changed
+    output: Box<dyn Write>,
+}
+
+/// Name matches the file of the breakpoint, either fully or by its file name
+fn same_file(name: &str, file: &str) -> bool {
+    name == file || Path::new(name).file_name().is_some_and(|x| x == file)
+}
+
+impl Debugger {
+    /// Reads commands from the terminal, or stdin if there is none
+    pub fn new() -> Self {
+        let input: Box<dyn BufRead> = match File::open(if cfg!(windows) { "CONIN$" } else { "/dev/tty" }) {
+            Ok(x) => Box::new(BufReader::new(x)),
+            Err(_) => Box::new(BufReader::new(io::stdin())),
+        };
+
+        Self::with_input(input)
+    }
+
+    pub fn with_input(input: Box<dyn BufRead>) -> Self {
+        Self {
+            breakpoints: vec![],
+            mode: Mode::Step,
+            entered: false,
+            last: None,
+            input,
+            output: Box::new(io::stderr()),
+        }
+    }
+
+    /// Prints to `output` instead of stderr
+    pub fn with_output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = output;
+        self
+    }
+
+    fn breakpoint_hit(&self, file: &str, line: usize) -> bool {
+        self.breakpoints.iter().any(|x| match x {
+            Breakpoint::Line(Some(x), l) => *l == line && same_file(file, x),
+            Breakpoint::Line(None, l) => *l == line,
+            Breakpoint::Function(_) => false,
+        })
+    }
+
+    fn add_breakpoint(&mut self, arg: &str) {
+        let breakpoint = match arg.rsplit_once(':') {
+            Some((file, line)) => match line.parse::<usize>() {
+                Ok(line) => Breakpoint::Line(Some(file.to_string()), line),
+                Err(_) => {
+                    let _ = writeln!(self.output, "invalid line '{}'", line);
+                    return;
+                },
+            },
+            None => match arg.parse::<usize>() {
+                Ok(line) => Breakpoint::Line(None, line),
+                Err(_) if is_name(arg) => Breakpoint::Function(arg.to_string()),
+                Err(_) => {
+                    let _ = writeln!(self.output, "usage: break [file:]line | break function");
+                    return;
+                },
+            },
+        };
+
+        let _ = writeln!(self.output, "breakpoint {} at {}", self.breakpoints.len() + 1, breakpoint);
+        self.breakpoints.push(breakpoint);
+    }
+
+    fn backtrace(&mut self, shell: &Shell) {
+        for (i, frame) in shell.backtrace().iter().enumerate() {
+            let _ = writeln!(self.output, "#{} {} at {}:{}", i, frame.function, frame.source.name, frame.line);
+        }
+    }
+
+    fn list(&mut self, shell: &Shell, line: usize) {
+        let text = &shell.source().text;
+        let first = line.saturating_sub(5).max(1);
+        for (i, x) in text.lines().enumerate().skip(first - 1).take(11) {
+            let marker = if i + 1 == line { '>' } else { ' ' };
+            let _ = writeln!(self.output, "{}{:>4} {}", marker, i + 1, x);
+        }
+    }
+
+    /// Reads and runs debugger commands until the script should continue
+    fn prompt(&mut self, shell: &mut Shell, command: &SimpleCommand) {
+        let source = shell.source().clone();
+        let line = source.line(command.start);
+        let text = source.text.get(command.start..command.end).unwrap_or_default();
+        let _ = writeln!(self.output, "{}:{}: {}", source.name, line, text);
+
+        let mut input = String::new();
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+
+            input.clear();
+            match self.input.read_line(&mut input) {
+                // nobody to ask, let it run
+                Ok(0) | Err(_) => {
+                    self.mode = Mode::Continue;
+                    self.breakpoints.clear();
+                    return;
+                },
+                Ok(_) => {},
+            }
+
+            let input = input.trim();
+            let (name, arg) = input.split_once(' ').map(|(x, y)| (x, y.trim())).unwrap_or((input, ""));
+            match name {
+                "" => {},
+                "break" | "b" => self.add_breakpoint(arg),
+                "delete" | "d" => match arg.parse::<usize>() {
+                    Ok(n) if n > 0 && n <= self.breakpoints.len() => {
+                        self.breakpoints.remove(n - 1);
+                    },
+                    _ => {
+                        let _ = writeln!(self.output, "no breakpoint '{}'", arg);
+                    },
+                },
+                "breakpoints" => for (i, x) in self.breakpoints.iter().enumerate() {
+                    let _ = writeln!(self.output, "{} {}", i + 1, x);
+                },
+                "continue" | "c" => {
+                    self.mode = Mode::Continue;
+                    return;
+                },
+                "step" | "s" => {
+                    self.mode = Mode::Step;
+                    return;
+                },
+                "next" | "n" => {
+                    self.mode = Mode::Next(shell.stack().len());
+                    return;
+                },
+                "print" | "p" => {
+                    let name = arg.trim_start_matches('$');
+                    let _ = match shell.var(name) {
+                        Some(value) => writeln!(self.output, "{}={}", name, value),
+                        None => writeln!(self.output, "{} is not set", name),
+                    };
+                },
+                "set" => match arg.split_once('=') {
+                    Some((name, value)) if is_name(name) => shell.set_var(name, value),
+                    _ => {
+                        let _ = writeln!(self.output, "usage: set name=value");
+                    },
+                },
+                "eval" | "e" => {
+                    shell.eval(arg);
+                },
+                "backtrace" | "bt" => self.backtrace(shell),
+                "list" | "l" => self.list(shell, line),
+                "quit" | "q" => {
+                    shell.exit = Some(1);
+                    self.mode = Mode::Continue;
+                    self.breakpoints.clear();
+                    return;
+                },
+                "help" | "h" => {
+                    let _ = writeln!(self.output, "{}", HELP);
+                },
+                _ => {
+                    let _ = writeln!(self.output, "unknown command '{}', try help", name);
+                },
+            }
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hook for Debugger {
+    fn before_command(&mut self, shell: &mut Shell, command: &SimpleCommand) {
+        let source = shell.source().clone();
+        let line = source.line(command.start);
+        let location = (source.name.clone(), line);
+
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next(depth) => shell.stack().len() <= depth,
+            Mode::Continue => false,
+        };
+
+        let hit = self.entered
+            || (self.last.as_ref() != Some(&location) && self.breakpoint_hit(&source.name, line));
+
+        self.entered = false;
+        self.last = Some(location);
+
+        if stop || hit {
+            self.prompt(shell, command);
+        }
+    }
+
+    fn enter_function(&mut self, _shell: &mut Shell, name: &str) {
+        if self.breakpoints.contains(&Breakpoint::Function(name.to_string())) {
+            self.entered = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Output shared with the test after the debugger is given to the shell
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs the script with the debugger reading `commands`, returns what
+    /// the debugger and the script printed
+    fn debug(script: &str, commands: &str) -> (String, String) {
+        let output = Output::default();
+        let debugger = Debugger::with_input(Box::new(io::Cursor::new(commands.to_string()))).with_output(Box::new(output.clone()));
+
+        let mut shell = Shell::new(false);
+        shell.hook = Some(Box::new(debugger));
+        let result = shell.run_str(script);
+
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        (printed.replace("(debug) ", ""), result.stdout)
+    }
+
+    const SCRIPT: &str = "f() {\n    x=2\n    echo in f\n}\nx=1\nf\necho $x\n";
+
+    #[test]
+    fn function_breakpoint() {
+        let (printed, stdout) = debug(SCRIPT, "break f\ncontinue\nbt\nprint x\ncontinue\n");
+        assert_eq!(printed, concat!(
+            "rush:5: x=1\n",
+            "breakpoint 1 at function f\n",
+            "rush:2: x=2\n",
+            "#0 f at rush:2\n",
+            "#1 main at rush:6\n",
+            "x=1\n",
+        ));
+        assert_eq!(stdout, "in f\n2\n");
+    }
+
+    #[test]
+    fn line_breakpoint() {
+        let (printed, _) = debug(SCRIPT, "break rush:7\nbreak nope:3\nc\np $x\nc\n");
+        assert_eq!(printed, concat!(
+            "rush:5: x=1\n",
+            "breakpoint 1 at rush:7\n",
+            "breakpoint 2 at nope:3\n",
+            "rush:7: echo $x\n",
+            "x=2\n",
+        ));
+    }
+
+    #[test]
+    fn step_and_next() {
+        let (printed, _) = debug(SCRIPT, "step\nstep\nstep\nstep\nc\n");
+        assert_eq!(printed, "rush:5: x=1\nrush:6: f\nrush:2: x=2\nrush:3: echo in f\nrush:7: echo $x\n");
+
+        let (printed, _) = debug(SCRIPT, "next\nnext\nnext\n");
+        assert_eq!(printed, "rush:5: x=1\nrush:6: f\nrush:7: echo $x\n");
+    }
+
+    #[test]
+    fn changing_the_script() {
+        let (printed, stdout) = debug("echo $x\n", "set x=changed\neval echo from eval\nprint y\nc\n");
+        assert_eq!(printed, "rush:1: echo $x\ny is not set\n");
+        assert_eq!(stdout, "from eval\nchanged\n");
+
+        let (_, stdout) = debug("echo a\necho b\n", "n\nquit\n");
+        assert_eq!(stdout, "a\n");
+    }
+
+    #[test]
+    fn runs_to_the_end_without_input() {
+        let (printed, stdout) = debug(SCRIPT, "");
+        assert_eq!(printed, "rush:5: x=1\n");
+        assert_eq!(stdout, "in f\n2\n");
+    }
+
+    #[test]
+    fn mistakes() {
+        let (printed, _) = debug("true\n", "break a:b\nbreak -\ndelete 1\nfly\nc\n");
+        assert_eq!(printed, concat!(
+            "rush:1: true\n",
+            "invalid line 'b'\n",
+            "usage: break [file:]line | break function\n",
+            "no breakpoint '1'\n",
+            "unknown command 'fly', try help\n",
+        ));
+    }
+}
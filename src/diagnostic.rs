@@ -0,0 +1,105 @@
+//! Errors printed like rustc does, with the location, the line of source and
+//! the span underlined
+//!
+//! ```text
+//! error: unexpected token
+//!  --> script.sh:3:10
+//!   |
+//! 3 | echo foo )
+//!   |          ^
+//! ```
+
+use crate::tokenizer::line_col;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Message about a span of the source
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+
+    /// Byte index of start of the span
+    pub start: usize,
+
+    /// Byte index of end of the span
+    pub end: usize,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, start: usize, end: usize) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            start,
+            end,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, start: usize, end: usize) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, start, end)
+        }
+    }
+
+    /// Renders the diagnostic with the line of `source` it points to, spans
+    /// over multiple lines are underlined until the end of the first one
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let start = floor_char_boundary(source, self.start);
+        let end = floor_char_boundary(source, self.end.max(start));
+        let (line, col) = line_col(source, start);
+
+        let line_start = source[..start].rfind('\n').map_or(0, |x| x + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |x| start + x);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        // tabs are kept so the caret lines up however wide they are shown
+        let indent: String = source[line_start..start].chars()
+            .map(|x| if x == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source[start..end.min(line_end)].chars().count().max(1);
+
+        let number = line.to_string();
+        let pad = " ".repeat(number.len());
+
+        format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.severity.as_str(),
+            self.message,
+            pad,
+            file,
+            line,
+            col,
+            pad,
+            number,
+            text,
+            pad,
+            indent,
+            "^".repeat(width),
+        )
+    }
+}
+
+/// Largest index not above `index` that is on a character boundary
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+
+    index
+}
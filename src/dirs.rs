@@ -0,0 +1,63 @@
+//! Locations of the configuration and state files, following the XDG base
+//! directory specification
+//!
+//! Each directory can be overridden with its own `RUSH_*` variable
+
+use std::path::PathBuf;
+
+/// Reads an absolute path from the environment, the spec says relative paths
+/// should be ignored
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|x| x.is_absolute())
+}
+
+/// Resolves directory using the override, the XDG variable or the default
+/// relative to home in that order
+fn xdg_dir(override_var: &str, xdg_var: &str, default: &str) -> Option<PathBuf> {
+    if let Some(path) = env_path(override_var) {
+        return Some(path);
+    }
+
+    if let Some(path) = env_path(xdg_var) {
+        return Some(path.join("rush"));
+    }
+
+    home_dir().map(|x| x.join(default).join("rush"))
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    // HOME is usually not set on windows
+    env_path("HOME").or_else(|| if cfg!(windows) { env_path("USERPROFILE") } else { None })
+}
+
+/// `$XDG_CONFIG_HOME/rush`, overridden by `$RUSH_CONFIG_DIR`
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("RUSH_CONFIG_DIR", "XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_STATE_HOME/rush`, overridden by `$RUSH_STATE_DIR`
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("RUSH_STATE_DIR", "XDG_STATE_HOME", ".local/state")
+}
+
+/// `$XDG_CACHE_HOME/rush`, overridden by `$RUSH_CACHE_DIR`
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("RUSH_CACHE_DIR", "XDG_CACHE_HOME", ".cache")
+}
+
+/// Script sourced by interactive shells
+pub fn rc_file() -> Option<PathBuf> {
+    config_dir().map(|x| x.join("rushrc"))
+}
+
+/// Declarative configuration read by interactive shells before the rc file
+pub fn config_file() -> Option<PathBuf> {
+    config_dir().map(|x| x.join("config.toml"))
+}
+
+/// File the interactive commands are appended to
+pub fn history_file() -> Option<PathBuf> {
+    state_dir().map(|x| x.join("history"))
+}
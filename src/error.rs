@@ -0,0 +1,146 @@
+//! Errors of every layer of the shell, from tokenizing to running builtins
+
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::ParseError;
+use crate::tokenizer::{line_col, TokenizeError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Tokenize(TokenizeError),
+    Parse(ParseError),
+
+    /// Word could not be expanded into what was needed
+    Expansion {
+        message: String,
+        start: usize,
+        end: usize,
+    },
+
+    /// Command could not be run, with the span of the code if any
+    Exec {
+        message: String,
+        span: Option<(usize, usize)>,
+    },
+
+    Builtin {
+        name: String,
+        message: String,
+    },
+
+    /// Error with what was being done when it happened, shown as
+    /// `context: error`
+    Context {
+        context: String,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    pub fn exec(message: impl Into<String>) -> Self {
+        Error::Exec {
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    pub fn builtin(name: &str, message: impl Into<String>) -> Self {
+        Error::Builtin {
+            name: name.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// Wraps the error with what was being done
+    pub fn context(self, context: impl Into<String>) -> Self {
+        Error::Context {
+            context: context.into(),
+            error: Box::new(self),
+        }
+    }
+
+    /// Sets the span of the code if the error has none
+    pub fn at(self, start: usize, end: usize) -> Self {
+        match self {
+            Error::Exec { message, span: None } => Error::Exec {
+                message,
+                span: Some((start, end)),
+            },
+            Error::Context { context, error } => Error::Context {
+                context,
+                error: Box::new(error.at(start, end)),
+            },
+            x => x,
+        }
+    }
+
+    /// Byte indices of the code that caused the error
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::Tokenize(x) => Some((x.start, x.end)),
+            Error::Parse(x) => Some((x.start, x.end)),
+            Error::Expansion { start, end, .. } => Some((*start, *end)),
+            Error::Exec { span, .. } => *span,
+            Error::Builtin { .. } => None,
+            Error::Context { error, .. } => error.span(),
+        }
+    }
+
+    /// Renders the error with the code it points to in `source` if it has a
+    /// span, otherwise as a single line
+    pub fn render(&self, file: &str, source: &str) -> String {
+        match self.span() {
+            Some((start, end)) => Diagnostic::error(self.to_string(), start, end).render(file, source),
+            None => format!("rush: {}\n", self),
+        }
+    }
+
+    /// Renders the error on a single line as `name: line N: message` like
+    /// `sh` does, errors without a span are shown on the line of `position`
+    pub fn render_line(&self, file: &str, source: &str, position: usize) -> String {
+        let (line, _) = line_col(source, self.span().map_or(position, |x| x.0));
+        format!("{}: line {}: {}\n", file, line, self)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Tokenize(x) => write!(f, "{}", x.message),
+            Error::Parse(x) => write!(f, "{}", x.message),
+            Error::Expansion { message, .. } | Error::Exec { message, .. } => write!(f, "{}", message),
+            Error::Builtin { name, message } => write!(f, "{}: {}", name, message),
+            Error::Context { context, error } => write!(f, "{}: {}", context, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Context { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<TokenizeError> for Error {
+    fn from(err: TokenizeError) -> Self {
+        Error::Tokenize(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::exec(err.to_string())
+    }
+}
@@ -0,0 +1,1658 @@
+//! Execution of parsed commands
+
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::process::{ExitStatus, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::alias;
+use crate::builtins::{Builtin, Context, Registry};
+use crate::error::Error;
+use crate::glob;
+use crate::ir;
+use crate::json::Json;
+use crate::parser::{double_quoted, is_name, parse, AndOr, Ast, Command as ShellCommand, Connector, Function, Pipeline, RedirectKind, Script, Redirect, SimpleCommand, WordPart};
+use crate::symbol::Symbol;
+use crate::table::Table;
+
+// there are no processes to start on wasm
+#[cfg(not(target_family = "wasm"))]
+use std::path::{Path, PathBuf};
+#[cfg(not(target_family = "wasm"))]
+use std::process::{Child, Command};
+use crate::tokenizer::{Lexer, Token};
+
+/// Status when the command was found but could not be executed
+pub const STATUS_NOT_EXECUTABLE: i32 = 126;
+
+/// Status when the command could not be found
+pub const STATUS_NOT_FOUND: i32 = 127;
+
+/// Status for syntax and usage errors
+pub const STATUS_USAGE: i32 = 2;
+
+/// Where a file descriptor of a command points to
+enum Io {
+    /// Same descriptor as the shell has
+    Inherit(u32),
+    File(File),
+    Reader(PipeReader),
+    Writer(PipeWriter),
+}
+
+impl Io {
+    /// Copy to use for the same descriptor, inherited ones stay inherited
+    fn share(&self) -> io::Result<Io> {
+        match self {
+            Io::Inherit(fd) => Ok(Io::Inherit(*fd)),
+            _ => self.try_clone(),
+        }
+    }
+
+    /// Copy that may be used for a different descriptor
+    fn try_clone(&self) -> io::Result<Io> {
+        match self {
+            Io::Inherit(fd) => dup_inherited(*fd).map(Io::File),
+            Io::File(x) => x.try_clone().map(Io::File),
+            Io::Reader(x) => x.try_clone().map(Io::Reader),
+            Io::Writer(x) => x.try_clone().map(Io::Writer),
+        }
+    }
+}
+
+/// Used by builtins for output
+impl Write for Io {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Io::Inherit(2) => io::stderr().write(buf),
+            Io::Inherit(_) => io::stdout().write(buf),
+            Io::File(x) => x.write(buf),
+            Io::Writer(x) => x.write(buf),
+            Io::Reader(_) => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Io::Inherit(2) => io::stderr().flush(),
+            Io::Inherit(_) => io::stdout().flush(),
+            Io::File(x) => x.flush(),
+            Io::Writer(x) => x.flush(),
+            Io::Reader(_) => Ok(()),
+        }
+    }
+}
+
+impl From<Io> for Stdio {
+    fn from(io: Io) -> Stdio {
+        match io {
+            Io::Inherit(_) => Stdio::inherit(),
+            Io::File(x) => x.into(),
+            Io::Reader(x) => x.into(),
+            Io::Writer(x) => x.into(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn dup_inherited(fd: u32) -> io::Result<File> {
+    use std::os::fd::AsFd;
+
+    let owned = match fd {
+        0 => io::stdin().as_fd().try_clone_to_owned()?,
+        1 => io::stdout().as_fd().try_clone_to_owned()?,
+        _ => io::stderr().as_fd().try_clone_to_owned()?,
+    };
+
+    Ok(File::from(owned))
+}
+
+#[cfg(windows)]
+fn dup_inherited(fd: u32) -> io::Result<File> {
+    use std::os::windows::io::AsHandle;
+
+    let owned = match fd {
+        0 => io::stdin().as_handle().try_clone_to_owned()?,
+        1 => io::stdout().as_handle().try_clone_to_owned()?,
+        _ => io::stderr().as_handle().try_clone_to_owned()?,
+    };
+
+    Ok(File::from(owned))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn dup_inherited(_fd: u32) -> io::Result<File> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Translates device paths scripts commonly redirect to
+#[cfg(windows)]
+fn redirect_path(target: &str) -> &str {
+    match target {
+        "/dev/null" => "NUL",
+        _ => target,
+    }
+}
+
+#[cfg(not(windows))]
+fn redirect_path(target: &str) -> &str {
+    target
+}
+
+/// Converts exit status of a process to a shell status, processes killed by a
+/// signal get 128 + signal number
+pub fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    1
+}
+
+#[cfg(unix)]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata().is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(all(not(unix), not(target_family = "wasm")))]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Name contains a directory so it is not looked up in `PATH`
+#[cfg(not(target_family = "wasm"))]
+fn is_path(name: &str) -> bool {
+    name.contains('/') || (cfg!(windows) && name.contains('\\'))
+}
+
+/// Extensions tried when looking up `name`, on windows these come from
+/// `PATHEXT` and the name is taken as is only if it already has one
+#[cfg(windows)]
+fn extensions(name: &str, pathext: Option<String>) -> Vec<String> {
+    let mut extensions = vec![];
+    if Path::new(name).extension().is_some() {
+        extensions.push(String::new());
+    }
+
+    let pathext = pathext.unwrap_or_else(|| DEFAULT_PATHEXT.to_string());
+    extensions.extend(pathext.split(';').filter(|x| !x.is_empty()).map(|x| x.to_string()));
+
+    extensions
+}
+
+#[cfg(all(not(windows), not(target_family = "wasm")))]
+fn extensions(_name: &str, _pathext: Option<String>) -> Vec<String> {
+    vec![String::new()]
+}
+
+/// Finds the executable in `PATH`, trying each of the `extensions`, on
+/// failure returns the status with the error message
+#[cfg(not(target_family = "wasm"))]
+fn find_command(name: &str, paths: &str, extensions: &[String]) -> Result<PathBuf, (i32, Error)> {
+    let candidates = |path: PathBuf| extensions.iter().map(move |ext| {
+        let mut path = path.clone().into_os_string();
+        path.push(ext);
+        PathBuf::from(path)
+    });
+
+    // paths are not looked up
+    if is_path(name) {
+        let mut exists = false;
+        for path in candidates(PathBuf::from(name)) {
+            if is_executable(&path) {
+                return Ok(path);
+            }
+
+            exists |= path.exists();
+        }
+
+        return if exists {
+            Err((STATUS_NOT_EXECUTABLE, Error::exec(format!("{}: Permission denied", name))))
+        } else {
+            Err((STATUS_NOT_FOUND, Error::exec(format!("{}: No such file or directory", name))))
+        };
+    }
+
+    let mut found_non_executable = false;
+    for dir in std::env::split_paths(paths) {
+        for path in candidates(dir.join(name)) {
+            if is_executable(&path) {
+                return Ok(path);
+            }
+
+            if path.is_file() {
+                found_non_executable = true;
+            }
+        }
+    }
+
+    if found_non_executable {
+        Err((STATUS_NOT_EXECUTABLE, Error::exec(format!("{}: Permission denied", name))))
+    } else {
+        Err((STATUS_NOT_FOUND, Error::exec(format!("{}: command not found", name))))
+    }
+}
+
+/// Used for lookup when `PATH` is not set
+#[cfg(not(windows))]
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+#[cfg(windows)]
+pub const DEFAULT_PATH: &str = "C:\\Windows\\System32;C:\\Windows";
+
+/// Used for lookup when `PATHEXT` is not set
+#[cfg(windows)]
+pub const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// Used for field splitting when `IFS` is not set
+pub const DEFAULT_IFS: &str = " \t\n";
+
+/// Used for the xtrace prefix when `PS4` is not set
+pub const DEFAULT_PS4: &str = "+ ";
+
+/// Prompt for the rest of a command when `PS2` is not set
+pub const DEFAULT_PS2: &str = "> ";
+
+/// Nested function calls allowed before giving up, so infinite recursion does
+/// not overflow the stack
+pub const MAX_FUNCTION_DEPTH: usize = 1000;
+
+/// Quotes the text for the shell if it has any special characters
+pub fn quote(text: &str) -> String {
+    let plain = !text.is_empty()
+        && text.chars().all(|x| x.is_ascii_alphanumeric() || "_-./=:,+@%^".contains(x));
+
+    if plain {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub value: String,
+
+    /// Passed in the environment of commands
+    pub exported: bool,
+}
+
+/// Buffer the running commands were parsed from
+#[derive(Debug)]
+pub struct SourceFile {
+    /// Path of the script, or a name like `-c`
+    pub name: String,
+
+    pub text: Rc<String>,
+
+    /// Syntax tree of the text, the functions defined in it point into it
+    pub ast: Ast,
+
+    /// Byte index of start of each line after the first, found when a line
+    /// is first needed, shared with the sources of the bodies
+    lines: Rc<OnceCell<Vec<usize>>>,
+
+    /// Bodies of the functions that were not raised yet, for scripts read
+    /// from the cache
+    bodies: ir::Bodies,
+
+    /// Sources of the bodies raised so far by the start of the function
+    raised: RefCell<HashMap<usize, Rc<SourceFile>>>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, text: Rc<String>, ast: Ast) -> Self {
+        Self {
+            name: name.to_string(),
+            text,
+            ast,
+            lines: Rc::new(OnceCell::new()),
+            bodies: ir::Bodies::default(),
+            raised: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Line of the byte index, starting at 1
+    pub fn line(&self, index: usize) -> usize {
+        let lines = self.lines.get_or_init(|| {
+            self.text.match_indices('\n').map(|(i, _)| i + 1).collect()
+        });
+
+        // lines starting at or before the index
+        lines.partition_point(|x| *x <= index) + 1
+    }
+}
+
+/// Function with the source it was defined in
+#[derive(Debug, Clone)]
+pub struct ShellFunction {
+    pub function: Function,
+    pub source: Rc<SourceFile>,
+}
+
+impl ShellFunction {
+    /// Source the body is in and the body, the body of functions read from
+    /// the cache is raised on the first call, `None` if it is not valid
+    pub fn body(&self) -> Option<(Rc<SourceFile>, Script)> {
+        let start = self.function.start;
+        if !self.source.bodies.contains(start) {
+            return Some((self.source.clone(), self.function.body));
+        }
+
+        let mut raised = self.source.raised.borrow_mut();
+        if let Some(x) = raised.get(&start) {
+            return Some((x.clone(), x.ast.script));
+        }
+
+        let source = Rc::new(SourceFile {
+            name: self.source.name.clone(),
+            text: self.source.text.clone(),
+            ast: self.source.bodies.raise(start)?,
+            lines: self.source.lines.clone(),
+            bodies: ir::Bodies::default(),
+            raised: RefCell::new(HashMap::new()),
+        });
+        raised.insert(start, source.clone());
+
+        Some((source.clone(), source.ast.script))
+    }
+}
+
+/// Function call on the call stack
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Name of the function called
+    pub function: Symbol,
+
+    /// Source of the call
+    pub source: Rc<SourceFile>,
+
+    /// Line of the call
+    pub line: usize,
+}
+
+/// Called while running commands, used for debugging and profiling
+pub trait Hook {
+    /// Before the simple command is expanded and run
+    fn before_command(&mut self, shell: &mut Shell, command: &SimpleCommand);
+
+    /// After the command finished, `elapsed` is the wall time until it was
+    /// reaped
+    fn after_command(&mut self, _shell: &mut Shell, _command: &SimpleCommand, _argv: &[String], _elapsed: Duration, _status: i32) {}
+
+    /// After the function was entered, the frame is already on the stack
+    fn enter_function(&mut self, _shell: &mut Shell, _name: &str) {}
+
+    /// Before the function returns, the frame is still on the stack
+    fn exit_function(&mut self, _shell: &mut Shell, _name: &str, _status: i32) {}
+
+    /// When the shell is done, called by the program running it
+    fn finish(&mut self, _shell: &Shell) {}
+}
+
+/// Result of [`Shell::run_str`]
+#[derive(Debug, Clone)]
+pub struct ExecResult {
+    /// Status of the last command
+    pub status: i32,
+
+    /// Everything written to stdout, including by the commands
+    pub stdout: String,
+
+    /// Everything written to stderr, including the errors of the shell
+    pub stderr: String,
+}
+
+/// Command that was started, builtins finish immediately
+enum Running {
+    Done(i32),
+    #[cfg(not(target_family = "wasm"))]
+    Child(Child),
+}
+
+pub struct Shell {
+    /// Strict POSIX mode
+    pub posix: bool,
+
+    /// Status of the last command
+    pub status: i32,
+
+    /// Set by `exit`, nothing is executed after it
+    pub exit: Option<i32>,
+
+    /// Positional parameters, starting with `$0`
+    pub args: Vec<String>,
+
+    /// Print commands before running them, `set -x`
+    pub xtrace: bool,
+
+    /// Exit when a command fails, `set -e`
+    pub errexit: bool,
+
+    /// Do not expand patterns into file names, `set -f`
+    pub noglob: bool,
+
+    /// Where JSON events of finished commands are written, one per line
+    pub trace: Option<Box<dyn Write>>,
+
+    /// Shell variables
+    pub vars: Table<Symbol, Variable>,
+
+    /// Defined functions
+    pub functions: Table<Symbol, ShellFunction>,
+
+    /// Replacements of command names in lines typed interactively, see
+    /// [`Shell::expand_aliases`]
+    pub aliases: Table<Symbol, String>,
+
+    /// Called while running commands
+    pub hook: Option<Box<dyn Hook>>,
+
+    /// Source of the commands being run
+    source: Rc<SourceFile>,
+
+    /// Functions being run, the innermost last
+    stack: Vec<Frame>,
+
+    /// Byte index of the command being run in `source`
+    position: usize,
+
+    /// Set by `return`, stops the function
+    pub(crate) returning: bool,
+
+    /// Descriptors the commands start with
+    io: [Io; 3],
+
+    builtins: Registry,
+}
+
+impl Shell {
+    pub fn new(posix: bool) -> Self {
+        let mut shell = Self {
+            posix,
+            status: 0,
+            exit: None,
+            args: vec!["rush".to_string()],
+            xtrace: false,
+            errexit: false,
+            noglob: false,
+            trace: None,
+            vars: Table::new(),
+            functions: Table::new(),
+            aliases: Table::new(),
+            hook: None,
+            source: Rc::new(SourceFile::new("rush", Rc::new(String::new()), Ast::default())),
+            stack: vec![],
+            position: 0,
+            returning: false,
+            io: [Io::Inherit(0), Io::Inherit(1), Io::Inherit(2)],
+            builtins: Registry::with_defaults(posix),
+        };
+
+        shell.set_var("RUSH_VERSION", env!("CARGO_PKG_VERSION"));
+        shell.set_var("IFS", DEFAULT_IFS);
+
+        shell
+    }
+
+    /// Copy of the shell to run a subshell in, changes made by either one
+    /// are not seen by the other
+    ///
+    /// Variables, functions and builtins are shared until they are changed so
+    /// making one costs nearly nothing, the hook and the trace writer stay
+    /// with the parent
+    pub fn subshell(&self) -> io::Result<Shell> {
+        Ok(Self {
+            posix: self.posix,
+            status: self.status,
+            exit: None,
+            args: self.args.clone(),
+            xtrace: self.xtrace,
+            errexit: self.errexit,
+            noglob: self.noglob,
+            trace: None,
+            vars: self.vars.clone(),
+            functions: self.functions.clone(),
+            aliases: self.aliases.clone(),
+            hook: None,
+            source: self.source.clone(),
+            stack: self.stack.clone(),
+            position: self.position,
+            returning: false,
+            io: [self.io[0].share()?, self.io[1].share()?, self.io[2].share()?],
+            builtins: self.builtins.clone(),
+        })
+    }
+
+    /// Imports the environment as exported variables
+    ///
+    /// Entries that are not valid names, like exported bash functions, are
+    /// dropped and `IFS` is never inherited as it changes how every command
+    /// is split
+    pub fn import_env(&mut self, env: impl IntoIterator<Item = (String, String)>) {
+        for (name, value) in env {
+            if !is_name(&name) || name == "IFS" || value.starts_with("() {") {
+                continue;
+            }
+
+            self.vars.insert(Symbol::intern(&name), Variable { value, exported: true });
+        }
+    }
+
+    /// Sets the variable and exports it
+    pub fn export_var(&mut self, name: &str, value: &str) {
+        self.vars.insert(Symbol::intern(name), Variable {
+            value: value.to_string(),
+            exported: true,
+        });
+    }
+
+    /// Sets the variable, keeping it exported if it was
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        let name = Symbol::intern(name);
+        match self.vars.get_mut(&name) {
+            Some(var) => var.value = value.to_string(),
+            None => {
+                self.vars.insert(name, Variable {
+                    value: value.to_string(),
+                    exported: false,
+                });
+            },
+        }
+    }
+
+    /// Adds the builtin, replacing any with the same name
+    pub fn register_builtin(&mut self, builtin: impl Builtin + 'static) {
+        self.builtins.register(builtin);
+    }
+
+    /// See [`Registry::defer`]
+    pub fn defer_builtins(&mut self, load: impl FnOnce() -> Vec<Rc<dyn Builtin>> + 'static) {
+        self.builtins.defer(load);
+    }
+
+    pub fn builtins(&self) -> &Registry {
+        &self.builtins
+    }
+
+    /// Variables passed to commands
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.as_str(), var.value.as_str()))
+    }
+
+    /// Value of a variable or special parameter
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.dynamic(name).unwrap_or_else(|| self.vars.get(&Symbol::get(name)?).map(|x| x.value.clone()))
+    }
+
+    /// Same as [`Shell::var`] for a name from the syntax tree, it is already
+    /// interned so it is not hashed again
+    fn lookup(&self, name: Symbol) -> Option<String> {
+        self.dynamic(name.as_str()).unwrap_or_else(|| self.vars.get(&name).map(|x| x.value.clone()))
+    }
+
+    /// Value of a special parameter or a variable the shell computes, `None`
+    /// if it is a plain variable
+    fn dynamic(&self, name: &str) -> Option<Option<String>> {
+        let value = match name {
+            "?" => Some(self.status.to_string()),
+            "$" => Some(std::process::id().to_string()),
+            "#" => Some(self.args.len().saturating_sub(1).to_string()),
+            "-" => Some(format!("{}{}{}", if self.errexit { "e" } else { "" }, if self.noglob { "f" } else { "" }, if self.xtrace { "x" } else { "" })),
+            "LINENO" => Some(self.line().to_string()),
+            "RUSH_FUNCNAME" => self.backtrace_var(|x| x.function.to_string()),
+            "RUSH_SOURCE" => self.backtrace_var(|x| x.source.name.clone()),
+            "RUSH_LINENO" => self.backtrace_var(|x| x.line.to_string()),
+            "@" => Some(self.positional().join(" ")),
+
+            // joined with the first character of IFS, nothing if it is empty
+            "*" => {
+                let ifs = self.var("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string());
+                let separator = ifs.chars().next().map(String::from).unwrap_or_default();
+                Some(self.positional().join(&separator))
+            },
+            _ => match name.parse::<usize>() {
+                Ok(i) => self.args.get(i).cloned(),
+                Err(_) => return None,
+            },
+        };
+
+        Some(value)
+    }
+
+    /// Positional parameters without `$0`
+    fn positional(&self) -> &[String] {
+        self.args.get(1..).unwrap_or_default()
+    }
+
+    /// Expands the word into fields, unquoted variables are split on `IFS`
+    ///
+    /// Each positional parameter of `"$@"` is its own field, the first and
+    /// the last are joined with the text around them
+    pub(crate) fn expand_fields(&self, parts: &[WordPart]) -> Vec<String> {
+        let ifs = self.var("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string());
+        let mut fields = vec![];
+        let mut current = String::new();
+
+        // quoted empty string is still a field
+        let mut has_current = false;
+
+        // the last field was ended by whitespace, which joins with the next
+        // separator that is not
+        let mut ended = false;
+
+        // the field as a pattern with the quoted parts escaped, and whether
+        // any unquoted part could match files
+        let mut pattern = String::new();
+        let mut glob = false;
+
+        let mut field = |current: &mut String, pattern: &mut String, glob: &mut bool| {
+            let matched = match *glob && !self.noglob {
+                true => glob::expand(pattern, self.posix),
+                false => vec![],
+            };
+
+            // a pattern that matches nothing is kept as it is
+            match matched.is_empty() {
+                true => fields.push(std::mem::take(current)),
+                false => fields.extend(matched),
+            }
+
+            pattern.clear();
+            *glob = false;
+        };
+
+        for part in parts {
+            match part {
+                WordPart::Literal(x) => {
+                    current.push_str(x);
+                    pattern.push_str(x);
+                    glob |= x.contains(['*', '?', '[']);
+                    has_current = true;
+                },
+
+                WordPart::Quoted(x) => {
+                    current.push_str(x);
+                    glob::escape(x, &mut pattern);
+                    has_current = true;
+                },
+
+                WordPart::Variable { name, quoted: true } if *name == "@" => {
+                    for (i, value) in self.positional().iter().enumerate() {
+                        if i > 0 {
+                            field(&mut current, &mut pattern, &mut glob);
+                        }
+
+                        current.push_str(value);
+                        glob::escape(value, &mut pattern);
+                        has_current = true;
+                    }
+                },
+
+                WordPart::Variable { name, quoted: true } => {
+                    let value = self.lookup(*name).unwrap_or_default();
+                    current.push_str(&value);
+                    glob::escape(&value, &mut pattern);
+                    has_current = true;
+                },
+
+                WordPart::Variable { name, quoted: false } => {
+                    // unquoted `$@` and `$*` split each parameter on its own
+                    let values = match name.as_str() {
+                        "@" | "*" => self.positional().to_vec(),
+                        _ => vec![self.lookup(*name).unwrap_or_default()],
+                    };
+
+                    for (i, value) in values.iter().enumerate() {
+                        // parameters are separated like whitespace would
+                        if i > 0 && has_current {
+                            field(&mut current, &mut pattern, &mut glob);
+                            has_current = false;
+                            ended = true;
+                        }
+
+                        for ch in value.chars() {
+                            if !ifs.contains(ch) {
+                                current.push(ch);
+                                pattern.push(ch);
+                                glob |= matches!(ch, '*' | '?' | '[');
+                                has_current = true;
+                                ended = false;
+                            } else if matches!(ch, ' ' | '\t' | '\n') {
+                                // runs of whitespace are a single separator
+                                if has_current {
+                                    field(&mut current, &mut pattern, &mut glob);
+                                    has_current = false;
+                                    ended = true;
+                                }
+                            } else {
+                                // any other separator ends a field even if it
+                                // is empty, unless whitespace just ended it
+                                if has_current || !ended {
+                                    field(&mut current, &mut pattern, &mut glob);
+                                }
+
+                                has_current = false;
+                                ended = false;
+                            }
+                        }
+                    }
+                },
+            }
+        }
+
+        if has_current {
+            field(&mut current, &mut pattern, &mut glob);
+        }
+
+        fields
+    }
+
+    /// Expands the word without field splitting
+    pub(crate) fn expand_word(&self, parts: &[WordPart]) -> String {
+        parts.iter()
+            .map(|x| match x {
+                WordPart::Literal(x) | WordPart::Quoted(x) => x.clone(),
+                WordPart::Variable { name, .. } => self.lookup(*name).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Runs the source capturing its output
+    pub fn run_str(&mut self, source: &str) -> ExecResult {
+        let (Ok((stdout, stdout_writer)), Ok((stderr, stderr_writer))) = (io::pipe(), io::pipe()) else {
+            return ExecResult {
+                status: 1,
+                stdout: String::new(),
+                stderr: "rush: could not create a pipe\n".to_string(),
+            };
+        };
+
+        // read while running so commands never block on a full pipe
+        let read = |mut reader: PipeReader| std::thread::spawn(move || {
+            let mut buffer = vec![];
+            let _ = reader.read_to_end(&mut buffer);
+            String::from_utf8_lossy(&buffer).into_owned()
+        });
+
+        let stdout = read(stdout);
+        let stderr = read(stderr);
+
+        let stdin = self.io[0].share().unwrap_or(Io::Inherit(0));
+        let saved = std::mem::replace(&mut self.io, [stdin, Io::Writer(stdout_writer), Io::Writer(stderr_writer)]);
+        let status = self.eval(source);
+
+        // closes the writers so the readers finish
+        self.io = saved;
+
+        ExecResult {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        }
+    }
+
+    /// Tokenizes, parses and runs the source, returns the status of the last
+    /// command
+    pub fn eval(&mut self, source: &str) -> i32 {
+        let name = self.source.name.clone();
+        self.eval_file(&name, source)
+    }
+
+    /// Same as [`Shell::eval`], the `name` is used to tell where commands
+    /// come from, like in the call stack
+    pub fn eval_file(&mut self, name: &str, source: &str) -> i32 {
+        let ast = parse(Lexer::new(Rc::new(source.to_string()), self.posix))
+            .map(|x| (x, ir::Bodies::default()))
+            .map_err(Error::from);
+
+        self.run_file(name, source, ast)
+    }
+
+    /// Whether the lines typed so far are a whole command, they are not if
+    /// they end before it does like after `|` or inside a string, then an
+    /// interactive shell reads another line
+    pub fn is_complete(&self, source: &str) -> bool {
+        let buffer = Rc::new(source.to_string());
+
+        // a continuation at the very end only joins with the next line
+        let continued = Lexer::new(buffer.clone(), self.posix)
+            .last()
+            .is_some_and(|x| x.is_ok_and(|x| matches!(&x.token, Token::Symbol(x) if x == "\\\n")));
+
+        !continued && parse(Lexer::new(buffer, self.posix)).err().is_none_or(|x| !x.incomplete)
+    }
+
+    /// Expanded `PS2`, shown while the rest of a command is read
+    pub fn continuation_prompt(&self) -> String {
+        let ps2 = self.var("PS2").unwrap_or_else(|| DEFAULT_PS2.to_string());
+        self.expand_word(&double_quoted(&ps2))
+    }
+
+    /// Line with the aliases at the start of its commands replaced, only
+    /// lines typed interactively are expanded
+    pub fn expand_aliases(&self, line: &str) -> String {
+        alias::expand(&self.aliases, line, self.posix)
+    }
+
+    /// Same as [`Shell::eval_file`] but the parsed script is cached on disk,
+    /// for files that are run often like the rc file, functions read from
+    /// the cache are only raised when they are called
+    pub fn eval_cached(&mut self, name: &str, source: &str) -> i32 {
+        let ast = ir::load(source, self.posix);
+        self.run_file(name, source, ast)
+    }
+
+    fn run_file(&mut self, name: &str, source: &str, ast: Result<(Ast, ir::Bodies), Error>) -> i32 {
+        let (ast, bodies) = match ast {
+            Ok(x) => x,
+            Err(err) => {
+                let text = self.render_error_in(&err, name, source);
+                let _ = write!(self.io[2], "{}", text);
+                self.status = STATUS_USAGE;
+                return self.status;
+            },
+        };
+
+        let mut source = SourceFile::new(name, Rc::new(source.to_string()), ast);
+        source.bodies = bodies;
+
+        let source = Rc::new(source);
+        let script = source.ast.script;
+
+        let saved = std::mem::replace(&mut self.source, source);
+        let saved_position = std::mem::take(&mut self.position);
+        let status = self.run(&script);
+        self.source = saved;
+        self.position = saved_position;
+
+        status
+    }
+
+    /// Source of the commands being run
+    pub fn source(&self) -> &Rc<SourceFile> {
+        &self.source
+    }
+
+    /// Functions being run, the innermost last
+    pub fn stack(&self) -> &[Frame] {
+        &self.stack
+    }
+
+    /// Line of the command being run
+    pub fn line(&self) -> usize {
+        self.source.line(self.position)
+    }
+
+    /// Where each function is at, the innermost first and `main` last
+    pub fn backtrace(&self) -> Vec<Frame> {
+        let mut source = self.source.clone();
+        let mut line = self.line();
+        let mut trace = vec![];
+
+        for frame in self.stack.iter().rev() {
+            trace.push(Frame {
+                function: frame.function,
+                source,
+                line,
+            });
+
+            source = frame.source.clone();
+            line = frame.line;
+        }
+
+        trace.push(Frame {
+            function: Symbol::intern("main"),
+            source,
+            line,
+        });
+
+        trace
+    }
+
+    /// Column of the backtrace as lines, inside functions only like the
+    /// arrays of bash
+    fn backtrace_var(&self, f: impl Fn(&Frame) -> String) -> Option<String> {
+        if self.stack.is_empty() {
+            return None;
+        }
+
+        Some(self.backtrace().iter().map(f).collect::<Vec<_>>().join("\n"))
+    }
+
+    /// Runs the script, it has to be from the syntax tree of the current
+    /// source
+    pub fn run(&mut self, script: &Script) -> i32 {
+        // kept so the tree can be borrowed while the shell is changed
+        let source = self.source.clone();
+        let ast = &source.ast;
+
+        for and_or in &ast[script.commands] {
+            if self.exit.is_some() || self.returning {
+                break;
+            }
+
+            self.run_and_or(ast, and_or);
+        }
+
+        self.status
+    }
+
+    fn run_and_or(&mut self, ast: &Ast, and_or: &AndOr) {
+        self.status = self.run_pipeline(ast, &and_or.first);
+        let rest = &ast[and_or.rest];
+
+        // failures of negated pipelines and of all but the last one in the
+        // list are checked by the script so they do not trigger errexit
+        let mut checked = and_or.first.negated || !and_or.rest.is_empty();
+
+        for (i, (connector, pipeline)) in rest.iter().enumerate() {
+            if self.exit.is_some() || self.returning {
+                return;
+            }
+
+            let run = match connector {
+                Connector::And => self.status == 0,
+                Connector::Or => self.status != 0,
+            };
+
+            if run {
+                self.status = self.run_pipeline(ast, pipeline);
+                checked = pipeline.negated || i + 1 < rest.len();
+            }
+        }
+
+        if self.errexit && self.status != 0 && !checked && self.exit.is_none() && !self.returning {
+            // `sh` exits silently
+            if !self.posix {
+                let _ = writeln!(self.io[2], "rush: exiting on error with status {} (set -e)", self.status);
+                for frame in self.backtrace() {
+                    let _ = writeln!(self.io[2], "    at {} ({}:{})", frame.function, frame.source.name, frame.line);
+                }
+            }
+
+            self.exit = Some(self.status);
+        }
+    }
+
+    fn run_pipeline(&mut self, ast: &Ast, pipeline: &Pipeline) -> i32 {
+        let len = pipeline.commands.len();
+
+        let base = (|| -> io::Result<[Io; 3]> {
+            Ok([self.io[0].share()?, self.io[1].share()?, self.io[2].share()?])
+        })();
+
+        let [stdin, stdout, stderr] = match base {
+            Ok(x) => x,
+            Err(err) => {
+                self.report(err.into());
+                return 1;
+            },
+        };
+
+        let mut stdins = vec![stdin];
+        let mut stdouts = vec![];
+        let mut stderrs = vec![stderr];
+        for _ in 1..len {
+            let result = io::pipe().and_then(|(reader, writer)| Ok((reader, writer, stderrs[0].share()?)));
+            match result {
+                Ok((reader, writer, stderr)) => {
+                    stdins.push(Io::Reader(reader));
+                    stdouts.push(Io::Writer(writer));
+                    stderrs.push(stderr);
+                },
+                Err(err) => {
+                    self.report(err.into());
+                    return 1;
+                },
+            }
+        }
+        stdouts.push(stdout);
+
+        // started from the end so builtins never write into a pipe nobody
+        // reads from yet
+        let mut running = vec![];
+        for command in ast[pipeline.commands].iter().rev() {
+            let fds = [stdins.pop().unwrap(), stdouts.pop().unwrap(), stderrs.pop().unwrap()];
+            let start = (SystemTime::now(), Instant::now());
+            running.push(match command {
+                ShellCommand::Simple(x) => {
+                    let (running, argv) = self.run_command(ast, x, fds, len > 1);
+                    (running, Some((x, argv)), start)
+                },
+                ShellCommand::Function(x) => {
+                    self.functions.insert(x.name, ShellFunction {
+                        function: *x,
+                        source: self.source.clone(),
+                    });
+                    (Running::Done(0), None, start)
+                },
+            });
+        }
+
+        // the pipeline has the status of the last command
+        let mut status = 0;
+        for (i, (x, argv, (start, instant))) in running.into_iter().enumerate() {
+            // only builtins run on wasm
+            #[cfg_attr(target_family = "wasm", allow(clippy::infallible_destructuring_match))]
+            let result = match x {
+                Running::Done(status) => status,
+                #[cfg(not(target_family = "wasm"))]
+                Running::Child(mut child) => match child.wait() {
+                    Ok(status) => exit_code(status),
+                    Err(err) => {
+                        self.report(err.into());
+                        1
+                    },
+                },
+            };
+
+            if let Some((command, argv)) = argv {
+                let elapsed = instant.elapsed();
+                if self.trace.is_some() {
+                    self.trace_event(&argv, start, elapsed, result);
+                }
+
+                if let Some(mut hook) = self.hook.take() {
+                    hook.after_command(self, command, &argv, elapsed, result);
+                    self.hook.get_or_insert(hook);
+                }
+            }
+
+            if i == 0 {
+                status = result;
+            }
+        }
+
+        // the status of exit and return is kept as it is
+        if pipeline.negated && self.exit.is_none() && !self.returning {
+            status = if status == 0 { 1 } else { 0 };
+        }
+
+        status
+    }
+
+    /// Expands and starts the command, returns it with the expanded arguments
+    fn run_command(&mut self, ast: &Ast, command: &SimpleCommand, fds: [Io; 3], in_pipeline: bool) -> (Running, Vec<String>) {
+        self.position = command.start;
+
+        // taken out so commands it runs do not call it again
+        if let Some(mut hook) = self.hook.take() {
+            hook.before_command(self, command);
+            self.hook.get_or_insert(hook);
+        }
+
+        // like quitting in the debugger
+        if let Some(status) = self.exit {
+            return (Running::Done(status), vec![]);
+        }
+
+        let argv: Vec<String> = ast[command.words].iter().flat_map(|x| self.expand_fields(&ast[x.parts])).collect();
+        let assignments: Vec<(&str, String)> = ast[command.assignments].iter()
+            .map(|x| (x.name.as_str(), self.expand_word(&ast[x.value.parts])))
+            .collect();
+
+        if self.xtrace {
+            self.xtrace(&assignments, &argv);
+        }
+
+        (self.start_command(ast, command, &argv, assignments, fds, in_pipeline), argv)
+    }
+
+    /// Prints the command after expansion prefixed with expanded `PS4`
+    fn xtrace(&mut self, assignments: &[(&str, String)], argv: &[String]) {
+        let ps4 = self.var("PS4").unwrap_or_else(|| DEFAULT_PS4.to_string());
+        let prefix = self.expand_word(&double_quoted(&ps4));
+
+        let line: Vec<String> = assignments.iter()
+            .map(|(name, value)| format!("{}={}", name, quote(value)))
+            .chain(argv.iter().map(|x| quote(x)))
+            .collect();
+
+        let _ = writeln!(self.io[2], "{}{}", prefix, line.join(" "));
+    }
+
+    /// Writes the JSON trace event of a finished command
+    fn trace_event(&mut self, argv: &[String], start: SystemTime, duration: Duration, status: i32) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+
+        let cwd = std::env::current_dir().map(|x| x.display().to_string()).unwrap_or_default();
+        let event = Json::object([
+            ("command", argv.first().map(|x| x.as_str()).unwrap_or_default().into()),
+            ("argv", Json::Array(argv.iter().map(|x| x.as_str().into()).collect())),
+            ("cwd", cwd.into()),
+            ("start", start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64().into()),
+            ("duration", duration.as_secs_f64().into()),
+            ("status", status.into()),
+        ]);
+
+        if let Err(err) = writeln!(trace, "{}", event).and_then(|_| trace.flush()) {
+            self.report(Error::from(err).context("trace"));
+            self.trace = None;
+        }
+    }
+
+    fn start_command(&mut self, ast: &Ast, command: &SimpleCommand, argv: &[String], assignments: Vec<(&str, String)>, mut fds: [Io; 3], in_pipeline: bool) -> Running {
+        for redirect in &ast[command.redirects] {
+            if let Err(err) = self.redirect(ast, redirect, &mut fds) {
+                let _ = write!(fds[2], "{}", self.render_error(&err));
+                return Running::Done(1);
+            }
+        }
+
+        // expanding to nothing leaves argv empty so there is a word
+        let (Some(name), Some(word)) = (argv.first(), ast[command.words].first()) else {
+            // only assignments and redirections, in a pipeline they would be
+            // in a subshell
+            if !in_pipeline {
+                for (name, value) in assignments {
+                    self.set_var(name, &value);
+                }
+            }
+
+            return Running::Done(0);
+        };
+
+        // in a pipeline they run in a subshell like in other shells, so they
+        // can not change this one
+        if in_pipeline && (self.find_function(name).is_some() || self.builtins.get_registered(name).is_some()) {
+            return self.start_in_subshell(name, (word.start, word.end), argv, fds, false);
+        }
+
+        match self.start_internal(name, (word.start, word.end), argv, fds, false, false) {
+            Ok(running) => running,
+            Err(fds) => self.spawn((word.start, word.end), name, argv, assignments, fds, in_pipeline),
+        }
+    }
+
+    /// Runs the function or builtin, the descriptors are given back if there
+    /// is neither, the deferred builtins are only looked at with `deferred`
+    fn start_internal(&mut self, name: &str, (start, end): (usize, usize), argv: &[String], mut fds: [Io; 3], in_pipeline: bool, deferred: bool) -> Result<Running, [Io; 3]> {
+        // functions can override builtins
+        if let Some(function) = self.find_function(name).cloned() {
+            return Ok(Running::Done(self.call(&function, (start, end), argv, fds)));
+        }
+
+        let builtin = match deferred {
+            true => self.builtins.get(name),
+            false => self.builtins.get_registered(name),
+        };
+
+        let Some(builtin) = builtin else {
+            return Err(fds);
+        };
+
+        let [_, stdout, stderr] = &mut fds;
+        let mut ctx = Context {
+            shell: self,
+            stdout,
+            stderr,
+            in_pipeline,
+        };
+
+        Ok(Running::Done(builtin.invoke(&mut ctx, &argv[1..])))
+    }
+
+    /// Runs the function or builtin of a pipeline in a subshell, kept out of
+    /// [`Shell::start_command`] as recursive functions go through it on
+    /// every call
+    fn start_in_subshell(&mut self, name: &str, (start, end): (usize, usize), argv: &[String], mut fds: [Io; 3], deferred: bool) -> Running {
+        let mut subshell = match self.subshell() {
+            Ok(x) => x,
+            Err(err) => {
+                let err = Error::exec(format!("{}: {}", name, err));
+                let _ = write!(fds[2], "{}", self.render_error(&err.at(start, end)));
+                return Running::Done(1);
+            },
+        };
+
+        subshell.hook = self.hook.take();
+        subshell.trace = self.trace.take();
+
+        let running = subshell.start_internal(name, (start, end), argv, fds, true, deferred).unwrap_or(Running::Done(0));
+
+        self.hook = subshell.hook.take();
+        self.trace = subshell.trace.take();
+
+        running
+    }
+
+    /// Function named `name`, if there is one
+    fn find_function(&self, name: &str) -> Option<&ShellFunction> {
+        self.functions.get(&Symbol::get(name)?)
+    }
+
+    /// Opens the target of the redirect into `fds`
+    fn redirect(&mut self, ast: &Ast, redirect: &Redirect, fds: &mut [Io; 3]) -> Result<(), Error> {
+        let (start, end) = (redirect.target.start, redirect.target.end);
+        let expanded = self.expand_word(&ast[redirect.target.parts]);
+        if expanded.is_empty() {
+            return Err(Error::Expansion {
+                message: format!("{}: ambiguous redirect", self.source.text.get(start..end).unwrap_or_default()),
+                start,
+                end,
+            });
+        }
+
+        let target = redirect_path(&expanded);
+        if redirect.fd > 2 {
+            return Err(Error::exec(format!("{}: redirecting descriptors above 2 is not supported", redirect.fd)).at(start, end));
+        }
+
+        let io = match redirect.kind {
+            RedirectKind::Input => File::open(target).map(Io::File),
+            RedirectKind::Output => File::create(target).map(Io::File),
+            RedirectKind::Append => OpenOptions::new().append(true).create(true).open(target).map(Io::File),
+            RedirectKind::Duplicate => match target.parse::<u32>() {
+                Ok(fd) if fd <= 2 => fds[fd as usize].try_clone(),
+                _ => return Err(Error::exec(format!("{}: bad file descriptor", target)).at(start, end)),
+            },
+        };
+
+        fds[redirect.fd as usize] = io.map_err(|x| Error::from(x).context(target).at(start, end))?;
+        Ok(())
+    }
+
+    /// Error as it is printed, pointing at the code in the current source
+    pub(crate) fn render_error(&self, err: &Error) -> String {
+        self.render_error_in(err, &self.source.name, &self.source.text)
+    }
+
+    /// Errors are shown on a single line in POSIX mode like `sh` does,
+    /// otherwise with the code they point to
+    fn render_error_in(&self, err: &Error, name: &str, source: &str) -> String {
+        match self.posix {
+            true => err.render_line(name, source, self.position),
+            false => err.render(name, source),
+        }
+    }
+
+    /// Prints the error to stderr of the shell
+    fn report(&mut self, err: Error) {
+        let text = self.render_error(&err);
+        let _ = write!(self.io[2], "{}", text);
+    }
+
+    /// Runs the deferred builtin, like the ones of plugins, which are only
+    /// loaded for names that are not anything else so starting commands does
+    /// not wait for them
+    fn start_deferred(&mut self, name: &str, span: (usize, usize), argv: &[String], fds: [Io; 3], in_pipeline: bool) -> Result<Running, [Io; 3]> {
+        if self.builtins.get(name).is_none() {
+            return Err(fds);
+        }
+
+        match in_pipeline {
+            true => Ok(self.start_in_subshell(name, span, argv, fds, true)),
+            false => self.start_internal(name, span, argv, fds, false, true),
+        }
+    }
+
+    /// Finds and starts the external command, or the deferred builtin if
+    /// there is none
+    #[cfg(not(target_family = "wasm"))]
+    fn spawn(&mut self, (start, end): (usize, usize), name: &str, argv: &[String], assignments: Vec<(&str, String)>, fds: [Io; 3], in_pipeline: bool) -> Running {
+        let paths = self.var("PATH").unwrap_or_else(|| DEFAULT_PATH.to_string());
+        let extensions = extensions(name, self.var("PATHEXT"));
+        let path = match find_command(name, &paths, &extensions) {
+            Ok(x) => x,
+            Err((status, err)) => {
+                let mut fds = match status == STATUS_NOT_FOUND && !is_path(name) {
+                    true => match self.start_deferred(name, (start, end), argv, fds, in_pipeline) {
+                        Ok(running) => return running,
+                        Err(fds) => fds,
+                    },
+                    false => fds,
+                };
+
+                let err = err.at(start, end);
+                let _ = write!(fds[2], "{}", self.render_error(&err));
+                return Running::Done(status);
+            },
+        };
+
+        // kept for reporting errors after stderr is given to the command
+        let mut errors = fds[2].share().unwrap_or(Io::Inherit(2));
+
+        // NOTE: on windows std runs .bat and .cmd files through cmd.exe
+        let [stdin, stdout, stderr] = fds;
+        let mut child = Command::new(path);
+        child.args(&argv[1..])
+            .env_clear()
+            .envs(self.exported())
+            .envs(assignments)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stderr);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            child.arg0(name);
+        }
+
+        match child.spawn() {
+            Ok(child) => Running::Child(child),
+            Err(err) => {
+                let status = match err.kind() {
+                    io::ErrorKind::NotFound => STATUS_NOT_FOUND,
+                    _ => STATUS_NOT_EXECUTABLE,
+                };
+
+                let err = Error::from(err).context(name).at(start, end);
+                let _ = write!(errors, "{}", self.render_error(&err));
+                Running::Done(status)
+            },
+        }
+    }
+
+    #[cfg(target_family = "wasm")]
+    fn spawn(&mut self, (start, end): (usize, usize), name: &str, argv: &[String], _assignments: Vec<(&str, String)>, fds: [Io; 3], in_pipeline: bool) -> Running {
+        let mut fds = match self.start_deferred(name, (start, end), argv, fds, in_pipeline) {
+            Ok(running) => return running,
+            Err(fds) => fds,
+        };
+
+        let err = Error::exec(format!("{}: running commands is not supported on this platform", name));
+        let _ = write!(fds[2], "{}", self.render_error(&err.at(start, end)));
+        Running::Done(STATUS_NOT_FOUND)
+    }
+
+    /// Runs the function with `argv` as the positional parameters and `fds`
+    /// as its descriptors, `start` and `end` are the span of the name in the
+    /// call
+    fn call(&mut self, function: &ShellFunction, (start, end): (usize, usize), argv: &[String], mut fds: [Io; 3]) -> i32 {
+        let name = &function.function.name;
+
+        // it would wait for the earlier commands which are started after it
+        if let Io::Reader(_) = fds[0] {
+            let err = Error::exec(format!("{}: functions can not read from a pipe yet", name));
+            let _ = write!(fds[2], "{}", self.render_error(&err.at(start, end)));
+            return 1;
+        }
+
+        if self.stack.len() >= MAX_FUNCTION_DEPTH {
+            let err = Error::exec(format!("{}: maximum function nesting level exceeded ({})", name, MAX_FUNCTION_DEPTH));
+            let _ = write!(fds[2], "{}", self.render_error(&err.at(start, end)));
+            return 1;
+        }
+
+        let Some((source, body)) = function.body() else {
+            let err = Error::exec(format!("{}: body could not be read from the cache", name));
+            let _ = write!(fds[2], "{}", self.render_error(&err.at(start, end)));
+            return 1;
+        };
+
+        let args = std::iter::once(self.args[0].clone()).chain(argv[1..].iter().cloned()).collect();
+        let saved_args = std::mem::replace(&mut self.args, args);
+        let saved_io = std::mem::replace(&mut self.io, fds);
+
+        self.stack.push(Frame {
+            function: *name,
+            source: self.source.clone(),
+            line: self.line(),
+        });
+
+        let saved_source = std::mem::replace(&mut self.source, source);
+        let saved_position = self.position;
+
+        if let Some(mut hook) = self.hook.take() {
+            hook.enter_function(self, name.as_str());
+            self.hook.get_or_insert(hook);
+        }
+
+        let status = self.run(&body);
+        self.returning = false;
+
+        if let Some(mut hook) = self.hook.take() {
+            hook.exit_function(self, name.as_str(), status);
+            self.hook.get_or_insert(hook);
+        }
+
+        self.source = saved_source;
+        self.position = saved_position;
+        self.stack.pop();
+        self.io = saved_io;
+        self.args = saved_args;
+
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Empty directory the test can write to, so outputs of redirections are
+    /// never left in the working directory
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rush-exec-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn redirections() {
+        let dir = temp_dir("redirections");
+        let mut shell = Shell::new(false);
+        shell.set_var("dir", dir.to_str().unwrap());
+
+        let result = shell.run_str(concat!(
+            "f() { echo err >&2; echo out; }\n",
+            "echo first > \"$dir/out\"\n",
+            "echo second >> \"$dir/out\"\n",
+            "f 2>\"$dir/log\" >\"$dir/two words\"\n",
+            "f 2>&1\n",
+        ));
+
+        assert_eq!(result.status, 0, "{}", result.stderr);
+        assert_eq!(result.stdout, "err\nout\n");
+        assert_eq!(std::fs::read_to_string(dir.join("out")).unwrap(), "first\nsecond\n");
+        assert_eq!(std::fs::read_to_string(dir.join("log")).unwrap(), "err\n");
+        assert_eq!(std::fs::read_to_string(dir.join("two words")).unwrap(), "out\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn redirection_errors() {
+        let dir = temp_dir("redirection-errors");
+        let mut shell = Shell::new(false);
+        shell.set_var("dir", dir.to_str().unwrap());
+
+        let result = shell.run_str("echo a > \"$dir/missing/out\"\necho $?\n");
+        assert_eq!(result.stdout, "1\n");
+        assert!(result.stderr.contains("missing/out"), "{}", result.stderr);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn not_found() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str("rush-no-such-command\necho $?\n./rush-no-such-file\necho $?\n");
+        assert_eq!(result.stdout, "127\n127\n");
+        assert!(result.stderr.contains("rush-no-such-command: command not found"), "{}", result.stderr);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn not_executable() {
+        let dir = temp_dir("not-executable");
+        std::fs::write(dir.join("script"), "echo x\n").unwrap();
+
+        let mut shell = Shell::new(false);
+        shell.set_var("dir", dir.to_str().unwrap());
+
+        let result = shell.run_str("\"$dir/script\"\necho $?\n");
+        assert_eq!(result.stdout, "126\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn killed_by_signal() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str("sh -c 'kill -TERM $$'\necho $?\n");
+        assert_eq!(result.stdout, "143\n");
+    }
+
+    #[test]
+    fn exit() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str("exit 3\necho not reached\n");
+        assert_eq!((result.status, result.stdout.as_str()), (3, ""));
+        assert_eq!(shell.exit, Some(3));
+
+        // the status of the last command without an argument
+        let mut shell = Shell::new(false);
+        assert_eq!(shell.run_str("false\nexit\n").status, 1);
+    }
+
+    #[test]
+    fn negation() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str("! true\necho $?\n! false\necho $?\n! rush-no-such-command 2>/dev/null\necho $?\n");
+        assert_eq!(result.stdout, "1\n0\n0\n");
+    }
+
+    #[test]
+    fn field_splitting() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str(concat!(
+            "f() { echo $#; }\n",
+            "x='  a  b  '\n",
+            "f $x; f \"$x\"\n",
+            "IFS=:\n",
+            "x='a::b:'\n",
+            "f $x\n",
+            "IFS=\n",
+            "x='a b'\n",
+            "f $x\n",
+        ));
+
+        assert_eq!(result.stdout, "2\n1\n3\n1\n");
+    }
+
+    #[test]
+    fn positional_parameters() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str(concat!(
+            "f() { echo $#; }\n",
+            "set -- 'a b' c ''\n",
+            "f \"$@\"; f $@; f \"$*\"; f \"x$@y\"\n",
+            "set --\n",
+            "f \"$@\"\n",
+        ));
+
+        assert_eq!(result.stdout, "3\n3\n1\n3\n0\n");
+    }
+
+    #[test]
+    fn xtrace() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str("f() { true; }\nPS4='[$n] '\nn=1\nset -x\nA='x y' f \"a b\" c\nset +x\nf no\n");
+        assert_eq!(result.stderr, "[1] A='x y' f 'a b' c\n[1] true\n[1] set +x\n");
+    }
+
+    /// Writer shared with the test after it is given to the shell
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_events() {
+        let trace = Shared::default();
+        let mut shell = Shell::new(false);
+        shell.trace = Some(Box::new(trace.clone()));
+        shell.run_str("set -- a \"say \\\"hi\\\"\nbye\"\nfalse\n");
+
+        let written = String::from_utf8(trace.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", written);
+        assert!(lines[0].starts_with(r#"{"command":"set","argv":["set","--","a","say \"hi\"\nbye"],"cwd":"#), "{}", lines[0]);
+
+        let event = Json::parse(lines[1]).unwrap();
+        assert_eq!(event.get("command"), Some(&"false".into()));
+        assert_eq!(event.get("status").and_then(|x| x.as_usize()), Some(1));
+        assert!(event.get("start").and_then(|x| x.as_f64()).is_some_and(|x| x > 0.0));
+        assert!(event.get("duration").and_then(|x| x.as_f64()).is_some_and(|x| x >= 0.0));
+    }
+
+    #[test]
+    fn call_stack() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str(concat!(
+            "inner() {\n",
+            "    caller; caller 0; caller 1\n",
+            "    echo \"$RUSH_FUNCNAME\"; echo \"$RUSH_LINENO\"; echo \"$RUSH_SOURCE\"\n",
+            "    caller 2 || echo past main\n",
+            "}\n",
+            "outer() {\n",
+            "    inner\n",
+            "}\n",
+            "outer\n",
+            "caller || echo not in a function\n",
+            "echo \"[$RUSH_FUNCNAME]\"\n",
+        ));
+
+        assert_eq!(result.stdout, concat!(
+            "7 rush\n7 outer rush\n9 main rush\n",
+            "inner\nouter\nmain\n",
+            "3\n7\n9\n",
+            "rush\nrush\nrush\n",
+            "past main\n",
+            "not in a function\n",
+            "[]\n",
+        ));
+    }
+
+    #[test]
+    fn errexit_backtrace() {
+        let mut shell = Shell::new(false);
+        let result = shell.run_str("set -e\nfail() {\n    false\n}\nwrap() {\n    fail\n}\nwrap\necho not reached\n");
+        assert_eq!((result.status, result.stdout.as_str()), (1, ""));
+        assert_eq!(result.stderr, concat!(
+            "rush: exiting on error with status 1 (set -e)\n",
+            "    at fail (rush:3)\n",
+            "    at wrap (rush:6)\n",
+            "    at main (rush:8)\n",
+        ));
+
+        // sh exits without saying anything
+        let mut shell = Shell::new(true);
+        assert_eq!(shell.run_str("set -e\nf() { false; }\nf\n").stderr, "");
+    }
+}
@@ -0,0 +1,45 @@
+//! Capabilities of this build, so scripts can check for them at runtime
+
+/// Names of the available features, printed by `--features`
+///
+/// Sorted, each one only where the code it names is built
+pub const FEATURES: &[&str] = &[
+    #[cfg(not(target_family = "wasm"))]
+    "commands",
+    "functions",
+    #[cfg(not(target_family = "wasm"))]
+    "lsp",
+    "pipelines",
+    #[cfg(unix)]
+    "plugins",
+    "posix-mode",
+    "redirections",
+    #[cfg(unix)]
+    "signals",
+    "variables",
+    "xdg-dirs",
+];
+
+pub fn has_feature(name: &str) -> bool {
+    FEATURES.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted() {
+        assert!(FEATURES.windows(2).all(|x| x[0] < x[1]), "{:?}", FEATURES);
+    }
+
+    #[test]
+    fn gated() {
+        assert_eq!(has_feature("commands"), cfg!(not(target_family = "wasm")));
+        assert_eq!(has_feature("lsp"), cfg!(not(target_family = "wasm")));
+        assert_eq!(has_feature("plugins"), cfg!(unix));
+        assert_eq!(has_feature("signals"), cfg!(unix));
+        assert!(has_feature("posix-mode"));
+        assert!(!has_feature("no-such-feature"));
+    }
+}
@@ -0,0 +1,245 @@
+//! Formatter for scripts, works on the tokens so comments and blank lines are
+//! kept
+//!
+//! - function bodies and continuation lines are indented by one level
+//! - words are separated by a single space, operators by spaces around them
+//! - double quoted strings without expansions or escapes become single quoted
+//! - consecutive blank lines become one and the file ends with a newline
+
+use std::rc::Rc;
+
+use crate::error::Error;
+use crate::parser::parse;
+use crate::tokenizer::{tokenize, Lexer, Token, TokenWithInfo};
+
+const INDENT: &str = "    ";
+
+/// Formatted pieces of a line
+#[derive(Debug, PartialEq)]
+enum Element {
+    Word(String),
+    Operator(String),
+    Redirect(String),
+    Comment(String),
+
+    /// Escaped newline
+    Continuation,
+    Newline,
+}
+
+fn is_operator(token: &Token) -> bool {
+    match token {
+        Token::Symbol(x) => matches!(x.as_str(), "|" | "||" | "&&" | ";" | "&" | "<" | ">" | ">>" | "<<"),
+        Token::Paren(x) => matches!(x, '(' | ')'),
+        _ => false,
+    }
+}
+
+/// Text of the token in the preferred quoting style
+fn token_text(token: &TokenWithInfo) -> String {
+    if let Token::String(raw, '"') = &token.token {
+        let inner = raw.strip_prefix('"').and_then(|x| x.strip_suffix('"'));
+        if let Some(inner) = inner.filter(|x| !x.contains(['$', '`', '\\', '\''])) {
+            return format!("'{}'", inner);
+        }
+    }
+
+    token.raw().to_string()
+}
+
+fn elements(tokens: &[TokenWithInfo]) -> Vec<Element> {
+    let mut elements: Vec<Element> = vec![];
+    let mut prev_end = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let adjacent = prev_end == Some(token.start);
+        prev_end = Some(token.end);
+
+        match &token.token {
+            Token::Newline(_) => elements.push(Element::Newline),
+            Token::Comment(_) => elements.push(Element::Comment(token.raw().trim_end().to_string())),
+            Token::Symbol(x) if x == "\\\n" && !adjacent => elements.push(Element::Continuation),
+
+            x if is_operator(x) => {
+                let redirect = matches!(x, Token::Symbol(x) if matches!(x.as_str(), "<" | ">" | ">>" | "<<"));
+
+                // descriptor number like 2>, and >& which are both part of
+                // the redirection
+                let text = token.raw().to_string();
+                match elements.last_mut() {
+                    Some(Element::Word(fd)) if redirect && adjacent && fd.chars().all(|x| x.is_ascii_digit()) => {
+                        let fd = std::mem::take(fd);
+                        elements.pop();
+                        elements.push(Element::Redirect(fd + &text));
+                    },
+                    Some(Element::Redirect(op)) if text == "&" && adjacent => op.push('&'),
+                    _ if redirect => elements.push(Element::Redirect(text)),
+                    _ => elements.push(Element::Operator(text)),
+                }
+            },
+
+            _ => {
+                let text = token_text(token);
+                let previous = i.checked_sub(1).map(|x| &tokens[x].token);
+                let continues = adjacent && previous.is_some_and(|x| !is_operator(x) && !matches!(x, Token::Newline(_) | Token::Comment(_)));
+
+                match elements.last_mut() {
+                    Some(Element::Word(word)) if continues => word.push_str(&text),
+                    _ => elements.push(Element::Word(text)),
+                }
+            },
+        }
+    }
+
+    elements
+}
+
+/// Element starts a command, where `{` and `}` are special
+fn starts_command(previous: Option<&Element>) -> bool {
+    match previous {
+        None | Some(Element::Newline) => true,
+        Some(Element::Operator(x)) => matches!(x.as_str(), ";" | "|" | "&&" | "||" | ")"),
+        Some(Element::Word(x)) => x == "{",
+        _ => false,
+    }
+}
+
+/// Formats the script, fails if it has syntax errors
+pub fn format(source: &str, posix: bool) -> Result<String, Error> {
+    let buffer = Rc::new(source.to_string());
+
+    // only valid scripts are formatted
+    parse(Lexer::new(buffer.clone(), posix))?;
+    let tokens = tokenize(buffer, posix)?;
+
+    let mut output = String::new();
+    let mut line = String::new();
+    let mut blank_lines = 0;
+
+    // braces open before the line, opened and closed on it
+    let mut depth: usize = 0;
+    let mut opened: usize = 0;
+    let mut closed: usize = 0;
+
+    // line started by closing a brace
+    let mut dedent = false;
+
+    // next line continues the command
+    let mut continued = false;
+    let mut previous: Option<&Element> = None;
+    let elements = elements(&tokens);
+
+    for element in &elements {
+        match element {
+            Element::Newline | Element::Continuation => {
+                if *element == Element::Continuation {
+                    line.push_str(if line.is_empty() { "\\" } else { " \\" });
+                }
+
+                if line.is_empty() {
+                    blank_lines += 1;
+                } else {
+                    // keep at most one blank line, none at the start
+                    if blank_lines > 0 && !output.is_empty() {
+                        output.push('\n');
+                    }
+
+                    blank_lines = 0;
+                    let level = depth - dedent as usize + continued as usize;
+                    output.push_str(&INDENT.repeat(level));
+                    output.push_str(&line);
+                    output.push('\n');
+                }
+
+                depth = (depth + opened).saturating_sub(dedent as usize + closed);
+                opened = 0;
+                closed = 0;
+                dedent = false;
+
+                continued = match element {
+                    Element::Continuation => true,
+                    _ => matches!(previous, Some(Element::Operator(x)) if matches!(x.as_str(), "|" | "&&" | "||")),
+                };
+
+                line.clear();
+            },
+
+            Element::Operator(op) if op == ";" => line.push(';'),
+
+            // f() {
+            Element::Operator(op) if op == "(" && matches!(previous, Some(Element::Word(_))) => line.push('('),
+            Element::Operator(op) if op == ")" && matches!(previous, Some(Element::Operator(x)) if x == "(") => line.push(')'),
+
+            Element::Operator(op) => {
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+
+                line.push_str(op);
+            },
+
+            Element::Word(text) | Element::Redirect(text) | Element::Comment(text) => {
+                if starts_command(previous) {
+                    match text.as_str() {
+                        "{" => opened += 1,
+                        "}" if opened > 0 => opened -= 1,
+                        "}" if line.is_empty() && !dedent && depth > 0 => dedent = true,
+                        "}" => closed += 1,
+                        _ => {},
+                    }
+                }
+
+                let attached = matches!(previous, Some(Element::Redirect(_)));
+                if !line.is_empty() && !attached {
+                    line.push(' ');
+                }
+
+                line.push_str(text);
+            },
+        }
+
+        previous = Some(element);
+    }
+
+    if !line.is_empty() {
+        if blank_lines > 0 && !output.is_empty() {
+            output.push('\n');
+        }
+
+        let level = depth - dedent as usize + continued as usize;
+        output.push_str(&INDENT.repeat(level));
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(raw: &str) -> TokenWithInfo {
+        TokenWithInfo {
+            start: 0,
+            end: raw.len(),
+            buffer: Rc::new(raw.to_string()),
+            token: Token::String(raw.to_string(), '"'),
+        }
+    }
+
+    #[test]
+    fn double_quotes() {
+        assert_eq!(token_text(&string(r#""a b""#)), "'a b'");
+        assert_eq!(token_text(&string(r#""""#)), "''");
+        assert_eq!(token_text(&string(r#""$a""#)), r#""$a""#);
+
+        // cut short at the end of the buffer
+        assert_eq!(token_text(&string(r#"""#)), r#"""#);
+    }
+
+    #[test]
+    fn scripts() {
+        assert_eq!(format("echo  \"a\"|cat\n\n\n\nf() {\necho x\n}\n", false).unwrap(), "echo 'a' | cat\n\nf() {\n    echo x\n}\n");
+    }
+}
@@ -0,0 +1,454 @@
+//! Pathname expansion, unquoted `*`, `?` and `[...]` in words match file names
+//!
+//! Outside of POSIX mode a `**` component matches any number of directories.
+//! Directories are read in parallel, when more than one branch of the tree has
+//! to be walked the branches are given to worker threads and their matches
+//! are merged back in order, so a large tree or a slow file system does not
+//! walk one directory at a time.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, Scope, ScopedJoinHandle};
+
+/// Escapes the text so it only matches itself, used for quoted parts of words
+pub fn escape(text: &str, out: &mut String) {
+    for ch in text.chars() {
+        if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            out.push('\\');
+        }
+
+        out.push(ch);
+    }
+}
+
+/// Single component of the pattern, between slashes
+#[derive(Debug)]
+enum Component {
+    /// No special characters, already unescaped
+    Literal(String),
+
+    Pattern(Vec<char>),
+
+    /// `**`
+    Recursive,
+}
+
+/// Position after the bracket expression starting at `pattern[i]`, `None` if
+/// it is not terminated so the `[` is taken literally
+fn bracket_end(pattern: &[char], i: usize) -> Option<usize> {
+    let mut j = i + 1;
+    if matches!(pattern.get(j), Some('!' | '^')) {
+        j += 1;
+    }
+
+    // `]` right after the `[` is part of the set
+    if pattern.get(j) == Some(&']') {
+        j += 1;
+    }
+
+    while let Some(ch) = pattern.get(j) {
+        match ch {
+            ']' => return Some(j + 1),
+            '\\' => j += 2,
+            _ => j += 1,
+        }
+    }
+
+    None
+}
+
+/// Whether the pattern has any unescaped special characters
+fn is_pattern(pattern: &[char]) -> bool {
+    let mut i = 0;
+    while let Some(ch) = pattern.get(i) {
+        match ch {
+            '*' | '?' => return true,
+            '[' if bracket_end(pattern, i).is_some() => return true,
+            '\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+
+    false
+}
+
+fn unescape(pattern: &[char]) -> String {
+    let mut text = String::new();
+    let mut iter = pattern.iter();
+    while let Some(ch) = iter.next() {
+        match ch {
+            '\\' => text.extend(iter.next()),
+            _ => text.push(*ch),
+        }
+    }
+
+    text
+}
+
+/// Whether `ch` is in the bracket expression `pattern[i..end]`
+fn bracket_matches(pattern: &[char], i: usize, end: usize, ch: char) -> bool {
+    let mut j = i + 1;
+    let negated = matches!(pattern[j], '!' | '^');
+    if negated {
+        j += 1;
+    }
+
+    let mut found = false;
+    let mut first = true;
+
+    // the closing `]` is at end - 1
+    while j < end - 1 {
+        if pattern[j] == ']' && !first {
+            break;
+        }
+
+        first = false;
+
+        let mut low = pattern[j];
+        if low == '\\' && j + 1 < end - 1 {
+            j += 1;
+            low = pattern[j];
+        }
+        j += 1;
+
+        // range like a-z, a `-` at the end is taken literally
+        let mut high = low;
+        if pattern[j] == '-' && j + 1 < end - 1 {
+            high = pattern[j + 1];
+            if high == '\\' && j + 2 < end - 1 {
+                high = pattern[j + 2];
+                j += 1;
+            }
+            j += 2;
+        }
+
+        if low <= ch && ch <= high {
+            found = true;
+        }
+    }
+
+    found != negated
+}
+
+/// Matches the name against a single component of the pattern
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+
+    // where the last `*` was and the name position it is tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let advanced = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            },
+            Some('?') => {
+                p += 1;
+                true
+            },
+            Some('[') => match bracket_end(pattern, p) {
+                Some(end) if bracket_matches(pattern, p, end, name[n]) => {
+                    p = end;
+                    true
+                },
+                Some(_) => false,
+                None => {
+                    p += 1;
+                    name[n] == '['
+                },
+            },
+            Some('\\') if p + 1 < pattern.len() => {
+                p += 2;
+                pattern[p - 1] == name[n]
+            },
+            Some(ch) => {
+                p += 1;
+                *ch == name[n]
+            },
+            None => false,
+        };
+
+        if advanced {
+            n += 1;
+            continue;
+        }
+
+        // let the last `*` match one more character
+        match star {
+            Some((star_p, star_n)) => {
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, n));
+            },
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Files starting with a dot are only matched if the pattern starts with one
+fn matches_name(pattern: &[char], name: &str) -> bool {
+    let literal_dot = pattern.first() == Some(&'.')
+        || (pattern.first() == Some(&'\\') && pattern.get(1) == Some(&'.'));
+
+    if name.starts_with('.') && !literal_dot {
+        return false;
+    }
+
+    matches(pattern, &name.chars().collect::<Vec<_>>())
+}
+
+/// Names in the directory with whether they are directories, sorted,
+/// unreadable directories are empty like in other shells
+fn read_dir(dir: &Path) -> Vec<(String, bool)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut names: Vec<(String, bool)> = entries
+        .filter_map(|x| x.ok())
+        .filter_map(|x| {
+            // symlinks to directories are followed except by `**`, which
+            // could loop forever
+            let is_dir = x.file_type().ok()?.is_dir();
+            Some((x.file_name().into_string().ok()?, is_dir))
+        })
+        .collect();
+
+    names.sort();
+    names
+}
+
+/// Matches of the branch, run on a worker if one is free
+enum Branch<'a> {
+    Worker(ScopedJoinHandle<'a, Vec<String>>),
+    Done(Vec<String>),
+}
+
+struct Walker {
+    /// Threads that can still be started
+    spare: AtomicUsize,
+}
+
+impl Walker {
+    /// Matches of the components in `dir`, `shown` is how `dir` is written in
+    /// the matches
+    fn walk(&self, dir: &Path, shown: &str, components: &[Component], directory: bool) -> Vec<String> {
+        let Some((component, rest)) = components.split_first() else {
+            return vec![shown.to_string()];
+        };
+
+        match component {
+            Component::Literal(name) => {
+                let path = dir.join(name);
+                let found = match rest.is_empty() {
+                    true if directory => path.is_dir(),
+                    true => std::fs::symlink_metadata(&path).is_ok(),
+                    false => path.is_dir(),
+                };
+
+                if !found {
+                    return vec![];
+                }
+
+                self.walk(&path, &join(shown, name, rest.is_empty() && !directory), rest, directory)
+            },
+
+            Component::Pattern(pattern) => {
+                let last = rest.is_empty() && !directory;
+                let names: Vec<String> = read_dir(dir).into_iter()
+                    .filter(|(name, _)| matches_name(pattern, name))
+                    // anything but the last component has to be a directory,
+                    // symlinks to directories are fine here
+                    .filter(|(name, is_dir)| last || *is_dir || dir.join(name).is_dir())
+                    .map(|(name, _)| name)
+                    .collect();
+
+                let dirs = names.into_iter().map(|name| (dir.join(&name), join(shown, &name, last)));
+                self.branches(dirs, rest, directory)
+            },
+
+            Component::Recursive => {
+                // zero directories
+                let mut found = self.walk(dir, shown, rest, directory);
+
+                let dirs: Vec<_> = read_dir(dir).into_iter()
+                    .filter(|(name, is_dir)| *is_dir && !name.starts_with('.'))
+                    .map(|(name, _)| (dir.join(&name), join(shown, &name, false)))
+                    .collect();
+
+                found.extend(self.branches(dirs.into_iter(), components, directory));
+                found
+            },
+        }
+    }
+
+    /// Walks the directories with the components, in parallel if they have to
+    /// be read, matches are in the order of the directories
+    fn branches(&self, dirs: impl Iterator<Item = (PathBuf, String)>, components: &[Component], directory: bool) -> Vec<String> {
+        // a single stat per branch is not worth a thread
+        let reads = components.iter().any(|x| !matches!(x, Component::Literal(_)));
+
+        thread::scope(|scope| {
+            let branches: Vec<Branch> = dirs
+                .map(|(dir, shown)| match self.spawn(scope, reads, &dir, &shown, components, directory) {
+                    Some(x) => Branch::Worker(x),
+                    None => Branch::Done(self.walk(&dir, &shown, components, directory)),
+                })
+                .collect();
+
+            branches.into_iter()
+                .flat_map(|x| match x {
+                    Branch::Worker(x) => x.join().unwrap_or_default(),
+                    Branch::Done(x) => x,
+                })
+                .collect()
+        })
+    }
+
+    /// Starts walking the branch on a worker, `None` if none is free
+    fn spawn<'scope>(&'scope self, scope: &'scope Scope<'scope, '_>, reads: bool, dir: &Path, shown: &str, components: &'scope [Component], directory: bool) -> Option<ScopedJoinHandle<'scope, Vec<String>>> {
+        if !reads || self.spare.fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| x.checked_sub(1)).is_err() {
+            return None;
+        }
+
+        let (dir, shown) = (dir.to_path_buf(), shown.to_string());
+        let worker = thread::Builder::new().spawn_scoped(scope, move || {
+            let found = self.walk(&dir, &shown, components, directory);
+            self.spare.fetch_add(1, Ordering::AcqRel);
+            found
+        });
+
+        // threads are not supported everywhere, like on wasm
+        if worker.is_err() {
+            self.spare.fetch_add(1, Ordering::AcqRel);
+        }
+
+        worker.ok()
+    }
+}
+
+/// Path of `name` in the directory shown as `shown`, with a slash after it
+/// unless it is the last component
+fn join(shown: &str, name: &str, last: bool) -> String {
+    let mut path = format!("{}{}", shown, name);
+    if !last {
+        path.push('/');
+    }
+
+    path
+}
+
+/// Files matching the pattern, sorted, quoted characters are escaped with a
+/// backslash as [`escape`] does
+///
+/// Nothing is returned if nothing matched or if it is not a pattern, then the
+/// word is kept as it is
+pub fn expand(pattern: &str, posix: bool) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let parts: Vec<&[char]> = chars.split(|x| *x == '/').collect();
+    if !parts.iter().any(|x| is_pattern(x)) {
+        return vec![];
+    }
+
+    let absolute = pattern.starts_with('/');
+    // trailing slash only matches directories
+    let directory = pattern.len() > 1 && pattern.ends_with('/');
+
+    let mut components: Vec<Component> = parts.iter()
+        .filter(|x| !x.is_empty())
+        .map(|x| match x {
+            ['*', '*'] if !posix => Component::Recursive,
+            x if is_pattern(x) => Component::Pattern(x.to_vec()),
+            x => Component::Literal(unescape(x)),
+        })
+        .collect();
+
+    // `**` at the end matches everything under the directory
+    if let Some(Component::Recursive) = components.last() {
+        components.push(Component::Pattern(vec!['*']));
+    }
+
+    let workers = thread::available_parallelism().map_or(1, |x| x.get());
+    let walker = Walker {
+        spare: AtomicUsize::new(workers.saturating_sub(1)),
+    };
+
+    let (dir, shown) = if absolute { ("/", "/") } else { (".", "") };
+    let mut found = walker.walk(Path::new(dir), shown, &components, directory);
+    found.sort();
+    found.dedup();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        matches_name(&pattern.chars().collect::<Vec<_>>(), name)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(glob("*.rs", "main.rs"));
+        assert!(!glob("*.rs", "main.rsx"));
+        assert!(glob("a*b*c", "aXbYbc"));
+        assert!(glob("?x", "ax"));
+        assert!(!glob("?x", "x"));
+        assert!(glob(r"\*", "*"));
+        assert!(!glob(r"\*", "a"));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(glob("[abc]", "b"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(!glob("[!a-c]", "b"));
+        assert!(glob("[]]", "]"));
+        assert!(glob("[a-]", "-"));
+
+        // not terminated so it is only text
+        assert!(glob("[a", "[a"));
+        assert!(is_pattern(&"[a]".chars().collect::<Vec<_>>()));
+        assert!(!is_pattern(&"[a".chars().collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn hidden_files() {
+        assert!(!glob("*", ".hidden"));
+        assert!(glob(".*", ".hidden"));
+        assert!(glob(r"\.*", ".hidden"));
+    }
+
+    #[test]
+    fn expand_in_tree() {
+        let root = std::env::temp_dir().join(format!("rush-glob-{}", std::process::id()));
+        for dir in ["a/b/c", "d"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        for file in ["x.rs", "a/y.rs", "a/b/c/z.rs", "d/w.txt"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let mut escaped = String::new();
+        escape(root.to_str().unwrap(), &mut escaped);
+        let found = |pattern: &str, posix: bool| -> Vec<String> {
+            expand(&format!("{}/{}", escaped, pattern), posix).into_iter()
+                .map(|x| x[escaped.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(found("*.rs", false), ["x.rs"]);
+        assert_eq!(found("*/", false), ["a/", "d/"]);
+        assert_eq!(found("**/*.rs", false), ["a/b/c/z.rs", "a/y.rs", "x.rs"]);
+        assert_eq!(found("**/*.rs", true), ["a/y.rs"]);
+        assert!(found("*.none", false).is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,820 @@
+//! Compact IR of parsed scripts and the on-disk cache of it
+//!
+//! The syntax tree is lowered into a flat list of instructions with the
+//! strings kept once in a table, which is written to
+//! `$XDG_CACHE_HOME/rush/<hash>.ir` keyed by the FNV hash of the source, the
+//! next run of the same script reads it back instead of tokenizing and
+//! parsing it again. The source is written at the start of the file and
+//! compared when it is read so another source with the same hash is a miss
+//!
+//! Spans are kept so errors still point into the source
+//!
+//! Function bodies read from the cache are only raised into a syntax tree
+//! when the function is first called, an rc file defining lots of functions
+//! only pays for the ones that are used
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::dirs;
+use crate::error::Error;
+use crate::parser::{parse, AndOr, Ast, Assignment, Command, Connector, Function, Pipeline, Redirect, RedirectKind, Script, SimpleCommand, Word, WordPart};
+use crate::symbol::Symbol;
+use crate::tokenizer::Lexer;
+
+/// Changed whenever the encoding or what scripts parse into changes, old
+/// files are simply not found as it is part of the hash
+const FORMAT_VERSION: u32 = 5;
+
+const MAGIC: &[u8; 4] = b"RSIR";
+
+/// Smaller scripts are parsed faster than the cache file is read
+pub const MIN_CACHED_SIZE: usize = 4096;
+
+/// Single instruction, each is followed by the instructions of its children
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Followed by `len` and-or lists
+    Script(u32),
+
+    /// Followed by the first pipeline and `rest` pairs of a connector and a
+    /// pipeline
+    AndOr(u32),
+    And,
+    Or,
+
+    /// Followed by `len` commands
+    Pipeline { negated: bool, len: u32 },
+
+    /// Followed by its assignments, words and redirects
+    Simple { assignments: u32, words: u32, redirects: u32, start: u32, end: u32 },
+
+    /// Followed by the body as a script of `len` instructions
+    Function { name: u32, start: u32, end: u32, len: u32 },
+
+    /// Followed by the value
+    Assignment { name: u32 },
+
+    /// Followed by the target
+    Redirect { fd: u32, kind: RedirectKind },
+
+    /// Followed by `len` parts
+    Word { len: u32, start: u32, end: u32 },
+    Literal(u32),
+    Quoted(u32),
+    Variable { name: u32, quoted: bool },
+}
+
+/// Lowered script, strings are indices into `strings`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Program {
+    pub strings: Vec<String>,
+    pub code: Vec<Op>,
+}
+
+struct Lowering<'a> {
+    ast: &'a Ast,
+    program: Program,
+    interned: HashMap<String, u32>,
+}
+
+impl Lowering<'_> {
+    fn string(&mut self, text: &str) -> u32 {
+        if let Some(x) = self.interned.get(text) {
+            return *x;
+        }
+
+        let index = self.program.strings.len() as u32;
+        self.program.strings.push(text.to_string());
+        self.interned.insert(text.to_string(), index);
+
+        index
+    }
+
+    fn script(&mut self, script: &Script) {
+        self.program.code.push(Op::Script(script.commands.len() as u32));
+        for x in &self.ast[script.commands] {
+            self.and_or(x);
+        }
+    }
+
+    fn and_or(&mut self, and_or: &AndOr) {
+        self.program.code.push(Op::AndOr(and_or.rest.len() as u32));
+        self.pipeline(&and_or.first);
+        for (connector, pipeline) in &self.ast[and_or.rest] {
+            self.program.code.push(match connector {
+                Connector::And => Op::And,
+                Connector::Or => Op::Or,
+            });
+            self.pipeline(pipeline);
+        }
+    }
+
+    fn pipeline(&mut self, pipeline: &Pipeline) {
+        self.program.code.push(Op::Pipeline {
+            negated: pipeline.negated,
+            len: pipeline.commands.len() as u32,
+        });
+
+        for command in &self.ast[pipeline.commands] {
+            match command {
+                Command::Simple(x) => self.simple_command(x),
+                Command::Function(x) => {
+                    let name = self.string(x.name.as_str());
+                    let at = self.program.code.len();
+                    self.program.code.push(Op::Function {
+                        name,
+                        start: x.start as u32,
+                        end: x.end as u32,
+                        len: 0,
+                    });
+                    self.script(&x.body);
+
+                    let body = (self.program.code.len() - at - 1) as u32;
+                    if let Op::Function { len, .. } = &mut self.program.code[at] {
+                        *len = body;
+                    }
+                },
+            }
+        }
+    }
+
+    fn simple_command(&mut self, command: &SimpleCommand) {
+        self.program.code.push(Op::Simple {
+            assignments: command.assignments.len() as u32,
+            words: command.words.len() as u32,
+            redirects: command.redirects.len() as u32,
+            start: command.start as u32,
+            end: command.end as u32,
+        });
+
+        for x in &self.ast[command.assignments] {
+            let name = self.string(x.name.as_str());
+            self.program.code.push(Op::Assignment { name });
+            self.word(&x.value);
+        }
+
+        for x in &self.ast[command.words] {
+            self.word(x);
+        }
+
+        for x in &self.ast[command.redirects] {
+            self.program.code.push(Op::Redirect { fd: x.fd, kind: x.kind });
+            self.word(&x.target);
+        }
+    }
+
+    fn word(&mut self, word: &Word) {
+        self.program.code.push(Op::Word {
+            len: word.parts.len() as u32,
+            start: word.start as u32,
+            end: word.end as u32,
+        });
+
+        for part in &self.ast[word.parts] {
+            let op = match part {
+                WordPart::Literal(x) => Op::Literal(self.string(x)),
+                WordPart::Quoted(x) => Op::Quoted(self.string(x)),
+                WordPart::Variable { name, quoted } => Op::Variable {
+                    name: self.string(name.as_str()),
+                    quoted: *quoted,
+                },
+            };
+            self.program.code.push(op);
+        }
+    }
+}
+
+/// Lowers the syntax tree into the IR
+pub fn lower(ast: &Ast) -> Program {
+    let mut lowering = Lowering {
+        ast,
+        program: Program::default(),
+        interned: HashMap::new(),
+    };
+    lowering.script(&ast.script);
+
+    lowering.program
+}
+
+/// Turns the IR back into a syntax tree, `None` if it is not valid
+struct Raising<'a> {
+    strings: &'a [String],
+    code: &'a [Op],
+    i: usize,
+    ast: Ast,
+
+    /// Function bodies were left out of the code, they are raised from
+    /// [`Bodies`] instead
+    lazy: bool,
+}
+
+impl Raising<'_> {
+    fn next(&mut self) -> Option<&Op> {
+        let op = self.code.get(self.i)?;
+        self.i += 1;
+
+        Some(op)
+    }
+
+    fn string(&self, index: u32) -> Option<String> {
+        self.strings.get(index as usize).cloned()
+    }
+
+    fn symbol(&self, index: u32) -> Option<Symbol> {
+        self.strings.get(index as usize).map(|x| Symbol::intern(x))
+    }
+
+    fn script(&mut self) -> Option<Script> {
+        let Some(&Op::Script(len)) = self.next() else {
+            return None;
+        };
+
+        let commands = (0..len).map(|_| self.and_or()).collect::<Option<Vec<_>>>()?;
+        Some(Script {
+            commands: self.ast.alloc(commands),
+        })
+    }
+
+    fn and_or(&mut self) -> Option<AndOr> {
+        let Some(&Op::AndOr(rest)) = self.next() else {
+            return None;
+        };
+
+        let first = self.pipeline()?;
+        let rest = (0..rest)
+            .map(|_| {
+                let connector = match self.next()? {
+                    Op::And => Connector::And,
+                    Op::Or => Connector::Or,
+                    _ => return None,
+                };
+
+                Some((connector, self.pipeline()?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(AndOr {
+            first,
+            rest: self.ast.alloc(rest),
+        })
+    }
+
+    fn pipeline(&mut self) -> Option<Pipeline> {
+        let Some(&Op::Pipeline { negated, len }) = self.next() else {
+            return None;
+        };
+
+        let commands = (0..len).map(|_| self.command()).collect::<Option<Vec<_>>>()?;
+        Some(Pipeline {
+            negated,
+            commands: self.ast.alloc(commands),
+        })
+    }
+
+    fn command(&mut self) -> Option<Command> {
+        match *self.next()? {
+            Op::Simple { assignments, words, redirects, start, end } => {
+                let assignments = (0..assignments)
+                    .map(|_| {
+                        let Some(&Op::Assignment { name }) = self.next() else {
+                            return None;
+                        };
+
+                        Some(Assignment {
+                            name: self.symbol(name)?,
+                            value: self.word()?,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+
+                let words = (0..words).map(|_| self.word()).collect::<Option<Vec<_>>>()?;
+
+                let redirects = (0..redirects)
+                    .map(|_| {
+                        let Some(&Op::Redirect { fd, kind }) = self.next() else {
+                            return None;
+                        };
+
+                        Some(Redirect {
+                            fd,
+                            kind,
+                            target: self.word()?,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+
+                Some(Command::Simple(SimpleCommand {
+                    assignments: self.ast.alloc(assignments),
+                    words: self.ast.alloc(words),
+                    redirects: self.ast.alloc(redirects),
+                    start: start as usize,
+                    end: end as usize,
+                }))
+            },
+            Op::Function { name, start, end, len } => {
+                let body = match self.lazy {
+                    true => Script::default(),
+                    false => {
+                        let i = self.i;
+                        let body = self.script()?;
+                        (self.i - i == len as usize).then_some(body)?
+                    },
+                };
+
+                Some(Command::Function(Function {
+                    name: self.symbol(name)?,
+                    body,
+                    start: start as usize,
+                    end: end as usize,
+                }))
+            },
+            _ => None,
+        }
+    }
+
+    fn word(&mut self) -> Option<Word> {
+        let Some(&Op::Word { len, start, end }) = self.next() else {
+            return None;
+        };
+
+        let parts = (0..len)
+            .map(|_| match *self.next()? {
+                Op::Literal(x) => Some(WordPart::Literal(self.string(x)?)),
+                Op::Quoted(x) => Some(WordPart::Quoted(self.string(x)?)),
+                Op::Variable { name, quoted } => Some(WordPart::Variable {
+                    name: self.symbol(name)?,
+                    quoted,
+                }),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Word {
+            parts: self.ast.alloc(parts),
+            start: start as usize,
+            end: end as usize,
+        })
+    }
+}
+
+/// Turns the IR back into the syntax tree the shell runs, `None` if it is
+/// not a valid program
+pub fn raise(program: &Program) -> Option<Ast> {
+    let mut raising = Raising {
+        strings: &program.strings,
+        code: &program.code,
+        i: 0,
+        ast: Ast::default(),
+        lazy: false,
+    };
+    raising.ast.script = raising.script()?;
+
+    // trailing instructions mean it is corrupted
+    (raising.i == program.code.len()).then_some(raising.ast)
+}
+
+/// Bodies of the functions of a cache file, each is decoded and raised only
+/// when it is needed
+#[derive(Debug, Default)]
+pub struct Bodies {
+    strings: Vec<String>,
+    bytes: Vec<u8>,
+
+    /// Where each body starts in the bytes and its number of instructions,
+    /// by the start of the function
+    code: HashMap<usize, (usize, u32)>,
+}
+
+impl Bodies {
+    /// Whether the body of the function starting at `start` is in here
+    pub fn contains(&self, start: usize) -> bool {
+        self.code.contains_key(&start)
+    }
+
+    /// Syntax tree with the body of the function starting at `start` as its
+    /// script, `None` if there is no such body or it is not valid
+    pub fn raise(&self, start: usize) -> Option<Ast> {
+        let (i, len) = *self.code.get(&start)?;
+        let code = Reader { bytes: &self.bytes, i }.code(len)?;
+
+        let mut raising = Raising {
+            strings: &self.strings,
+            code: &code,
+            i: 0,
+            ast: Ast::default(),
+            lazy: false,
+        };
+        raising.ast.script = raising.script()?;
+
+        (raising.i == code.len()).then_some(raising.ast)
+    }
+}
+
+/// Reads the binary form straight into a syntax tree without the bodies of
+/// the functions, they are skipped and raised from the [`Bodies`] once they
+/// are called
+pub fn raise_lazy(bytes: Vec<u8>) -> Option<(Ast, Bodies)> {
+    let mut reader = Reader { bytes: &bytes, i: 0 };
+    let strings = reader.header()?;
+
+    let mut left = reader.uint()? as usize;
+    let mut code = vec![];
+    let mut bodies = HashMap::new();
+
+    while left > 0 {
+        let op = reader.op()?;
+        left -= 1;
+
+        if let Op::Function { start, len, .. } = op {
+            let size = reader.uint()? as usize;
+            bodies.insert(start as usize, (reader.i, len));
+
+            reader.i = reader.i.checked_add(size).filter(|x| *x <= bytes.len())?;
+            left = left.checked_sub(len as usize)?;
+        }
+
+        code.push(op);
+    }
+
+    if reader.i != bytes.len() {
+        return None;
+    }
+
+    let mut raising = Raising {
+        strings: &strings,
+        code: &code,
+        i: 0,
+        ast: Ast::default(),
+        lazy: true,
+    };
+    raising.ast.script = raising.script()?;
+
+    if raising.i != code.len() {
+        return None;
+    }
+
+    let ast = raising.ast;
+    Some((ast, Bodies { strings, bytes, code: bodies }))
+}
+
+fn write_uint(out: &mut Vec<u8>, mut value: u32) {
+    // LEB128, most numbers fit in a single byte
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// Cursor over the binary form
+struct Reader<'a> {
+    bytes: &'a [u8],
+    i: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.i)?;
+        self.i += 1;
+
+        Some(byte)
+    }
+
+    fn uint(&mut self) -> Option<u32> {
+        let mut value: u32 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+
+    /// Start and length
+    fn span(&mut self) -> Option<(u32, u32)> {
+        let start = self.uint()?;
+        Some((start, start.checked_add(self.uint()?)?))
+    }
+
+    fn str(&mut self) -> Option<&str> {
+        let len = self.uint()? as usize;
+        let bytes = self.bytes.get(self.i..self.i.checked_add(len)?)?;
+        self.i += len;
+
+        std::str::from_utf8(bytes).ok()
+    }
+
+    /// Magic and the strings
+    fn header(&mut self) -> Option<Vec<String>> {
+        if self.bytes.get(..MAGIC.len())? != MAGIC {
+            return None;
+        }
+
+        self.i = MAGIC.len();
+
+        let len = self.uint()?;
+        let mut strings = Vec::with_capacity(len.min(1 << 16) as usize);
+        for _ in 0..len {
+            strings.push(self.str()?.to_string());
+        }
+
+        Some(strings)
+    }
+
+    /// Single instruction, a function is followed by the size of its body in
+    /// bytes which is left to the caller
+    fn op(&mut self) -> Option<Op> {
+        let op = match self.byte()? {
+            0 => Op::Script(self.uint()?),
+            1 => Op::AndOr(self.uint()?),
+            2 => Op::And,
+            3 => Op::Or,
+            4 => Op::Pipeline { negated: self.uint()? != 0, len: self.uint()? },
+            5 => {
+                let (assignments, words, redirects) = (self.uint()?, self.uint()?, self.uint()?);
+                let (start, end) = self.span()?;
+                Op::Simple { assignments, words, redirects, start, end }
+            },
+            6 => {
+                let name = self.uint()?;
+                let (start, end) = self.span()?;
+                Op::Function { name, start, end, len: self.uint()? }
+            },
+            7 => Op::Assignment { name: self.uint()? },
+            8 => Op::Redirect {
+                fd: self.uint()?,
+                kind: match self.uint()? {
+                    0 => RedirectKind::Input,
+                    1 => RedirectKind::Output,
+                    2 => RedirectKind::Append,
+                    3 => RedirectKind::Duplicate,
+                    _ => return None,
+                },
+            },
+            9 => {
+                let len = self.uint()?;
+                let (start, end) = self.span()?;
+                Op::Word { len, start, end }
+            },
+            10 => Op::Literal(self.uint()?),
+            11 => Op::Quoted(self.uint()?),
+            12 => Op::Variable { name: self.uint()?, quoted: self.uint()? != 0 },
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    /// `len` instructions including the bodies of functions
+    fn code(&mut self, len: u32) -> Option<Vec<Op>> {
+        let mut code = Vec::with_capacity(len.min(1 << 16) as usize);
+        for _ in 0..len {
+            let op = self.op()?;
+            if let Op::Function { .. } = op {
+                self.uint()?;
+            }
+
+            code.push(op);
+        }
+
+        Some(code)
+    }
+}
+
+fn redirect_kind(kind: RedirectKind) -> u32 {
+    match kind {
+        RedirectKind::Input => 0,
+        RedirectKind::Output => 1,
+        RedirectKind::Append => 2,
+        RedirectKind::Duplicate => 3,
+    }
+}
+
+/// Writes the instructions, bodies of functions are written after their size
+/// so they can be skipped
+fn encode_code(code: &[Op], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while let Some(op) = code.get(i) {
+        i += 1;
+
+        // ends are written as lengths, they are smaller
+        let (tag, args): (u8, &[u32]) = match *op {
+            Op::Script(len) => (0, &[len]),
+            Op::AndOr(rest) => (1, &[rest]),
+            Op::And => (2, &[]),
+            Op::Or => (3, &[]),
+            Op::Pipeline { negated, len } => (4, &[negated as u32, len]),
+            Op::Simple { assignments, words, redirects, start, end } => (5, &[assignments, words, redirects, start, end - start]),
+            Op::Function { name, start, end, len } => {
+                let end_of_body = (i + len as usize).min(code.len());
+                let mut body = vec![];
+                encode_code(&code[i..end_of_body], &mut body);
+                i = end_of_body;
+
+                out.push(6);
+                for x in [name, start, end - start, len, body.len() as u32] {
+                    write_uint(out, x);
+                }
+
+                out.extend(body);
+                continue;
+            },
+            Op::Assignment { name } => (7, &[name]),
+            Op::Redirect { fd, kind } => (8, &[fd, redirect_kind(kind)]),
+            Op::Word { len, start, end } => (9, &[len, start, end - start]),
+            Op::Literal(x) => (10, &[x]),
+            Op::Quoted(x) => (11, &[x]),
+            Op::Variable { name, quoted } => (12, &[name, quoted as u32]),
+        };
+
+        out.push(tag);
+        for x in args {
+            write_uint(out, *x);
+        }
+    }
+}
+
+impl Program {
+    /// Binary form written to the cache
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+
+        write_uint(&mut out, self.strings.len() as u32);
+        for x in &self.strings {
+            write_uint(&mut out, x.len() as u32);
+            out.extend_from_slice(x.as_bytes());
+        }
+
+        write_uint(&mut out, self.code.len() as u32);
+        encode_code(&self.code, &mut out);
+
+        out
+    }
+
+    /// Reads the binary form, `None` if it is not valid
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes, i: 0 };
+        let strings = reader.header()?;
+
+        let len = reader.uint()?;
+        let code = reader.code(len)?;
+
+        (reader.i == bytes.len()).then_some(Self { strings, code })
+    }
+}
+
+/// 64-bit FNV-1a
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for x in bytes {
+        hash ^= *x as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+/// Key of the cache file of the source, the mode and the version of the
+/// shell are part of it as they change what the source parses into
+fn cache_key(source: &str, posix: bool) -> String {
+    format!("{}\0{}\0{}\0{}", env!("CARGO_PKG_VERSION"), FORMAT_VERSION, posix, source)
+}
+
+fn cache_file(key: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|x| x.join(format!("{:016x}.ir", fnv1a(key.as_bytes()))))
+}
+
+/// Contents of the cache file, the key followed by the binary form
+fn keyed(key: &str, program: &Program) -> Vec<u8> {
+    let mut out = vec![];
+    write_uint(&mut out, key.len() as u32);
+    out.extend_from_slice(key.as_bytes());
+    out.extend(program.encode());
+
+    out
+}
+
+/// Binary form in the cache file, `None` if the file is for another key
+fn unkeyed(mut bytes: Vec<u8>, key: &str) -> Option<Vec<u8>> {
+    let mut reader = Reader { bytes: &bytes, i: 0 };
+    let len = reader.uint()? as usize;
+    let start = reader.i;
+    let end = start.checked_add(len)?;
+
+    if bytes.get(start..end)? != key.as_bytes() {
+        return None;
+    }
+
+    bytes.drain(..end);
+    Some(bytes)
+}
+
+/// Parses the source, reading it from the cache when it was parsed before,
+/// sources smaller than [`MIN_CACHED_SIZE`] are always parsed
+///
+/// Function bodies are only in the [`Bodies`] when it was read from the cache
+pub fn load(source: &str, posix: bool) -> Result<(Ast, Bodies), Error> {
+    let key = cache_key(source, posix);
+    let file = cache_file(&key).filter(|_| source.len() >= MIN_CACHED_SIZE);
+
+    let cached = file.as_ref()
+        .and_then(|x| std::fs::read(x).ok())
+        .and_then(|x| unkeyed(x, &key))
+        .and_then(raise_lazy);
+
+    if let Some(x) = cached {
+        return Ok(x);
+    }
+
+    let ast = parse(Lexer::new(Rc::new(source.to_string()), posix))?;
+
+    if let Some(file) = file {
+        // the cache is only an optimization so failing to write it is fine,
+        // written to a temporary file first so other shells never read half
+        // of it
+        let temporary = file.with_extension(format!("{}.tmp", std::process::id()));
+        let written = file.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&temporary, keyed(&key, &lower(&ast))))
+            .and_then(|_| std::fs::rename(&temporary, &file));
+
+        if written.is_err() {
+            let _ = std::fs::remove_file(&temporary);
+        }
+    }
+
+    Ok((ast, Bodies::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "A=1 echo \"$A\" 'b' >>out 2>&1 | cat\n! f || g && h\nf() {\n  echo $1 ${2}\n}\n";
+
+    fn program(source: &str) -> Program {
+        lower(&parse(Lexer::new(Rc::new(source.to_string()), false)).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = program(SCRIPT);
+        let decoded = Program::decode(&program.encode()).unwrap();
+        assert_eq!(decoded, program);
+
+        // raising and lowering again gives the same program
+        assert_eq!(lower(&raise(&decoded).unwrap()), program);
+    }
+
+    #[test]
+    fn lazy_bodies() {
+        let (ast, bodies) = raise_lazy(program(SCRIPT).encode()).unwrap();
+        let start = SCRIPT.find("f()").unwrap();
+        assert!(bodies.contains(start));
+        assert!(!bodies.contains(0));
+
+        let body = bodies.raise(start).unwrap();
+        assert_eq!(body.script.commands.len(), 1);
+        assert_eq!(ast.script.commands.len(), 3);
+    }
+
+    #[test]
+    fn corrupted() {
+        let bytes = program(SCRIPT).encode();
+        assert!(Program::decode(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Program::decode(&[&bytes[..], &[0]].concat()).is_none());
+        assert!(Program::decode(b"RSIX").is_none());
+        assert!(raise_lazy(bytes[..bytes.len() / 2].to_vec()).is_none());
+    }
+
+    #[test]
+    fn cache_is_for_its_key() {
+        let program = program(SCRIPT);
+        let key = cache_key(SCRIPT, false);
+        let bytes = keyed(&key, &program);
+
+        assert_eq!(unkeyed(bytes.clone(), &key), Some(program.encode()));
+        assert_eq!(unkeyed(bytes.clone(), &cache_key(SCRIPT, true)), None);
+        assert_eq!(unkeyed(bytes.clone(), &cache_key("echo other", false)), None);
+        assert_eq!(unkeyed(bytes[..key.len() / 2].to_vec(), &key), None);
+    }
+
+    #[test]
+    fn leb128() {
+        for value in [0, 1, 127, 128, 300, u32::MAX] {
+            let mut out = vec![];
+            write_uint(&mut out, value);
+            assert_eq!(Reader { bytes: &out, i: 0 }.uint(), Some(value));
+        }
+    }
+}
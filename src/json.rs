@@ -0,0 +1,412 @@
+//! Minimal JSON values, used for machine readable output and the language
+//! server
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+
+    /// Keys in the order they were added
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Object from key value pairs
+    pub fn object<const N: usize>(pairs: [(&str, Json); N]) -> Json {
+        Json::Object(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    /// Parses the whole string, returns the error with its byte index
+    pub fn parse(source: &str) -> Result<Json, (String, usize)> {
+        let mut parser = Parser { source, pos: 0, depth: 0 };
+        let value = parser.value()?;
+
+        parser.skip_whitespace();
+        if parser.pos < source.len() {
+            return parser.error("trailing characters");
+        }
+
+        Ok(value)
+    }
+
+    /// Value of the key if this is an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Number if it is a non negative integer
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64().filter(|x| x.fract() == 0.0 && *x >= 0.0).map(|x| x as usize)
+    }
+}
+
+/// Arrays and objects nested deeper than this are an error
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    source: &'a str,
+
+    /// Byte index of the next character
+    pos: usize,
+
+    /// Arrays and objects the parser is in
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: &str) -> Result<T, (String, usize)> {
+        Err((message.to_string(), self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(ch @ (' ' | '\t' | '\n' | '\r')) = self.peek() {
+            self.pos += ch.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), (String, usize)> {
+        if !self.source[self.pos..].starts_with(text) {
+            return self.error(&format!("expected '{}'", text));
+        }
+
+        self.pos += text.len();
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, (String, usize)> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+
+            Some('[' | '{') => {
+                // recursion could overflow the stack otherwise
+                if self.depth == MAX_DEPTH {
+                    return self.error("nested too deeply");
+                }
+
+                self.depth += 1;
+                let value = match self.peek() {
+                    Some('[') => self.array(),
+                    _ => self.object(),
+                };
+                self.depth -= 1;
+
+                value
+            },
+
+            Some(_) => self.error("expected a value"),
+            None => self.error("unexpected end"),
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, (String, usize)> {
+        self.pos += 1;
+        let mut items = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                },
+                _ => return self.error("expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, (String, usize)> {
+        self.pos += 1;
+        let mut pairs = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(pairs));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return self.error("expected a key");
+            }
+
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(":")?;
+            pairs.push((key, self.value()?));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(pairs));
+                },
+                _ => return self.error("expected ',' or '}'"),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, (String, usize)> {
+        let start = self.pos;
+        while let Some(ch @ ('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) = self.peek() {
+            self.pos += ch.len_utf8();
+        }
+
+        match self.source[start..self.pos].parse::<f64>() {
+            Ok(x) => Ok(Json::Number(x)),
+            Err(_) => Err(("invalid number".to_string(), start)),
+        }
+    }
+
+    /// Four hex digits of `\u` escape
+    fn hex(&mut self) -> Result<u32, (String, usize)> {
+        // from_str_radix would also take a sign
+        let digits = self.source.get(self.pos..self.pos + 4).filter(|x| x.chars().all(|x| x.is_ascii_hexdigit()));
+        match digits.and_then(|x| u32::from_str_radix(x, 16).ok()) {
+            Some(x) => {
+                self.pos += 4;
+                Ok(x)
+            },
+            None => self.error("invalid unicode escape"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, (String, usize)> {
+        self.pos += 1;
+        let mut string = String::new();
+
+        loop {
+            let Some(ch) = self.peek() else {
+                return self.error("unterminated string");
+            };
+
+            self.pos += ch.len_utf8();
+            match ch {
+                '"' => return Ok(string),
+                '\\' => {
+                    let Some(escape) = self.peek() else {
+                        return self.error("unterminated string");
+                    };
+
+                    self.pos += escape.len_utf8();
+                    match escape {
+                        '"' | '\\' | '/' => string.push(escape),
+                        'b' => string.push('\u{8}'),
+                        'f' => string.push('\u{c}'),
+                        'n' => string.push('\n'),
+                        'r' => string.push('\r'),
+                        't' => string.push('\t'),
+                        'u' => {
+                            let mut code = self.hex()?;
+
+                            // surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.source[self.pos..].starts_with("\\u") {
+                                self.pos += 2;
+                                let low = self.hex()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+
+                            string.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                        },
+                        _ => return self.error("invalid escape"),
+                    }
+                },
+                _ => string.push(ch),
+            }
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(x: &str) -> Json {
+        Json::String(x.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(x: String) -> Json {
+        Json::String(x)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(x: usize) -> Json {
+        Json::Number(x as f64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(x: i32) -> Json {
+        Json::Number(x as f64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Json {
+        Json::Number(x)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(x: bool) -> Json {
+        Json::Bool(x)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, x: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in x.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            x if (x as u32) < 0x20 => write!(f, "\\u{:04x}", x as u32)?,
+            x => write!(f, "{}", x)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Compact JSON on a single line
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(x) => write!(f, "{}", x),
+            Json::Number(x) if x.is_finite() => write!(f, "{}", x),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(x) => write_string(f, x),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        Json::parse(source).unwrap_err().0
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(Json::parse(r#""a\"b\\c\/d\n\t""#), Ok("a\"b\\c/d\n\t".into()));
+        assert_eq!(Json::parse(r#""\u0041\u00E9""#), Ok("Aé".into()));
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#), Ok("😀".into()));
+
+        // lone surrogates can not be a char
+        assert_eq!(Json::parse(r#""\ud83d""#), Ok("\u{fffd}".into()));
+
+        assert_eq!(error(r#""\u+041""#), "invalid unicode escape");
+        assert_eq!(error(r#""\u12""#), "invalid unicode escape");
+        assert_eq!(error(r#""\x""#), "invalid escape");
+        assert_eq!(error(r#""abc"#), "unterminated string");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(Json::parse("42"), Ok(Json::Number(42.0)));
+        assert_eq!(Json::parse("-1.5e2"), Ok(Json::Number(-150.0)));
+        assert_eq!(Json::parse("3").unwrap().as_usize(), Some(3));
+        assert_eq!(Json::parse("3.5").unwrap().as_usize(), None);
+        assert_eq!(error("1-"), "invalid number");
+        assert_eq!(error("-"), "invalid number");
+    }
+
+    #[test]
+    fn nesting() {
+        let value = Json::parse(r#" {"a": [1, {"b": null}, true], "c": {}} "#).unwrap();
+        assert_eq!(value, Json::object([
+            ("a", Json::Array(vec![1.into(), Json::object([("b", Json::Null)]), true.into()])),
+            ("c", Json::Object(vec![])),
+        ]));
+        assert_eq!(value.to_string(), r#"{"a":[1,{"b":null},true],"c":{}}"#);
+
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(error(&nested(MAX_DEPTH + 1)), "nested too deeply");
+        assert_eq!(error(&"[".repeat(100_000)), "nested too deeply");
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(Json::parse(""), Err(("unexpected end".to_string(), 0)));
+        assert_eq!(Json::parse("[1,]"), Err(("expected a value".to_string(), 3)));
+        assert_eq!(error("[1 2]"), "expected ',' or ']'");
+        assert_eq!(error("{1: 2}"), "expected a key");
+        assert_eq!(error(r#"{"a" 1}"#), "expected ':'");
+        assert_eq!(error(r#"{"a": 1"#), "expected ',' or '}'");
+        assert_eq!(error("nul"), "expected 'null'");
+        assert_eq!(error("1 2"), "trailing characters");
+    }
+
+    #[test]
+    fn escaped_when_written() {
+        assert_eq!(Json::from("a\"b\\\n\u{1}").to_string(), r#""a\"b\\\n\u0001""#);
+    }
+}
@@ -0,0 +1,35 @@
+//! Rush shell language, can be embedded in other programs
+//!
+//! The source is turned into tokens by a [`Lexer`], into commands by
+//! [`parse`] as they are read and then run by [`Shell`]
+
+pub mod alias;
+pub mod builtins;
+pub mod config;
+pub mod debugger;
+pub mod diagnostic;
+pub mod dirs;
+pub mod error;
+pub mod exec;
+pub mod features;
+pub mod fmt;
+pub mod glob;
+pub mod ir;
+pub mod json;
+pub mod lint;
+#[cfg(not(target_family = "wasm"))]
+pub mod lsp;
+pub mod parser;
+pub mod playground;
+pub mod plugins;
+pub mod profiler;
+pub mod symbol;
+pub mod table;
+pub mod tokenizer;
+pub mod toml;
+
+pub use crate::builtins::{Builtin, Context};
+pub use crate::error::Error;
+pub use crate::exec::{ExecResult, Shell};
+pub use crate::parser::parse;
+pub use crate::tokenizer::{tokenize, Lexer};
@@ -0,0 +1,277 @@
+//! Linter catching common mistakes in scripts
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::diagnostic::Severity;
+use crate::json::Json;
+use crate::parser::{parse_tokens, Ast, Command, Pipeline, Script, SimpleCommand, Word, WordPart};
+use crate::symbol::Symbol;
+use crate::tokenizer::{line_col, Lexer, Token};
+
+/// Single finding of the linter
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub severity: Severity,
+
+    /// Short name of the check
+    pub code: &'static str,
+
+    pub message: String,
+
+    /// Byte index of start of the offending code
+    pub start: usize,
+
+    /// Byte index of end of the offending code
+    pub end: usize,
+}
+
+impl Lint {
+    /// `file:line:col: severity: message [code]`
+    pub fn display(&self, file: &str, source: &str) -> String {
+        let (line, col) = line_col(source, self.start);
+        format!("{}:{}:{}: {}: {} [{}]", file, line, col, self.severity.as_str(), self.message, self.code)
+    }
+
+    pub fn to_json(&self, file: &str, source: &str) -> Json {
+        let (line, column) = line_col(source, self.start);
+        let (end_line, end_column) = line_col(source, self.end);
+
+        Json::object([
+            ("file", file.into()),
+            ("line", line.into()),
+            ("column", column.into()),
+            ("end_line", end_line.into()),
+            ("end_column", end_column.into()),
+            ("severity", self.severity.as_str().into()),
+            ("code", self.code.into()),
+            ("message", self.message.as_str().into()),
+        ])
+    }
+}
+
+/// Runs all checks, `posix` enables checks for POSIX portability, which is
+/// also enabled by a `sh` shebang
+pub fn lint(source: &str, posix: bool) -> Vec<Lint> {
+    let posix = posix || source.lines().next().is_some_and(|x| x.starts_with("#!") && x.trim_end().ends_with("/sh"));
+
+    let mut lints = vec![];
+
+    // the parser does not support command substitution, backticks are
+    // reported here and replaced with an empty quoted string to lint the rest
+    let tokens = Lexer::new(Rc::new(source.to_string()), posix)
+        .map(|token| {
+            let mut token = token?;
+            if let Token::String(_, '`') = token.token {
+                lints.push(Lint {
+                    severity: Severity::Error,
+                    code: "syntax",
+                    message: "command substitution is not supported yet".to_string(),
+                    start: token.start,
+                    end: token.end,
+                });
+
+                token.token = Token::String("\"\"".to_string(), '"');
+            }
+
+            Ok(token)
+        });
+
+    match parse_tokens(tokens, posix) {
+        Ok(ast) => {
+            let mut linter = Linter {
+                ast: &ast,
+                posix,
+                assigned: HashSet::new(),
+                lints,
+            };
+
+            linter.script(&ast.script);
+            lints = linter.lints;
+        },
+
+        Err(err) => lints.push(Lint {
+            severity: Severity::Error,
+            code: "syntax",
+            message: err.message,
+            start: err.start,
+            end: err.end,
+        }),
+    }
+
+    lints.sort_by_key(|x| x.start);
+    lints
+}
+
+struct Linter<'a> {
+    ast: &'a Ast,
+    posix: bool,
+
+    /// Variables assigned so far
+    assigned: HashSet<Symbol>,
+
+    lints: Vec<Lint>,
+}
+
+impl Linter<'_> {
+    fn warn(&mut self, code: &'static str, message: String, start: usize, end: usize) {
+        self.lints.push(Lint {
+            severity: Severity::Warning,
+            code,
+            message,
+            start,
+            end,
+        });
+    }
+
+    fn script(&mut self, script: &Script) {
+        let ast = self.ast;
+        for and_or in &ast[script.commands] {
+            self.pipeline(&and_or.first);
+            for (_, pipeline) in &ast[and_or.rest] {
+                self.pipeline(pipeline);
+            }
+        }
+    }
+
+    fn pipeline(&mut self, pipeline: &Pipeline) {
+        let ast = self.ast;
+
+        // cat file | command
+        if let [Command::Simple(first), _, ..] = &ast[pipeline.commands] {
+            if let [cat, file] = &ast[first.words] {
+                let is_file = file.literal(ast).is_none_or(|x| !x.starts_with('-'));
+                if cat.literal(ast) == Some("cat") && is_file && first.redirects.is_empty() {
+                    self.warn(
+                        "useless-cat",
+                        format!("useless cat, redirect the file into the next command with '< {}'", file.text(ast)),
+                        first.start,
+                        first.end,
+                    );
+                }
+            }
+        }
+
+        for command in &ast[pipeline.commands] {
+            match command {
+                Command::Simple(x) => self.command(x),
+                Command::Function(x) => self.script(&x.body),
+            }
+        }
+    }
+
+    fn command(&mut self, command: &SimpleCommand) {
+        let ast = self.ast;
+        let words = &ast[command.words];
+
+        for assignment in &ast[command.assignments] {
+            self.word(&assignment.value, false);
+        }
+
+        // prefix assignments only apply to the command
+        if words.is_empty() {
+            for assignment in &ast[command.assignments] {
+                self.assigned.insert(assignment.name);
+            }
+        }
+
+        for word in words {
+            self.word(word, true);
+        }
+
+        for redirect in &ast[command.redirects] {
+            self.word(&redirect.target, true);
+        }
+
+        let name = words.first().and_then(|x| x.literal(ast));
+
+        // [ x == y ]
+        if self.posix && matches!(name, Some("[" | "test")) {
+            for word in &words[1..] {
+                if word.literal(ast) == Some("==") {
+                    self.warn("posix-test-equals", "'==' is not POSIX in test, use '='".to_string(), word.start, word.end);
+                }
+            }
+        }
+
+        // export and read assign variables too
+        if matches!(name, Some("export" | "read")) {
+            for word in &words[1..] {
+                let text = word.text(ast);
+                let name = text.split('=').next().unwrap_or_default();
+                self.assigned.insert(Symbol::intern(name));
+            }
+        }
+    }
+
+    /// `split` is set if the word undergoes field splitting
+    fn word(&mut self, word: &Word, split: bool) {
+        for part in &self.ast[word.parts] {
+            let WordPart::Variable { name, quoted } = part else {
+                continue;
+            };
+
+            let special = name.as_str().chars().all(|x| !x.is_ascii_alphabetic() && x != '_');
+
+            // only the special parameters that are always numbers or flags
+            // are safe unquoted, positional ones are split like any other
+            let safe = matches!(name.as_str(), "?" | "#" | "$" | "!" | "-");
+
+            if split && !*quoted && !safe {
+                self.warn(
+                    "unquoted-expansion",
+                    format!("unquoted ${}, use \"${}\" to prevent word splitting", name, name),
+                    word.start,
+                    word.end,
+                );
+            }
+
+            // uppercase ones are usually from the environment
+            let lowercase = name.as_str().chars().any(|x| x.is_ascii_lowercase());
+            if !special && lowercase && !self.assigned.contains(name) {
+                self.warn("unset-variable", format!("${} is used but never assigned", name), word.start, word.end);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(source: &str) -> Vec<&'static str> {
+        lint(source, false).into_iter().map(|x| x.code).collect()
+    }
+
+    #[test]
+    fn unquoted_expansions() {
+        assert_eq!(codes("x=1\necho $x"), ["unquoted-expansion"]);
+        assert_eq!(codes("echo $1 $@ $*"), ["unquoted-expansion"; 3]);
+        assert!(codes("x=1\necho \"$x\" \"$@\" $? $#").is_empty());
+
+        // assignments are not split
+        assert!(codes("x=1\ny=$x").is_empty());
+    }
+
+    #[test]
+    fn unset_variables() {
+        assert_eq!(codes("echo \"$x\""), ["unset-variable"]);
+        assert!(codes("export x=1\necho \"$x\" \"$HOME\" \"$1\"").is_empty());
+    }
+
+    #[test]
+    fn backticks_are_unsupported() {
+        let lints = lint("x=`pwd`\necho \"$y\"", false);
+        assert_eq!(lints.len(), 2);
+        assert!(matches!(lints[0].severity, Severity::Error));
+        assert_eq!(lints[0].message, "command substitution is not supported yet");
+        assert_eq!(lints[1].code, "unset-variable");
+    }
+
+    #[test]
+    fn posix_checks() {
+        assert!(codes("[ a == b ]").is_empty());
+        assert_eq!(codes("#!/bin/sh\n[ a == b ]"), ["posix-test-equals"]);
+        assert_eq!(codes("cat file | grep x"), ["useless-cat"]);
+    }
+}
@@ -0,0 +1,567 @@
+//! Language server, speaks the Language Server Protocol on stdin and stdout
+//!
+//! Documents are synced in full, the server publishes diagnostics of the
+//! linter, lists functions as document symbols, completes variables,
+//! commands and the arguments of builtins and shows the help of builtins on
+//! hover
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use crate::exec::{is_executable, Shell, DEFAULT_PATH};
+use crate::json::Json;
+use crate::diagnostic::Severity;
+use crate::lint::lint;
+use crate::parser::{is_name, parse, Ast, Command, Function, Script, SimpleCommand};
+use crate::plugins;
+use crate::tokenizer::Lexer;
+
+/// Reads a message, returns `None` at the end of input
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"));
+    };
+
+    let mut buffer = vec![0; length];
+    reader.read_exact(&mut buffer)?;
+
+    Ok(Some(String::from_utf8_lossy(&buffer).into_owned()))
+}
+
+fn write_message(writer: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// LSP position of byte index, the column is in UTF-16 code units
+fn position(source: &str, index: usize) -> Json {
+    let before = &source[..index.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+
+    Json::object([
+        ("line", before.matches('\n').count().into()),
+        ("character", before[line_start..].encode_utf16().count().into()),
+    ])
+}
+
+fn range(source: &str, start: usize, end: usize) -> Json {
+    Json::object([
+        ("start", position(source, start)),
+        ("end", position(source, end)),
+    ])
+}
+
+/// Byte index of LSP position, clamped to the line
+fn offset(source: &str, position: &Json) -> usize {
+    let line = position.get("line").and_then(|x| x.as_usize()).unwrap_or(0);
+    let character = position.get("character").and_then(|x| x.as_usize()).unwrap_or(0);
+
+    let mut line_start = 0;
+    for _ in 0..line {
+        match source[line_start..].find('\n') {
+            Some(x) => line_start += x + 1,
+            None => return source.len(),
+        }
+    }
+
+    let mut units = 0;
+    for (i, ch) in source[line_start..].char_indices() {
+        if ch == '\n' || units >= character {
+            return line_start + i;
+        }
+
+        units += ch.len_utf16();
+    }
+
+    source.len()
+}
+
+fn parse_document(source: &str) -> Option<Ast> {
+    parse(Lexer::new(Rc::new(source.to_string()), false)).ok()
+}
+
+/// Commands of the script, without the ones in functions
+fn commands<'a>(ast: &'a Ast, script: &Script) -> impl Iterator<Item = &'a Command> {
+    ast[script.commands].iter()
+        .flat_map(|x| std::iter::once(&x.first).chain(ast[x.rest].iter().map(|(_, x)| x)))
+        .flat_map(|x| &ast[x.commands])
+}
+
+/// Calls `f` with every command in the script, including the ones in
+/// functions
+fn visit<'a>(ast: &'a Ast, script: &Script, f: &mut impl FnMut(&'a Command)) {
+    for command in commands(ast, script) {
+        f(command);
+        if let Command::Function(function) = command {
+            visit(ast, &function.body, f);
+        }
+    }
+}
+
+/// Symbols of the functions defined in the script
+fn function_symbols(source: &str, ast: &Ast, script: &Script) -> Vec<Json> {
+    commands(ast, script)
+        .filter_map(|x| match x {
+            Command::Function(x) => Some(function_symbol(source, ast, x)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Document symbol of the function with the nested functions as children
+fn function_symbol(source: &str, ast: &Ast, function: &Function) -> Json {
+    let children = function_symbols(source, ast, &function.body);
+
+    Json::object([
+        ("name", function.name.as_str().into()),
+        // Function
+        ("kind", 12.into()),
+        ("range", range(source, function.start, function.end)),
+        ("selectionRange", range(source, function.start, function.start + function.name.as_str().len())),
+        ("children", Json::Array(children)),
+    ])
+}
+
+/// Characters that end a word when looking around the cursor
+fn is_word_boundary(ch: char) -> bool {
+    ch.is_whitespace() || matches!(ch, ';' | '|' | '&' | '<' | '>' | '(' | ')' | '"' | '\'' | '`')
+}
+
+/// Words of the command before the one at `start`, a rough split on blanks
+/// from the last character that starts a command, assignments are skipped
+fn command_words(source: &str, start: usize) -> Vec<&str> {
+    let before = &source[..start];
+    let command = before.rfind([';', '|', '&', '\n', '(']).map_or(0, |x| x + 1);
+
+    before[command..].split_whitespace()
+        .skip_while(|x| x.split_once('=').is_some_and(|(name, _)| is_name(name)))
+        .collect()
+}
+
+fn completion_item(label: &str, kind: i32, detail: &str) -> Json {
+    Json::object([
+        ("label", label.into()),
+        ("kind", kind.into()),
+        ("detail", detail.into()),
+    ])
+}
+
+struct Server {
+    /// Text of the open documents by URI
+    documents: HashMap<String, String>,
+
+    /// Used for the builtins and the environment
+    shell: Shell,
+
+    /// Executables in `PATH`, found on first completion
+    commands: Option<Vec<String>>,
+
+    /// Got the shutdown request
+    shutdown: bool,
+}
+
+impl Server {
+    /// Text of the document the request is for
+    fn document(&self, params: &Json) -> Option<&str> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        self.documents.get(uri).map(|x| x.as_str())
+    }
+
+    fn diagnostics(&self, uri: &str) -> Json {
+        let source = self.documents.get(uri).map(|x| x.as_str()).unwrap_or_default();
+        let diagnostics = lint(source, false).into_iter()
+            .map(|x| Json::object([
+                ("range", range(source, x.start, x.end)),
+                ("severity", match x.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                }.into()),
+                ("code", x.code.into()),
+                ("source", "rush".into()),
+                ("message", x.message.into()),
+            ]))
+            .collect();
+
+        Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", Json::object([
+                ("uri", uri.into()),
+                ("diagnostics", Json::Array(diagnostics)),
+            ])),
+        ])
+    }
+
+    fn commands(&mut self) -> &[String] {
+        self.commands.get_or_insert_with(|| {
+            let paths = self.shell.var("PATH").unwrap_or_else(|| DEFAULT_PATH.to_string());
+            let mut commands: Vec<String> = std::env::split_paths(&paths)
+                .filter_map(|x| std::fs::read_dir(x).ok())
+                .flatten()
+                .filter_map(|x| x.ok().map(|x| x.path()))
+                .filter(|x| is_executable(x))
+                .filter_map(|x| x.file_name()?.to_str().map(|x| x.to_string()))
+                .collect();
+
+            commands.sort();
+            commands.dedup();
+
+            commands
+        })
+    }
+
+    fn completion(&mut self, params: &Json) -> Json {
+        let Some(source) = self.document(params) else {
+            return Json::Array(vec![]);
+        };
+
+        let source = source.to_string();
+        let index = offset(&source, params.get("position").unwrap_or(&Json::Null));
+        let start = source[..index].rfind(is_word_boundary).map_or(0, |x| x + 1);
+        let prefix = &source[start..index];
+        let ast = parse_document(&source);
+
+        let words = command_words(&source, start);
+
+        let mut items = vec![];
+        if let Some(typed) = prefix.strip_prefix('$') {
+            let (typed, braced) = match typed.strip_prefix('{') {
+                Some(x) => (x, true),
+                None => (typed, false),
+            };
+
+            let mut names: Vec<String> = self.shell.vars.keys().map(|x| x.to_string()).collect();
+            if let Some(ast) = &ast {
+                visit(ast, &ast.script, &mut |x| {
+                    if let Command::Simple(x) = x {
+                        names.extend(ast[x.assignments].iter().map(|x| x.name.to_string()));
+                    }
+                });
+            }
+
+            names.sort();
+            names.dedup();
+
+            // the word is replaced so the text includes the `$`
+            items.extend(names.iter().filter(|x| x.starts_with(typed)).map(|x| {
+                let text = match braced {
+                    true => format!("${{{}}}", x),
+                    false => format!("${}", x),
+                };
+
+                Json::object([
+                    ("label", text.as_str().into()),
+                    // Variable
+                    ("kind", 6.into()),
+                    ("detail", "variable".into()),
+                    ("insertText", text.into()),
+                ])
+            }));
+        } else if let Some((name, args)) = words.split_first() {
+            // arguments, only builtins know what they take
+            let args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+            if let Some(builtin) = self.shell.builtins().get(name) {
+                let candidates = builtin.complete(&self.shell, &args);
+
+                // Value
+                items.extend(candidates.iter()
+                    .filter(|x| x.starts_with(prefix))
+                    .map(|x| completion_item(x, 12, name)));
+            }
+        } else {
+            let mut functions = vec![];
+            if let Some(ast) = &ast {
+                visit(ast, &ast.script, &mut |x| {
+                    if let Command::Function(x) = x {
+                        functions.push(x.name);
+                    }
+                });
+            }
+
+            let mut builtins: Vec<&str> = self.shell.builtins().names().filter(|x| x.starts_with(prefix)).collect();
+            builtins.sort();
+
+            // Function
+            items.extend(functions.iter().filter(|x| x.as_str().starts_with(prefix)).map(|x| completion_item(x.as_str(), 3, "function")));
+            items.extend(builtins.iter().map(|x| completion_item(x, 3, "builtin")));
+            items.extend(self.commands().iter().filter(|x| x.starts_with(prefix)).map(|x| completion_item(x, 3, "command")));
+        }
+
+        Json::Array(items)
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some(source) = self.document(params) else {
+            return Json::Null;
+        };
+
+        let index = offset(source, params.get("position").unwrap_or(&Json::Null));
+        let Some(ast) = parse_document(source) else {
+            return Json::Null;
+        };
+
+        // only the command name
+        let mut found: Option<&SimpleCommand> = None;
+        visit(&ast, &ast.script, &mut |x| {
+            if let Command::Simple(x) = x {
+                if ast[x.words].first().is_some_and(|x| x.start <= index && index <= x.end) {
+                    found = Some(x);
+                }
+            }
+        });
+
+        let Some(word) = found.and_then(|x| ast[x.words].first()) else {
+            return Json::Null;
+        };
+
+        let Some(builtin) = word.literal(&ast).and_then(|x| self.shell.builtins().get(x)) else {
+            return Json::Null;
+        };
+
+        let help = builtin.help();
+        let (usage, description) = help.split_once("\n\n").unwrap_or((help, ""));
+        let usage = if usage.is_empty() { builtin.name() } else { usage };
+
+        Json::object([
+            ("contents", Json::object([
+                ("kind", "markdown".into()),
+                ("value", format!("```sh\n{}\n```\n\n{}\n\n*builtin*", usage, description).into()),
+            ])),
+            ("range", range(source, word.start, word.end)),
+        ])
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let Some(source) = self.document(params) else {
+            return Json::Array(vec![]);
+        };
+
+        match parse_document(source) {
+            Some(ast) => Json::Array(function_symbols(source, &ast, &ast.script)),
+            None => Json::Array(vec![]),
+        }
+    }
+
+    /// Handles the request, returns the result or the error code with the
+    /// message
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i32, String)> {
+        match method {
+            "initialize" => Ok(Json::object([
+                ("capabilities", Json::object([
+                    // full sync
+                    ("textDocumentSync", 1.into()),
+                    ("documentSymbolProvider", true.into()),
+                    ("hoverProvider", true.into()),
+                    ("completionProvider", Json::object([
+                        ("triggerCharacters", Json::Array(vec!["$".into()])),
+                    ])),
+                ])),
+                ("serverInfo", Json::object([
+                    ("name", "rush".into()),
+                    ("version", env!("CARGO_PKG_VERSION").into()),
+                ])),
+            ])),
+
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            },
+
+            "textDocument/completion" => Ok(self.completion(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+
+            // MethodNotFound
+            _ => Err((-32601, format!("{}: method not found", method))),
+        }
+    }
+
+    /// Handles the notification, returns the messages to send
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let document = params.get("textDocument");
+        let Some(uri) = document.and_then(|x| x.get("uri")).and_then(|x| x.as_str()) else {
+            return vec![];
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = document.and_then(|x| x.get("text")).and_then(|x| x.as_str()).unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+            },
+
+            // with full sync the last change is the whole document
+            "textDocument/didChange" => {
+                let text = match params.get("contentChanges") {
+                    Some(Json::Array(changes)) => changes.last().and_then(|x| x.get("text")).and_then(|x| x.as_str()),
+                    _ => None,
+                };
+
+                if let Some(text) = text {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+            },
+
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+            },
+
+            _ => return vec![],
+        }
+
+        vec![self.diagnostics(uri)]
+    }
+}
+
+/// Runs the server until the `exit` notification, returns the exit status
+pub fn run() -> i32 {
+    let mut shell = Shell::new(false);
+    shell.import_env(std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?))));
+
+    // for the builtins of plugins and their completers, loaded on the first
+    // completion or hover that could need them
+    shell.defer_builtins(|| {
+        let (plugins, errors) = plugins::load_all();
+        for err in errors {
+            eprintln!("rush: lsp: plugin: {}", err);
+        }
+
+        plugins.to_builtins()
+    });
+
+    let mut server = Server {
+        documents: HashMap::new(),
+        shell,
+        commands: None,
+        shutdown: false,
+    };
+
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+
+    loop {
+        let message = match read_message(&mut stdin) {
+            Ok(Some(x)) => x,
+            Ok(None) => return 1,
+            Err(err) => {
+                eprintln!("rush: lsp: {}", err);
+                return 1;
+            },
+        };
+
+        let message = match Json::parse(&message) {
+            Ok(x) => x,
+            Err((err, _)) => {
+                let response = Json::object([
+                    ("jsonrpc", "2.0".into()),
+                    ("id", Json::Null),
+                    // ParseError
+                    ("error", Json::object([("code", (-32700).into()), ("message", err.into())])),
+                ]);
+
+                let _ = write_message(&mut stdout, &response);
+                continue;
+            },
+        };
+
+        let method = message.get("method").and_then(|x| x.as_str()).unwrap_or_default();
+        let params = message.get("params").unwrap_or(&Json::Null);
+
+        if method == "exit" {
+            return if server.shutdown { 0 } else { 1 };
+        }
+
+        let responses = match message.get("id") {
+            Some(id) => {
+                let result = match server.request(method, params) {
+                    Ok(x) => ("result", x),
+                    Err((code, message)) => ("error", Json::object([("code", code.into()), ("message", message.into())])),
+                };
+
+                vec![Json::object([("jsonrpc", "2.0".into()), ("id", id.clone()), result])]
+            },
+            None => server.notification(method, params),
+        };
+
+        for response in responses {
+            if let Err(err) = write_message(&mut stdout, &response) {
+                eprintln!("rush: lsp: {}", err);
+                return 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Labels of the completion at the end of the source
+    fn complete(source: &str) -> Vec<String> {
+        let mut server = Server {
+            documents: HashMap::from([("file".to_string(), source.to_string())]),
+            shell: Shell::new(false),
+            commands: Some(vec!["ls".to_string()]),
+            shutdown: false,
+        };
+        server.shell.set_var("HOME", "/");
+
+        let lines: Vec<&str> = source.split('\n').collect();
+        let params = Json::object([
+            ("textDocument", Json::object([("uri", "file".into())])),
+            ("position", Json::object([
+                ("line", (lines.len() - 1).into()),
+                ("character", lines[lines.len() - 1].len().into()),
+            ])),
+        ]);
+
+        match server.completion(&params) {
+            Json::Array(items) => items.iter().map(|x| x.get("label").and_then(|x| x.as_str()).unwrap().to_string()).collect(),
+            _ => panic!("completion is not an array"),
+        }
+    }
+
+    #[test]
+    fn arguments_of_builtins() {
+        assert_eq!(complete("set -o err"), ["errexit"]);
+        assert!(complete("A=1 export ").contains(&"HOME".to_string()));
+        assert_eq!(complete("echo x; set -o x"), ["xtrace"]);
+        assert!(complete("ls ").is_empty());
+    }
+
+    #[test]
+    fn filtered_by_the_word() {
+        assert_eq!(complete("f() { :; }\nex"), ["exit", "export"]);
+        assert_eq!(complete("f() { :; }\n"), ["f", "alias", "caller", "exit", "export", "features", "return", "set", "unalias", "unset", "ls"]);
+        assert_eq!(complete("l"), ["ls"]);
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(complete("HOST=x\necho $HO"), ["$HOME", "$HOST"]);
+        assert_eq!(complete("echo \"${HOM"), ["${HOME}"]);
+    }
+}
@@ -1,21 +1,420 @@
+use std::cell::LazyCell;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, IsTerminal, Read, Write};
 use std::rc::Rc;
 
-mod tokenizer;
+mod cli;
 
-// enum Error {
-//     TokenizerError(usize)
-// }
-
-use crate::tokenizer::tokenize;
+use rush::config::{self, Config};
+use rush::debugger::Debugger;
+use rush::dirs;
+use rush::exec::{self, Shell, STATUS_USAGE};
+use rush::features::FEATURES;
+use rush::json::Json;
+use rush::plugins::{self, Plugins};
+use rush::profiler::Profiler;
 
 fn main() {
-    let s = r#"'aaa' "bbb" `ccc`"#.to_string();
-    // let s = r#"124 0x2fFF 'aha' "hehe""#.to_string();
+    let args = match cli::parse_args(std::env::args()) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("rush: {}", err);
+            std::process::exit(STATUS_USAGE);
+        },
+    };
+
+    if args.version {
+        println!("rush {}", env!("CARGO_PKG_VERSION"));
+        println!("features: {}", FEATURES.join(" "));
+        return;
+    }
+
+    if args.features {
+        for feature in FEATURES {
+            println!("{}", feature);
+        }
+
+        return;
+    }
+
+    if args.lsp {
+        std::process::exit(rush::lsp::run());
+    }
+
+    if let Some(tool) = args.tool {
+        std::process::exit(match tool {
+            cli::Tool::Lint => lint(&args),
+            cli::Tool::Fmt => fmt(&args),
+        });
+    }
+
+    let mut shell = Shell::new(args.posix);
+    shell.xtrace = args.xtrace;
+    match (args.debug, args.profile) {
+        (true, true) => {
+            eprintln!("rush: --debug and --profile can not be used together");
+            std::process::exit(STATUS_USAGE);
+        },
+        (true, false) => shell.hook = Some(Box::new(Debugger::new())),
+        (false, true) => shell.hook = Some(Box::new(Profiler::new())),
+        (false, false) => {},
+    }
+
+    if let Some(fd) = args.trace_fd {
+        match open_fd(fd) {
+            Ok(x) => shell.trace = Some(x),
+            Err(err) => {
+                eprintln!("rush: --trace-fd: {}", err);
+                std::process::exit(STATUS_USAGE);
+            },
+        }
+    }
+
+    // opened the first time a command is not a function, builtin or in PATH,
+    // or the prompt shows their segments, as `sh` there are none
+    let plugins: Rc<LazyCell<Plugins, fn() -> Plugins>> = Rc::new(LazyCell::new(load_plugins));
+    if !args.posix {
+        let deferred = plugins.clone();
+        shell.defer_builtins(move || deferred.to_builtins());
+    }
+
+    shell.import_env(std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| !args.clean_env || args.keep_env.contains(name)));
+    // with -c the first argument is $0
+    shell.args = match (&args.command, &args.script) {
+        (Some(_), _) if !args.args.is_empty() => args.args.clone(),
+        (None, Some(script)) => std::iter::once(script.clone()).chain(args.args.iter().cloned()).collect(),
+        _ => std::iter::once("rush".to_string()).chain(args.args.iter().cloned()).collect(),
+    };
+
+    match (&args.command, &args.script) {
+        (Some(command), _) => {
+            shell.eval_file("-c", command);
+        },
+        (None, Some(path)) if path != "-" => match std::fs::read_to_string(path) {
+            Ok(x) => {
+                shell.eval_cached(path, &x);
+            },
+            Err(err) => {
+                eprintln!("rush: {}: {}", path, err);
+                std::process::exit(exec::STATUS_NOT_FOUND);
+            },
+        },
+        _ if std::io::stdin().is_terminal() => repl(&mut shell, &plugins),
+        _ => {
+            let mut s = String::new();
+            if let Err(err) = std::io::stdin().read_to_string(&mut s) {
+                eprintln!("rush: {}", err);
+                std::process::exit(1);
+            }
+
+            shell.eval_file("stdin", &s);
+        },
+    }
+
+    if let Some(mut hook) = shell.hook.take() {
+        hook.finish(&shell);
+    }
+
+    std::process::exit(shell.status);
+}
+
+/// `rush lint [--json] [--posix] file...`, returns 1 if anything was found
+fn lint(args: &cli::Args) -> i32 {
+    let mut json = false;
+    let mut posix = args.posix;
+    let mut files = vec![];
+    for arg in &args.args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--posix" => posix = true,
+            _ => files.push(arg.as_str()),
+        }
+    }
+
+    if files.is_empty() {
+        files.push("-");
+    }
+
+    let mut status = 0;
+    let mut found = vec![];
+    for file in files {
+        let source = if file == "-" {
+            let mut s = String::new();
+            std::io::stdin().read_to_string(&mut s).map(|_| s)
+        } else {
+            std::fs::read_to_string(file)
+        };
+
+        let source = match source {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!("rush: {}: {}", file, err);
+                status = STATUS_USAGE;
+                continue;
+            },
+        };
+
+        for x in rush::lint::lint(&source, posix) {
+            if status == 0 {
+                status = 1;
+            }
+
+            if json {
+                found.push(x.to_json(file, &source));
+            } else {
+                println!("{}", x.display(file, &source));
+            }
+        }
+    }
+
+    if json {
+        println!("{}", Json::Array(found));
+    }
+
+    status
+}
+
+/// `rush fmt [--check] [--posix] file...`, formats the files in place, stdin
+/// is written to stdout, with `--check` nothing is written and it returns 1 if
+/// any file is not formatted
+fn fmt(args: &cli::Args) -> i32 {
+    let mut check = false;
+    let mut posix = args.posix;
+    let mut files = vec![];
+    for arg in &args.args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--posix" => posix = true,
+            _ => files.push(arg.as_str()),
+        }
+    }
+
+    if files.is_empty() {
+        files.push("-");
+    }
+
+    let mut status = 0;
+    for file in files {
+        let source = if file == "-" {
+            let mut s = String::new();
+            std::io::stdin().read_to_string(&mut s).map(|_| s)
+        } else {
+            std::fs::read_to_string(file)
+        };
+
+        let source = match source {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!("rush: {}: {}", file, err);
+                status = STATUS_USAGE;
+                continue;
+            },
+        };
+
+        let formatted = match rush::fmt::format(&source, posix) {
+            Ok(x) => x,
+            Err(err) => {
+                eprint!("{}", err.render(file, &source));
+                status = STATUS_USAGE;
+                continue;
+            },
+        };
+
+        if check {
+            if formatted != source {
+                println!("{}", file);
+                if status == 0 {
+                    status = 1;
+                }
+            }
+        } else if file == "-" {
+            print!("{}", formatted);
+        } else if formatted != source {
+            if let Err(err) = std::fs::write(file, formatted) {
+                eprintln!("rush: {}: {}", file, err);
+                status = STATUS_USAGE;
+            }
+        }
+    }
+
+    status
+}
+
+/// Writer for a descriptor inherited from the parent, like `--trace-fd 3`
+#[cfg(unix)]
+fn open_fd(fd: u32) -> Result<Box<dyn Write>, String> {
+    use std::os::fd::FromRawFd;
+
+    match fd {
+        1 => Ok(Box::new(std::io::stdout())),
+        2 => Ok(Box::new(std::io::stderr())),
+        _ => {
+            // checks the descriptor is open without taking ownership of it
+            std::fs::metadata(format!("/dev/fd/{}", fd)).map_err(|x| format!("{}: {}", fd, x))?;
+
+            // SAFETY: the descriptor is open and nothing else in the shell
+            // uses descriptors above 2
+            Ok(Box::new(unsafe { File::from_raw_fd(fd as i32) }))
+        },
+    }
+}
+
+#[cfg(not(unix))]
+fn open_fd(fd: u32) -> Result<Box<dyn Write>, String> {
+    match fd {
+        1 => Ok(Box::new(std::io::stdout())),
+        2 => Ok(Box::new(std::io::stderr())),
+        _ => Err(format!("{}: only 1 and 2 are supported on this platform", fd)),
+    }
+}
+
+/// Reads the configuration file if there is one, errors in it are printed
+/// and whatever is valid is still used
+fn load_config() -> Config {
+    let Some(path) = dirs::config_file().filter(|x| x.is_file()) else {
+        return Config::default();
+    };
 
-    println!("str: {:#?}", s);
+    let source = match std::fs::read_to_string(&path) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("rush: {}: {}", path.display(), err);
+            return Config::default();
+        },
+    };
 
-    let x = tokenize(Rc::new(s));
+    let (config, errors) = Config::parse(&source);
+    for err in errors {
+        eprint!("{}", err.render(&path.display().to_string(), &source));
+    }
 
-    println!("got: {:#?}", x);
+    config
 }
 
+/// Keeps the last `size` lines of the history file
+fn trim_history(path: &std::path::Path, size: usize) -> std::io::Result<()> {
+    let history = match std::fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    let lines: Vec<&str> = history.lines().collect();
+    if lines.len() <= size {
+        return Ok(());
+    }
+
+    let kept: String = lines[lines.len() - size..].iter().map(|x| format!("{}\n", x)).collect();
+    std::fs::write(path, kept)
+}
+
+/// Opens the history file for appending, creating the directory if needed
+fn open_history(config: &config::History) -> Option<File> {
+    let path = config.path()?;
+    let result = path.parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| config.size.map_or(Ok(()), |size| trim_history(&path, size)))
+        .and_then(|_| OpenOptions::new().append(true).create(true).open(&path));
+
+    match result {
+        Ok(x) => Some(x),
+        Err(err) => {
+            eprintln!("rush: {}: {}", path.display(), err);
+            None
+        },
+    }
+}
+
+/// Loads the plugins, the ones that fail are reported and skipped
+fn load_plugins() -> Plugins {
+    let (plugins, errors) = plugins::load_all();
+    for err in errors {
+        eprintln!("rush: plugin: {}", err);
+    }
+
+    plugins
+}
+
+/// Reads and runs commands line by line until `exit` or EOF
+fn repl(shell: &mut Shell, plugins: &LazyCell<Plugins, fn() -> Plugins>) {
+    let config = load_config();
+    config.apply(shell);
+
+    if let Some(path) = dirs::rc_file().filter(|x| x.is_file()) {
+        match std::fs::read_to_string(&path) {
+            Ok(x) => {
+                shell.eval_cached(&path.display().to_string(), &x);
+            },
+            Err(err) => eprintln!("rush: {}: {}", path.display(), err),
+        }
+    }
+
+    let mut history = config.history.enabled.then(|| open_history(&config.history)).flatten();
+    let mut previous = String::new();
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+
+    // lines of a command that is not complete yet
+    let mut command = String::new();
+
+    while shell.exit.is_none() {
+        let prompt = match command.is_empty() {
+            true => config.prompt.render(shell, || plugins.prompt()),
+            false => shell.continuation_prompt(),
+        };
+
+        eprint!("{}", prompt);
+        let _ = std::io::stderr().flush();
+
+        line.clear();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => {
+                // what was typed so far is run for the error about it
+                if !command.is_empty() {
+                    shell.eval_file("stdin", &shell.expand_aliases(&command));
+                }
+
+                break;
+            },
+            Ok(_) => {
+                // shown again when abbreviations changed it, it is what runs
+                // and what is saved, only the first line starts a command
+                if command.is_empty() {
+                    let abbreviated = config.abbreviate(&line, shell.posix);
+                    if abbreviated != line {
+                        eprint!("{}", abbreviated);
+                    }
+
+                    line = abbreviated;
+                }
+
+                command.push_str(&line);
+                let expanded = shell.expand_aliases(&command);
+                if !shell.is_complete(&expanded) {
+                    continue;
+                }
+
+                let command = std::mem::take(&mut command);
+                let ignored = command.trim().is_empty()
+                    || config.history.ignore_space && command.starts_with([' ', '\t'])
+                    || config.history.ignore_duplicates && command == previous;
+
+                if let Some(file) = history.as_mut().filter(|_| !ignored) {
+                    if let Err(err) = file.write_all(command.as_bytes()) {
+                        eprintln!("rush: history: {}", err);
+                        history = None;
+                    }
+                }
+
+                shell.eval_file("stdin", &expanded);
+                previous = command;
+            },
+            Err(err) => {
+                eprintln!("rush: {}", err);
+                break;
+            },
+        }
+    }
+}
@@ -0,0 +1,1114 @@
+//! Implementation of the parser, turns tokens into commands
+//!
+//! Tokens are read from the [`Lexer`](crate::tokenizer::Lexer) as the
+//! grammar needs them, so the first error in the buffer is the one reported
+//! and the tokens are never all kept around
+//!
+//! Nodes are kept in an [`Ast`] arena, one vector per kind of node, and refer
+//! to their children by [`List`] ranges into it so a script is a handful of
+//! allocations and its commands are next to each other in memory
+
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::ops::Index;
+
+use crate::symbol::Symbol;
+use crate::tokenizer::{Lexer, Token, TokenWithInfo, TokenizeError};
+
+/// Syntax error with position of the offending part of the buffer
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+
+    /// Byte index of start of the error
+    pub start: usize,
+
+    /// Byte index of end of the error
+    pub end: usize,
+
+    /// The buffer ended before the command did, like after `|` or in a
+    /// string, so more lines could still make it valid
+    pub incomplete: bool,
+}
+
+impl From<TokenizeError> for ParseError {
+    fn from(err: TokenizeError) -> Self {
+        Self {
+            message: err.message,
+            start: err.start,
+            end: err.end,
+            incomplete: false,
+        }
+    }
+}
+
+/// Part of a word, quotes are already removed
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text
+    Literal(String),
+
+    /// Quoted or escaped text, taken as is
+    Quoted(String),
+
+    /// `$name` or `${name}`, `quoted` when inside double quotes
+    Variable {
+        name: Symbol,
+        quoted: bool,
+    },
+}
+
+/// Range of nodes of the same kind in the [`Ast`], `&ast[list]` is the
+/// slice of them
+pub struct List<T> {
+    start: u32,
+    len: u32,
+    kind: PhantomData<fn() -> T>,
+}
+
+impl<T> List<T> {
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// derives would require T to implement them
+impl<T> Clone for List<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for List<T> {}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            len: 0,
+            kind: PhantomData,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.start + self.len)
+    }
+}
+
+/// Single shell word, made out of tokens that are not separated by whitespace
+#[derive(Debug, Clone, Copy)]
+pub struct Word {
+    pub parts: List<WordPart>,
+
+    /// Byte index of start of the word
+    pub start: usize,
+
+    /// Byte index of end of the word
+    pub end: usize,
+}
+
+impl Word {
+    /// Text of the word with the quotes removed, variables are kept as
+    /// written
+    pub fn text(&self, ast: &Ast) -> String {
+        ast[self.parts].iter()
+            .map(|x| match x {
+                WordPart::Literal(x) | WordPart::Quoted(x) => x.clone(),
+                WordPart::Variable { name, .. } => format!("${{{}}}", name),
+            })
+            .collect()
+    }
+
+    /// Text of the word if none of it is quoted
+    pub fn literal<'a>(&self, ast: &'a Ast) -> Option<&'a str> {
+        match &ast[self.parts] {
+            [WordPart::Literal(x)] => Some(x),
+            _ => None,
+        }
+    }
+}
+
+/// Word while it is being parsed
+struct PartialWord {
+    parts: Vec<WordPart>,
+    start: usize,
+    end: usize,
+}
+
+impl PartialWord {
+    fn push(&mut self, part: WordPart) {
+        // merge with previous part of the same kind
+        match (self.parts.last_mut(), part) {
+            (Some(WordPart::Literal(x)), WordPart::Literal(y)) => x.push_str(&y),
+            (Some(WordPart::Quoted(x)), WordPart::Quoted(y)) => x.push_str(&y),
+            (_, part) => self.parts.push(part),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    /// `<`
+    Input,
+
+    /// `>`
+    Output,
+
+    /// `>>`
+    Append,
+
+    /// `>&` and `<&`
+    Duplicate,
+}
+
+/// Redirection of file descriptor `fd`
+#[derive(Debug, Clone, Copy)]
+pub struct Redirect {
+    pub fd: u32,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+/// Variable assignment `name=value`
+#[derive(Debug, Clone, Copy)]
+pub struct Assignment {
+    pub name: Symbol,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SimpleCommand {
+    /// Assignments before the command name
+    pub assignments: List<Assignment>,
+
+    pub words: List<Word>,
+    pub redirects: List<Redirect>,
+
+    /// Byte index of start of the command
+    pub start: usize,
+
+    /// Byte index of end of the command
+    pub end: usize,
+}
+
+/// Function definition `name() { ... }`
+#[derive(Debug, Clone, Copy)]
+pub struct Function {
+    pub name: Symbol,
+    pub body: Script,
+
+    /// Byte index of start of the definition, which is the name
+    pub start: usize,
+
+    /// Byte index of end of the definition
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Function(Function),
+}
+
+/// Commands connected with `|`
+#[derive(Debug, Clone, Copy)]
+pub struct Pipeline {
+    /// Pipeline starts with `!`
+    pub negated: bool,
+
+    pub commands: List<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    /// `&&`
+    And,
+
+    /// `||`
+    Or,
+}
+
+/// Pipelines connected with `&&` and `||`
+#[derive(Debug, Clone, Copy)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: List<(Connector, Pipeline)>,
+}
+
+/// List of commands, the whole buffer or a function body
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Script {
+    pub commands: List<AndOr>,
+}
+
+/// Arena of the nodes of a parsed buffer
+#[derive(Debug, Default)]
+pub struct Ast {
+    /// Commands of the whole buffer
+    pub script: Script,
+
+    and_ors: Vec<AndOr>,
+    links: Vec<(Connector, Pipeline)>,
+    commands: Vec<Command>,
+    assignments: Vec<Assignment>,
+    words: Vec<Word>,
+    redirects: Vec<Redirect>,
+    parts: Vec<WordPart>,
+}
+
+/// Kind of node with its own vector in the [`Ast`]
+pub trait Node: Sized {
+    fn nodes(ast: &Ast) -> &Vec<Self>;
+    fn nodes_mut(ast: &mut Ast) -> &mut Vec<Self>;
+}
+
+macro_rules! node {
+    ($type:ty, $field:ident) => {
+        impl Node for $type {
+            fn nodes(ast: &Ast) -> &Vec<Self> {
+                &ast.$field
+            }
+
+            fn nodes_mut(ast: &mut Ast) -> &mut Vec<Self> {
+                &mut ast.$field
+            }
+        }
+    };
+}
+
+node!(AndOr, and_ors);
+node!((Connector, Pipeline), links);
+node!(Command, commands);
+node!(Assignment, assignments);
+node!(Word, words);
+node!(Redirect, redirects);
+node!(WordPart, parts);
+
+impl Ast {
+    /// Adds the nodes next to each other, returns their range
+    pub fn alloc<T: Node>(&mut self, nodes: impl IntoIterator<Item = T>) -> List<T> {
+        let vec = T::nodes_mut(self);
+        let start = vec.len();
+        vec.extend(nodes);
+
+        List {
+            start: start as u32,
+            len: (vec.len() - start) as u32,
+            kind: PhantomData,
+        }
+    }
+}
+
+impl<T: Node> Index<List<T>> for Ast {
+    type Output = [T];
+
+    fn index(&self, list: List<T>) -> &[T] {
+        &T::nodes(self)[list.start as usize..(list.start + list.len) as usize]
+    }
+}
+
+/// Words and operators, what the grammar actually works with
+#[derive(Debug, Clone, Copy)]
+enum Item {
+    Word(Word),
+    Operator(&'static str, usize, usize),
+    Newline(usize),
+}
+
+impl Item {
+    fn span(&self) -> (usize, usize) {
+        match *self {
+            Item::Word(x) => (x.start, x.end),
+            Item::Operator(_, start, end) => (start, end),
+            Item::Newline(start) => (start, start + 1),
+        }
+    }
+}
+
+fn operator(token: &Token) -> Option<&'static str> {
+    const OPERATORS: [&str; 9] = ["|", "||", "&&", ";", "&", "<", ">", ">>", "<<"];
+
+    match token {
+        Token::Symbol(x) => OPERATORS.into_iter().find(|y| x == y),
+        Token::Paren('(') => Some("("),
+        Token::Paren(')') => Some(")"),
+        _ => None,
+    }
+}
+
+/// Tokens read from the lexer as they are needed, the ones looked ahead at
+/// are kept until they are taken
+struct Tokens<I> {
+    iter: I,
+    peeked: VecDeque<TokenWithInfo>,
+
+    /// Byte index of end of the last token taken
+    end: usize,
+
+    /// Length of the buffer, used as position of errors at the end
+    len: usize,
+}
+
+impl<I: Iterator<Item = Result<TokenWithInfo, TokenizeError>>> Tokens<I> {
+    /// Token `n` tokens after the next one
+    fn peek(&mut self, n: usize) -> Result<Option<&TokenWithInfo>, ParseError> {
+        while self.peeked.len() <= n {
+            let Some(token) = self.iter.next().transpose()? else {
+                return Ok(None);
+            };
+
+            self.len = token.buffer.len();
+            self.peeked.push_back(token);
+        }
+
+        Ok(self.peeked.get(n))
+    }
+
+    fn next(&mut self) -> Result<Option<TokenWithInfo>, ParseError> {
+        self.peek(0)?;
+
+        let token = self.peeked.pop_front();
+        if let Some(x) = &token {
+            self.end = x.end;
+        }
+
+        Ok(token)
+    }
+}
+
+/// Special parameters like `$?`
+fn is_special(ch: char) -> bool {
+    matches!(ch, '?' | '#' | '$' | '!' | '@' | '*' | '-' | '0'..='9')
+}
+
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+/// Splits a double quoted string into quoted text and variables, returns
+/// `None` if the string is not terminated
+fn unquote_double(raw: &str) -> Option<Vec<WordPart>> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut iter = raw.chars().skip(1).peekable();
+
+    while let Some(ch) = iter.next() {
+        match ch {
+            '"' => {
+                if iter.next().is_some() {
+                    return None;
+                }
+
+                if !text.is_empty() || parts.is_empty() {
+                    parts.push(WordPart::Quoted(text));
+                }
+
+                return Some(parts);
+            },
+
+            '\\' => match iter.next() {
+                Some('\n') => {},
+                Some(x @ ('$' | '`' | '"' | '\\')) => text.push(x),
+                Some(x) => {
+                    text.push('\\');
+                    text.push(x);
+                },
+                None => return None,
+            },
+
+            '$' => {
+                let mut name = String::new();
+                match iter.peek() {
+                    Some('{') => {
+                        iter.next();
+                        for x in iter.by_ref() {
+                            if x == '}' {
+                                break;
+                            }
+
+                            name.push(x);
+                        }
+                    },
+
+                    Some(x) if is_special(*x) => name.push(iter.next().unwrap()),
+
+                    _ => while let Some(x) = iter.next_if(|x| x.is_ascii_alphanumeric() || *x == '_') {
+                        name.push(x);
+                    },
+                }
+
+                if name.is_empty() {
+                    text.push('$');
+                    continue;
+                }
+
+                if !text.is_empty() {
+                    parts.push(WordPart::Quoted(std::mem::take(&mut text)));
+                }
+
+                parts.push(WordPart::Variable { name: Symbol::intern(&name), quoted: true });
+            },
+
+            _ => text.push(ch),
+        }
+    }
+
+    None
+}
+
+/// Parts of the text as if it was inside double quotes, used for variables
+/// like `PS4` that are expanded when used
+pub fn double_quoted(text: &str) -> Vec<WordPart> {
+    unquote_double(&format!("\"{}\"", text)).unwrap_or_else(|| vec![WordPart::Quoted(text.to_string())])
+}
+
+/// Parses parts of the word starting at the next token, takes the tokens
+/// used
+fn word_parts<I>(tokens: &mut Tokens<I>) -> Result<Vec<WordPart>, ParseError>
+where
+    I: Iterator<Item = Result<TokenWithInfo, TokenizeError>>,
+{
+    let Some(token) = tokens.next()? else {
+        return Ok(vec![]);
+    };
+
+    // unterminated strings always run until the end of the buffer
+    let error = |message: &str| ParseError {
+        message: message.to_string(),
+        start: token.start,
+        end: token.end,
+        incomplete: message == "unterminated string",
+    };
+
+    let part = match &token.token {
+        Token::String(raw, '\'') => {
+            if raw.len() < 2 || !raw.ends_with('\'') {
+                return Err(error("unterminated string"));
+            }
+
+            WordPart::Quoted(raw[1..raw.len() - 1].to_string())
+        },
+
+        Token::String(raw, '"') => match unquote_double(raw) {
+            Some(parts) => return Ok(parts),
+            None => return Err(error("unterminated string")),
+        },
+
+        Token::String(_, _) => return Err(error("command substitution is not supported yet")),
+
+        // escaped newline just continues the line
+        Token::Symbol(x) if x == "\\\n" => return Ok(vec![]),
+        Token::Symbol(x) if x.starts_with('\\') && x.len() > 1 => WordPart::Quoted(x[1..].to_string()),
+
+        Token::Symbol(x) if x == "$" => match tokens.peek(0)?.filter(|x| x.start == token.end) {
+            // ${name}
+            Some(next) if matches!(next.token, Token::Paren('{')) => {
+                let open = next.end;
+                let mut n = 1;
+                let (close, end) = loop {
+                    match tokens.peek(n)? {
+                        Some(x) if matches!(x.token, Token::Paren('}')) => break (x.start, x.end),
+                        Some(_) => n += 1,
+                        None => return Err(error("missing '}'")),
+                    }
+                };
+
+                let name = token.buffer[open..close].to_string();
+                let valid = is_name(&name)
+                    || (name.len() == 1 && name.chars().all(is_special))
+                    || name.parse::<u32>().is_ok();
+
+                if !valid {
+                    return Err(ParseError {
+                        message: "bad substitution".to_string(),
+                        start: token.start,
+                        end,
+                        incomplete: false,
+                    });
+                }
+
+                for _ in 0..=n {
+                    tokens.next()?;
+                }
+
+                WordPart::Variable { name: Symbol::intern(&name), quoted: false }
+            },
+
+            Some(next) => {
+                let raw = next.raw();
+                let variable = match &next.token {
+                    Token::Identifier(name) => Some((*name, "")),
+                    Token::Keyword(_) => Some((Symbol::intern(raw), "")),
+
+                    // only single digit, $10 is $1 followed by 0
+                    Token::Integer(_) | Token::Symbol(_) if raw.chars().next().is_some_and(is_special) => {
+                        let (name, rest) = raw.split_at(1);
+                        Some((Symbol::intern(name), rest))
+                    },
+
+                    _ => None,
+                };
+
+                let Some((name, rest)) = variable else {
+                    return Ok(vec![WordPart::Literal("$".to_string())]);
+                };
+
+                let rest = rest.to_string();
+                tokens.next()?;
+
+                let variable = WordPart::Variable { name, quoted: false };
+                if rest.is_empty() {
+                    variable
+                } else {
+                    return Ok(vec![variable, WordPart::Literal(rest)]);
+                }
+            },
+
+            None => WordPart::Literal("$".to_string()),
+        },
+
+        _ => WordPart::Literal(token.raw().to_string()),
+    };
+
+    Ok(vec![part])
+}
+
+fn finish_word(word: PartialWord, ast: &mut Ast) -> Word {
+    Word {
+        parts: ast.alloc(word.parts),
+        start: word.start,
+        end: word.end,
+    }
+}
+
+/// Splits word into an assignment if it starts with `name=`
+fn assignment(word: &Word, ast: &mut Ast) -> Option<Assignment> {
+    let Some(WordPart::Literal(first)) = ast[word.parts].first() else {
+        return None;
+    };
+
+    let (name, value) = first.split_once('=')?;
+    if !is_name(name) {
+        return None;
+    }
+
+    let name = Symbol::intern(name);
+    let start = word.start + name.as_str().len() + 1;
+
+    // the value is the rest of the first part and the other parts
+    let value = (!value.is_empty()).then(|| WordPart::Literal(value.to_string()));
+    let rest = ast[word.parts][1..].to_vec();
+    let parts = ast.alloc(value.into_iter().chain(rest));
+
+    Some(Assignment {
+        name,
+        value: Word {
+            parts,
+            start,
+            end: word.end,
+        },
+    })
+}
+
+struct Parser<I> {
+    tokens: Tokens<I>,
+
+    /// Items looked ahead at
+    items: VecDeque<Item>,
+
+    /// Byte index of end of the last item taken
+    end: usize,
+
+    /// Extensions like `[[` are errors instead of commands
+    posix: bool,
+
+    ast: Ast,
+}
+
+impl<I: Iterator<Item = Result<TokenWithInfo, TokenizeError>>> Parser<I> {
+    /// Groups the next tokens into a word or an operator, parts of the words
+    /// are added to the arena
+    fn read_item(&mut self) -> Result<Option<Item>, ParseError> {
+        let mut word: Option<PartialWord> = None;
+
+        while let Some(token) = self.tokens.peek(0)? {
+            let operator = operator(&token.token);
+            let word_token = operator.is_none() && !matches!(token.token, Token::Newline(_) | Token::Comment(_));
+
+            // whitespace or an operator ends the word, a word with no parts
+            // was only a line continuation
+            if let Some(current) = &word {
+                if current.end != token.start || !word_token {
+                    if !current.parts.is_empty() {
+                        break;
+                    }
+
+                    word = None;
+                }
+            }
+
+            let (start, end) = (token.start, token.end);
+            if !word_token {
+                let newline = matches!(token.token, Token::Newline(_));
+                self.tokens.next()?;
+
+                match operator {
+                    Some(x) => return Ok(Some(Item::Operator(x, start, end))),
+                    None if newline => return Ok(Some(Item::Newline(start))),
+                    None => continue,
+                }
+            }
+
+            let parts = word_parts(&mut self.tokens)?;
+            let current = word.get_or_insert_with(|| PartialWord {
+                parts: vec![],
+                start,
+                end,
+            });
+
+            current.end = self.tokens.end;
+            for part in parts {
+                current.push(part);
+            }
+        }
+
+        Ok(word.filter(|x| !x.parts.is_empty()).map(|x| Item::Word(finish_word(x, &mut self.ast))))
+    }
+
+    /// Item `n` items after the current one
+    fn nth(&mut self, n: usize) -> Result<Option<Item>, ParseError> {
+        while self.items.len() <= n {
+            let Some(item) = self.read_item()? else {
+                return Ok(None);
+            };
+
+            self.items.push_back(item);
+        }
+
+        Ok(self.items.get(n).copied())
+    }
+
+    fn peek(&mut self) -> Result<Option<Item>, ParseError> {
+        self.nth(0)
+    }
+
+    /// Takes the current item
+    fn advance(&mut self) {
+        if let Some(item) = self.items.pop_front() {
+            self.end = item.span().1;
+        }
+    }
+
+    fn peek_operator(&mut self) -> Result<Option<&'static str>, ParseError> {
+        match self.peek()? {
+            Some(Item::Operator(x, _, _)) => Ok(Some(x)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Literal word at the current position, used for `{` and `}` which are
+    /// only special at the start of a command
+    fn peek_literal(&mut self) -> Result<Option<&str>, ParseError> {
+        match self.peek()? {
+            Some(Item::Word(x)) => Ok(x.literal(&self.ast)),
+            _ => Ok(None),
+        }
+    }
+
+    fn error<T>(&mut self, message: &str) -> Result<T, ParseError> {
+        let item = self.peek()?;
+        let (start, end) = match item {
+            Some(x) => x.span(),
+            None => (self.tokens.len, self.tokens.len),
+        };
+
+        Err(ParseError {
+            message: message.to_string(),
+            start,
+            end,
+            incomplete: item.is_none(),
+        })
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ParseError> {
+        while let Some(Item::Newline(_)) = self.peek()? {
+            self.advance();
+        }
+
+        Ok(())
+    }
+
+    /// Parses commands until the end, or until `}` if `nested`
+    fn script(&mut self, nested: bool) -> Result<Script, ParseError> {
+        let mut commands = vec![];
+
+        loop {
+            self.skip_newlines()?;
+            if self.peek()?.is_none() || (nested && self.peek_literal()? == Some("}")) {
+                break;
+            }
+
+            commands.push(self.and_or()?);
+
+            match self.peek()? {
+                Some(Item::Newline(_)) => {},
+                Some(Item::Operator(";", _, _)) => self.advance(),
+                None => break,
+                _ => return self.error("unexpected token"),
+            }
+        }
+
+        Ok(Script {
+            commands: self.ast.alloc(commands),
+        })
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = vec![];
+
+        loop {
+            let connector = match self.peek_operator()? {
+                Some("&&") => Connector::And,
+                Some("||") => Connector::Or,
+                _ => break,
+            };
+
+            self.advance();
+            self.skip_newlines()?;
+            rest.push((connector, self.pipeline()?));
+        }
+
+        Ok(AndOr {
+            first,
+            rest: self.ast.alloc(rest),
+        })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut negated = false;
+        if self.peek_literal()? == Some("!") {
+            negated = true;
+            self.advance();
+        }
+
+        let mut commands = vec![self.command()?];
+        while self.peek_operator()? == Some("|") {
+            self.advance();
+            self.skip_newlines()?;
+            commands.push(self.command()?);
+        }
+
+        Ok(Pipeline {
+            negated,
+            commands: self.ast.alloc(commands),
+        })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        if let Some(function) = self.function()? {
+            return Ok(Command::Function(function));
+        }
+
+        let posix = self.posix;
+        match self.peek_literal()? {
+            Some("{") => return self.error("brace groups are not supported yet"),
+            Some("[[") if posix => return self.error("'[[' is not available in POSIX mode, use '['"),
+            Some("function") if posix => {
+                return self.error("'function' is not available in POSIX mode, use 'name() { ... }'");
+            },
+            _ => {},
+        }
+
+        self.simple_command().map(Command::Simple)
+    }
+
+    /// Parses function definition at the current position, returns `None` if
+    /// there is none
+    fn function(&mut self) -> Result<Option<Function>, ParseError> {
+        let (Some(Item::Word(word)), Some(Item::Operator("(", _, _)), Some(Item::Operator(")", _, _))) =
+            (self.peek()?, self.nth(1)?, self.nth(2)?) else {
+            return Ok(None);
+        };
+
+        let Some(name) = word.literal(&self.ast).filter(|x| is_name(x)).map(Symbol::intern) else {
+            return self.error("invalid function name");
+        };
+
+        let start = word.start;
+        for _ in 0..3 {
+            self.advance();
+        }
+
+        self.skip_newlines()?;
+
+        if self.peek_literal()? != Some("{") {
+            return self.error("expected '{'");
+        }
+
+        self.advance();
+        let body = self.script(true)?;
+
+        let end = match self.peek()? {
+            Some(Item::Word(x)) if x.literal(&self.ast) == Some("}") => x.end,
+            _ => return self.error("missing '}'"),
+        };
+
+        self.advance();
+
+        Ok(Some(Function {
+            name,
+            body,
+            start,
+            end,
+        }))
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut assignments = vec![];
+        let mut words = vec![];
+        let mut redirects = vec![];
+        let start = self.peek()?.map_or(self.tokens.len, |x| x.span().0);
+
+        loop {
+            match self.peek()? {
+                Some(Item::Word(word)) => {
+                    // number right before a redirection is the file descriptor
+                    if let Some(redirect) = self.redirect()? {
+                        redirects.push(redirect);
+                        continue;
+                    }
+
+                    match assignment(&word, &mut self.ast).filter(|_| words.is_empty()) {
+                        Some(x) => assignments.push(x),
+                        None => words.push(word),
+                    }
+
+                    self.advance();
+                },
+
+                Some(Item::Operator(x, _, _)) => match x {
+                    "<" | ">" | ">>" => redirects.push(self.redirect()?.unwrap()),
+                    "&" => return self.error("background jobs are not supported yet"),
+                    "<<" => return self.error("here-documents are not supported yet"),
+                    "(" | ")" => return self.error("subshells are not supported yet"),
+                    _ => break,
+                },
+
+                _ => break,
+            }
+        }
+
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return self.error("expected a command");
+        }
+
+        Ok(SimpleCommand {
+            assignments: self.ast.alloc(assignments),
+            words: self.ast.alloc(words),
+            redirects: self.ast.alloc(redirects),
+            start,
+            end: self.end,
+        })
+    }
+
+    /// Parses redirection at the current position, returns `None` if there is
+    /// none
+    fn redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let mut fd = None;
+        if let (Some(Item::Word(word)), Some(Item::Operator(_, start, _))) = (self.peek()?, self.nth(1)?) {
+            if word.end == start {
+                fd = word.literal(&self.ast).and_then(|x| x.parse::<u32>().ok());
+            }
+        }
+
+        let offset = if fd.is_some() { 1 } else { 0 };
+        let (operator, end) = match self.nth(offset)? {
+            Some(Item::Operator(x, _, end)) if matches!(x, "<" | ">" | ">>") => (x, end),
+            _ => return Ok(None),
+        };
+
+        for _ in 0..=offset {
+            self.advance();
+        }
+
+        let mut kind = match operator {
+            "<" => RedirectKind::Input,
+            ">" => RedirectKind::Output,
+            _ => RedirectKind::Append,
+        };
+
+        // >& and <& duplicate descriptors
+        if let Some(Item::Operator(x, start, _)) = self.peek()? {
+            if x == "&" && start == end && operator != ">>" {
+                kind = RedirectKind::Duplicate;
+                self.advance();
+            }
+        }
+
+        let target = match self.peek()? {
+            Some(Item::Word(word)) => word,
+            _ => return self.error("expected a file name"),
+        };
+
+        self.advance();
+
+        Ok(Some(Redirect {
+            fd: fd.unwrap_or(if operator == "<" { 0 } else { 1 }),
+            kind,
+            target,
+        }))
+    }
+}
+
+/// Parses the tokens as they come from the lexer, stops at the first error
+/// in them or in the grammar, in POSIX mode of the lexer its extensions are
+/// errors too
+pub fn parse(lexer: Lexer) -> Result<Ast, ParseError> {
+    let posix = lexer.posix();
+    parse_tokens(lexer, posix)
+}
+
+/// Like [`parse`] for tokens changed after the lexer
+pub fn parse_tokens<I>(tokens: I, posix: bool) -> Result<Ast, ParseError>
+where
+    I: IntoIterator<Item = Result<TokenWithInfo, TokenizeError>>,
+{
+    let mut parser = Parser {
+        posix,
+        tokens: Tokens {
+            iter: tokens.into_iter(),
+            peeked: VecDeque::new(),
+            end: 0,
+            len: 0,
+        },
+        items: VecDeque::new(),
+        end: 0,
+        ast: Ast::default(),
+    };
+
+    parser.ast.script = parser.script(false)?;
+    Ok(parser.ast)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::tokenizer::Lexer;
+
+    fn parse_str(source: &str) -> Result<Ast, ParseError> {
+        parse(Lexer::new(Rc::new(source.to_string()), false))
+    }
+
+    /// Words of each simple command in the script
+    fn words(source: &str) -> Vec<Vec<String>> {
+        let ast = parse_str(source).unwrap();
+        let mut commands = vec![];
+
+        for and_or in &ast[ast.script.commands] {
+            let pipelines = std::iter::once(&and_or.first).chain(ast[and_or.rest].iter().map(|x| &x.1));
+            for pipeline in pipelines {
+                for command in &ast[pipeline.commands] {
+                    if let Command::Simple(x) = command {
+                        commands.push(ast[x.words].iter().map(|x| x.text(&ast)).collect());
+                    }
+                }
+            }
+        }
+
+        commands
+    }
+
+    #[test]
+    fn commands_and_operators() {
+        assert_eq!(words("a b; c | d && e\nf"), [vec!["a", "b"], vec!["c"], vec!["d"], vec!["e"], vec!["f"]]);
+    }
+
+    #[test]
+    fn quotes_are_removed() {
+        assert_eq!(words(r#"echo 'a b' "c\"d" e\ f"#), [vec!["echo", "a b", "c\"d", "e f"]]);
+    }
+
+    #[test]
+    fn variables() {
+        let ast = parse_str(r#"echo $a${b}"$c" $12"#).unwrap();
+        let Command::Simple(command) = ast[ast[ast.script.commands][0].first.commands][0] else {
+            panic!("expected a simple command");
+        };
+
+        let words = &ast[command.words];
+        assert_eq!(words[1].text(&ast), "${a}${b}${c}");
+        assert!(matches!(ast[words[1].parts][2], WordPart::Variable { quoted: true, .. }));
+        assert_eq!(words[2].text(&ast), "${1}2");
+    }
+
+    #[test]
+    fn assignments_and_redirects() {
+        let ast = parse_str("A=1 cmd B=2 2>err <in").unwrap();
+        let Command::Simple(command) = ast[ast[ast.script.commands][0].first.commands][0] else {
+            panic!("expected a simple command");
+        };
+
+        let assignments = &ast[command.assignments];
+        assert_eq!(assignments.len(), 1);
+        assert_eq!(assignments[0].name, "A");
+        assert_eq!(ast[command.words].iter().map(|x| x.text(&ast)).collect::<Vec<_>>(), ["cmd", "B=2"]);
+
+        let redirects = &ast[command.redirects];
+        assert_eq!((redirects[0].fd, redirects[0].kind), (2, RedirectKind::Output));
+        assert_eq!((redirects[1].fd, redirects[1].kind), (0, RedirectKind::Input));
+    }
+
+    #[test]
+    fn functions() {
+        let ast = parse_str("f() {\n  echo a\n}\nf").unwrap();
+        let Command::Function(function) = ast[ast[ast.script.commands][0].first.commands][0] else {
+            panic!("expected a function");
+        };
+
+        assert_eq!(function.name, "f");
+        assert_eq!(function.body.commands.len(), 1);
+    }
+
+    #[test]
+    fn incomplete() {
+        for source in ["echo 'a\n", "echo \"a\n", "a |\n", "a &&\n", "f()\n", "f() {\necho\n"] {
+            assert!(parse_str(source).unwrap_err().incomplete, "{:?}", source);
+        }
+
+        for source in ["echo >\n", "a | |\n", "f() x\n", "echo ${a b}\n"] {
+            assert!(!parse_str(source).unwrap_err().incomplete, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_str("echo 'a").unwrap_err().message, "unterminated string");
+        assert_eq!(parse_str("a |").unwrap_err().message, "expected a command");
+        assert_eq!(parse_str("f() {\necho").unwrap_err().message, "missing '}'");
+    }
+
+    #[test]
+    fn posix_mode() {
+        let posix = |source: &str| parse(Lexer::new(Rc::new(source.to_string()), true)).map(|_| ()).map_err(|x| x.message);
+
+        assert!(posix("[[ a = a ]]").unwrap_err().starts_with("'[[' is not available"));
+        assert!(posix("function f { :; }").unwrap_err().starts_with("'function' is not available"));
+        assert_eq!(posix("{ echo a; }"), Err("brace groups are not supported yet".to_string()));
+
+        // only as the command, not as its arguments or quoted
+        assert_eq!(posix("echo [[ function {; f() { echo; }; '[[' a"), Ok(()));
+
+        assert_eq!(words("[[ a ]]; function f"), [vec!["[[", "a", "]]"], vec!["function", "f"]]);
+    }
+}
@@ -0,0 +1,347 @@
+//! JSON views of the tokens, syntax tree, diagnostics and expansions, used by
+//! the browser playground and the docs so they show what the real
+//! implementation does
+//!
+//! On wasm these are exported with a C ABI so JavaScript can call them without
+//! any bindings, see [`wasm`]
+
+use std::rc::Rc;
+
+use crate::exec::Shell;
+use crate::json::Json;
+use crate::lint::lint;
+use crate::parser::{parse, AndOr, Ast, Command, Connector, ParseError, Pipeline, RedirectKind, Script, Word, WordPart};
+use crate::tokenizer::{tokenize, Lexer, Token, TokenWithInfo};
+
+fn error(message: &str, start: usize, end: usize) -> Json {
+    Json::object([(
+        "error",
+        Json::object([
+            ("message", message.into()),
+            ("start", start.into()),
+            ("end", end.into()),
+        ]),
+    )])
+}
+
+fn token_kind(token: &Token) -> &'static str {
+    match token {
+        Token::Keyword(_) => "keyword",
+        Token::Identifier(_) => "identifier",
+        Token::Integer(_) => "integer",
+        Token::String(_, _) => "string",
+        Token::Paren(_) => "paren",
+        Token::Symbol(_) => "symbol",
+        Token::Newline(_) => "newline",
+        Token::Comment(_) => "comment",
+    }
+}
+
+fn token(token: &TokenWithInfo) -> Json {
+    Json::object([
+        ("kind", token_kind(&token.token).into()),
+        ("text", token.raw().into()),
+        ("start", token.start.into()),
+        ("end", token.end.into()),
+    ])
+}
+
+fn word(ast: &Ast, word: &Word) -> Json {
+    let parts = ast[word.parts].iter()
+        .map(|x| match x {
+            WordPart::Literal(x) => Json::object([("literal", x.as_str().into())]),
+            WordPart::Quoted(x) => Json::object([("quoted", x.as_str().into())]),
+            WordPart::Variable { name, quoted } => Json::object([
+                ("variable", name.as_str().into()),
+                ("quoted", (*quoted).into()),
+            ]),
+        })
+        .collect();
+
+    Json::object([
+        ("text", word.text(ast).into()),
+        ("parts", Json::Array(parts)),
+        ("start", word.start.into()),
+        ("end", word.end.into()),
+    ])
+}
+
+fn command(ast: &Ast, command: &Command) -> Json {
+    match command {
+        Command::Simple(x) => Json::object([
+            ("type", "simple".into()),
+            ("assignments", Json::Array(ast[x.assignments].iter()
+                .map(|x| Json::object([("name", x.name.as_str().into()), ("value", word(ast, &x.value))]))
+                .collect())),
+            ("words", Json::Array(ast[x.words].iter().map(|x| word(ast, x)).collect())),
+            ("redirects", Json::Array(ast[x.redirects].iter()
+                .map(|x| Json::object([
+                    ("fd", (x.fd as usize).into()),
+                    ("kind", match x.kind {
+                        RedirectKind::Input => "input",
+                        RedirectKind::Output => "output",
+                        RedirectKind::Append => "append",
+                        RedirectKind::Duplicate => "duplicate",
+                    }.into()),
+                    ("target", word(ast, &x.target)),
+                ]))
+                .collect())),
+            ("start", x.start.into()),
+            ("end", x.end.into()),
+        ]),
+
+        Command::Function(x) => Json::object([
+            ("type", "function".into()),
+            ("name", x.name.as_str().into()),
+            ("body", script(ast, &x.body)),
+            ("start", x.start.into()),
+            ("end", x.end.into()),
+        ]),
+    }
+}
+
+fn pipeline(ast: &Ast, pipeline: &Pipeline) -> Json {
+    Json::object([
+        ("negated", pipeline.negated.into()),
+        ("commands", Json::Array(ast[pipeline.commands].iter().map(|x| command(ast, x)).collect())),
+    ])
+}
+
+fn and_or(ast: &Ast, and_or: &AndOr) -> Json {
+    let rest = ast[and_or.rest].iter()
+        .map(|(connector, x)| Json::object([
+            ("connector", match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            }.into()),
+            ("pipeline", pipeline(ast, x)),
+        ]))
+        .collect();
+
+    Json::object([
+        ("first", pipeline(ast, &and_or.first)),
+        ("rest", Json::Array(rest)),
+    ])
+}
+
+fn script(ast: &Ast, script: &Script) -> Json {
+    Json::object([("commands", Json::Array(ast[script.commands].iter().map(|x| and_or(ast, x)).collect()))])
+}
+
+/// `{"tokens": [...]}` or `{"error": {...}}`
+pub fn tokens(source: &str, posix: bool) -> Json {
+    match tokenize(Rc::new(source.to_string()), posix) {
+        Ok(tokens) => Json::object([("tokens", Json::Array(tokens.iter().map(token).collect()))]),
+        Err(err) => error(&err.message, err.start, err.end),
+    }
+}
+
+/// `{"ast": {...}}` or `{"error": {...}}`
+pub fn ast(source: &str, posix: bool) -> Json {
+    match parse(Lexer::new(Rc::new(source.to_string()), posix)) {
+        Ok(x) => Json::object([("ast", script(&x, &x.script))]),
+        Err(ParseError { message, start, end, .. }) => error(&message, start, end),
+    }
+}
+
+/// Findings of the linter, including syntax errors
+pub fn diagnostics(source: &str, posix: bool) -> Json {
+    Json::Array(lint(source, posix).iter().map(|x| x.to_json("-", source)).collect())
+}
+
+/// `{"commands": [...]}` with the fields every simple command outside of
+/// functions expands to, or `{"error": {...}}`
+///
+/// Nothing is run, only assignments without a command name are applied so the
+/// commands after them see the variables
+pub fn expansion(source: &str, posix: bool) -> Json {
+    let ast = match parse(Lexer::new(Rc::new(source.to_string()), posix)) {
+        Ok(x) => x,
+        Err(ParseError { message, start, end, .. }) => return error(&message, start, end),
+    };
+
+    let mut shell = Shell::new(posix);
+    let mut commands = vec![];
+
+    let pipelines = ast[ast.script.commands].iter().flat_map(|x| std::iter::once(&x.first).chain(ast[x.rest].iter().map(|(_, x)| x)));
+    for command in pipelines.flat_map(|x| &ast[x.commands]) {
+        let Command::Simple(command) = command else {
+            continue;
+        };
+
+        let fields: Vec<Json> = ast[command.words].iter()
+            .flat_map(|x| shell.expand_fields(&ast[x.parts]))
+            .map(Json::String)
+            .collect();
+
+        if fields.is_empty() {
+            for assignment in &ast[command.assignments] {
+                let value = shell.expand_word(&ast[assignment.value.parts]);
+                shell.set_var(assignment.name.as_str(), &value);
+            }
+        }
+
+        commands.push(Json::object([
+            ("fields", Json::Array(fields)),
+            ("start", command.start.into()),
+            ("end", command.end.into()),
+        ]));
+    }
+
+    Json::object([("commands", Json::Array(commands))])
+}
+
+/// Exports for JavaScript
+///
+/// Input is written into memory from `rush_alloc` and freed with `rush_free`,
+/// functions return length of their JSON result which is then read from
+/// `rush_result`
+///
+/// ```js
+/// const input = new TextEncoder().encode(source);
+/// const ptr = rush.rush_alloc(input.length);
+/// new Uint8Array(rush.memory.buffer, ptr, input.length).set(input);
+/// const len = rush.rush_ast(ptr, input.length, 0);
+/// rush.rush_free(ptr, input.length);
+/// const ast = JSON.parse(new TextDecoder().decode(new Uint8Array(rush.memory.buffer, rush.rush_result(), len)));
+/// ```
+#[cfg(target_family = "wasm")]
+pub mod wasm {
+    use std::cell::RefCell;
+
+    use crate::json::Json;
+
+    thread_local! {
+        /// Result of the last call, kept until the next one
+        static RESULT: RefCell<String> = const { RefCell::new(String::new()) };
+    }
+
+    #[no_mangle]
+    pub extern "C" fn rush_alloc(len: usize) -> *mut u8 {
+        let mut buffer = Vec::<u8>::with_capacity(len);
+        let ptr = buffer.as_mut_ptr();
+        std::mem::forget(buffer);
+
+        ptr
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to come from `rush_alloc` with the same `len`
+    #[no_mangle]
+    pub unsafe extern "C" fn rush_free(ptr: *mut u8, len: usize) {
+        drop(Vec::from_raw_parts(ptr, 0, len));
+    }
+
+    #[no_mangle]
+    pub extern "C" fn rush_result() -> *const u8 {
+        RESULT.with(|x| x.borrow().as_ptr())
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to point to `len` bytes
+    unsafe fn call(ptr: *const u8, len: usize, f: impl FnOnce(&str) -> Json) -> usize {
+        let source = String::from_utf8_lossy(std::slice::from_raw_parts(ptr, len));
+        let result = f(&source).to_string();
+        let len = result.len();
+        RESULT.with(|x| *x.borrow_mut() = result);
+
+        len
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to point to `len` bytes
+    #[no_mangle]
+    pub unsafe extern "C" fn rush_tokens(ptr: *const u8, len: usize, posix: u32) -> usize {
+        call(ptr, len, |x| super::tokens(x, posix != 0))
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to point to `len` bytes
+    #[no_mangle]
+    pub unsafe extern "C" fn rush_ast(ptr: *const u8, len: usize, posix: u32) -> usize {
+        call(ptr, len, |x| super::ast(x, posix != 0))
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to point to `len` bytes
+    #[no_mangle]
+    pub unsafe extern "C" fn rush_diagnostics(ptr: *const u8, len: usize, posix: u32) -> usize {
+        call(ptr, len, |x| super::diagnostics(x, posix != 0))
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` has to point to `len` bytes
+    #[no_mangle]
+    pub unsafe extern "C" fn rush_expansion(ptr: *const u8, len: usize, posix: u32) -> usize {
+        call(ptr, len, |x| super::expansion(x, posix != 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_of_the_source() {
+        assert_eq!(tokens("echo 'a' # b", false).to_string(), concat!(
+            r#"{"tokens":[{"kind":"identifier","text":"echo","start":0,"end":4},"#,
+            r#"{"kind":"string","text":"'a'","start":5,"end":8},"#,
+            r##"{"kind":"comment","text":"# b","start":9,"end":12}]}"##,
+        ));
+
+        // left to the parser to report
+        assert_eq!(tokens("\"a", false).to_string(), r#"{"tokens":[{"kind":"string","text":"\"a","start":0,"end":2}]}"#);
+    }
+
+    #[test]
+    fn syntax_tree() {
+        let tree = ast("A=1 ls \"$x\" >out | wc && f() { :; }", false);
+        let and_or = match tree.get("ast").and_then(|x| x.get("commands")) {
+            Some(Json::Array(x)) => &x[0],
+            x => panic!("no commands in {:?}", x),
+        };
+
+        let Some(Json::Array(commands)) = and_or.get("first").and_then(|x| x.get("commands")) else {
+            panic!("no pipeline in {}", and_or);
+        };
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].get("assignments").unwrap().to_string(), r#"[{"name":"A","value":{"text":"1","parts":[{"literal":"1"}],"start":2,"end":3}}]"#);
+        assert_eq!(commands[0].get("words").unwrap().to_string(), concat!(
+            r#"[{"text":"ls","parts":[{"literal":"ls"}],"start":4,"end":6},"#,
+            r#"{"text":"${x}","parts":[{"variable":"x","quoted":true}],"start":7,"end":11}]"#,
+        ));
+        assert_eq!(commands[0].get("redirects").unwrap().to_string(), r#"[{"fd":1,"kind":"output","target":{"text":"out","parts":[{"literal":"out"}],"start":13,"end":16}}]"#);
+
+        let rest = and_or.get("rest").unwrap().to_string();
+        assert!(rest.starts_with(r#"[{"connector":"&&","pipeline":{"negated":false,"commands":[{"type":"function","name":"f","#), "{}", rest);
+
+        assert_eq!(ast("echo )", false).to_string(), r#"{"error":{"message":"subshells are not supported yet","start":5,"end":6}}"#);
+    }
+
+    #[test]
+    fn lints() {
+        assert_eq!(diagnostics("x=1\necho \"$x\"", false), Json::Array(vec![]));
+
+        let found = diagnostics("echo $x", false).to_string();
+        assert!(found.contains(r#""line":1,"column":6,"end_line":1,"end_column":8,"severity":"warning","code":"unquoted-expansion""#), "{}", found);
+    }
+
+    #[test]
+    fn expansions() {
+        let source = "x='a b'; echo $x \"$x\"; y=$x; echo \"$y\"*; f() { z=1; }; echo $z.";
+        assert_eq!(expansion(source, false).to_string(), concat!(
+            r#"{"commands":[{"fields":[],"start":0,"end":7},"#,
+            r#"{"fields":["echo","a","b","a b"],"start":9,"end":21},"#,
+            r#"{"fields":[],"start":23,"end":27},"#,
+            r#"{"fields":["echo","a b*"],"start":29,"end":39},"#,
+            r#"{"fields":["echo","."],"start":55,"end":63}]}"#,
+        ));
+        assert_eq!(expansion("echo )", false).to_string(), r#"{"error":{"message":"subshells are not supported yet","start":5,"end":6}}"#);
+    }
+}
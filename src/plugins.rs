@@ -0,0 +1,310 @@
+//! Loading of builtins, completers and prompt segments from shared libraries
+//!
+//! Plugins are shared libraries in the `plugins` directory of the config
+//! directory, they have to export two functions
+//!
+//! - `uint32_t rush_plugin_abi_version(void)` returning [`PLUGIN_ABI_VERSION`]
+//! - `void rush_plugin_init(const PluginRegistrar *registrar)` which registers
+//!   everything the plugin provides
+//!
+//! Libraries are never unloaded as the shell keeps pointers to their
+//! functions. Scripts, `-c` and interactive shells only load them the first
+//! time a command is not a function, a builtin of the shell or in `PATH`, or
+//! the prompt shows their segments, so commands in `PATH` win over builtins of
+//! plugins. They are not loaded with `--posix`
+
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::builtins::{Builtin, Context};
+use crate::dirs;
+use crate::exec::Shell;
+
+#[cfg(unix)]
+use std::ffi::CStr;
+
+/// Version of the plugin interface, increased on every incompatible change
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Text output given to plugin functions
+#[repr(C)]
+pub struct PluginOutput {
+    pub data: *mut c_void,
+
+    /// Writes `len` bytes from `buf`
+    pub write: extern "C" fn(data: *mut c_void, buf: *const u8, len: usize),
+}
+
+/// Builtin, gets the arguments without the name and returns the status
+pub type PluginBuiltinFn = extern "C" fn(
+    stdout: *const PluginOutput,
+    stderr: *const PluginOutput,
+    argc: usize,
+    argv: *const *const c_char,
+) -> c_int;
+
+/// Completer of a builtin, writes candidates for the next argument one per
+/// line
+pub type PluginCompleteFn = extern "C" fn(out: *const PluginOutput, argc: usize, argv: *const *const c_char);
+
+/// Prompt segment, writes the text shown before the prompt
+pub type PluginPromptFn = extern "C" fn(out: *const PluginOutput);
+
+/// Passed to `rush_plugin_init`
+#[repr(C)]
+pub struct PluginRegistrar {
+    pub data: *mut c_void,
+
+    /// Registers builtin `name`, the completer is optional
+    pub register_builtin: extern "C" fn(
+        data: *mut c_void,
+        name: *const c_char,
+        builtin: PluginBuiltinFn,
+        complete: Option<PluginCompleteFn>,
+    ),
+
+    pub register_prompt_segment: extern "C" fn(data: *mut c_void, segment: PluginPromptFn),
+}
+
+/// Builtin provided by a plugin
+#[derive(Clone)]
+pub struct PluginBuiltin {
+    name: String,
+    builtin: PluginBuiltinFn,
+    complete: Option<PluginCompleteFn>,
+}
+
+/// Everything the loaded plugins registered
+#[derive(Default, Clone)]
+pub struct Plugins {
+    pub builtins: Vec<PluginBuiltin>,
+    pub prompt_segments: Vec<PluginPromptFn>,
+}
+
+extern "C" fn write_to(data: *mut c_void, buf: *const u8, len: usize) {
+    if buf.is_null() {
+        return;
+    }
+
+    // SAFETY: data is always the writer given in `output` and the plugin
+    // promises buf has len bytes
+    let writer = unsafe { &mut *(data as *mut &mut dyn Write) };
+    let _ = writer.write_all(unsafe { std::slice::from_raw_parts(buf, len) });
+}
+
+/// Calls `f` with output that writes into `writer`
+fn with_output<T>(mut writer: &mut dyn Write, f: impl FnOnce(&PluginOutput) -> T) -> T {
+    let output = PluginOutput {
+        data: &mut writer as *mut &mut dyn Write as *mut c_void,
+        write: write_to,
+    };
+
+    f(&output)
+}
+
+/// Arguments as C strings, arguments containing nul bytes are cut short
+fn c_args(args: &[String]) -> (Vec<CString>, Vec<*const c_char>) {
+    let strings: Vec<CString> = args.iter()
+        .map(|x| CString::new(x.split('\0').next().unwrap_or_default()).unwrap_or_default())
+        .collect();
+    let pointers = strings.iter().map(|x| x.as_ptr()).collect();
+
+    (strings, pointers)
+}
+
+impl Builtin for PluginBuiltin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn invoke(&self, ctx: &mut Context, args: &[String]) -> i32 {
+        let (_strings, argv) = c_args(args);
+
+        with_output(ctx.stdout, |stdout| {
+            with_output(ctx.stderr, |stderr| {
+                (self.builtin)(stdout, stderr, argv.len(), argv.as_ptr())
+            })
+        })
+    }
+
+    fn complete(&self, _shell: &Shell, args: &[String]) -> Vec<String> {
+        let Some(complete) = self.complete else {
+            return vec![];
+        };
+
+        let (_strings, argv) = c_args(args);
+        let mut buffer: Vec<u8> = vec![];
+        with_output(&mut buffer, |out| complete(out, argv.len(), argv.as_ptr()));
+
+        String::from_utf8_lossy(&buffer)
+            .lines()
+            .map(|x| x.to_string())
+            .collect()
+    }
+}
+
+impl Plugins {
+    /// Text of all the prompt segments
+    pub fn prompt(&self) -> String {
+        let mut buffer: Vec<u8> = vec![];
+        for segment in &self.prompt_segments {
+            with_output(&mut buffer, |out| segment(out));
+        }
+
+        String::from_utf8_lossy(&buffer).into_owned()
+    }
+
+    /// The builtins to register in the shell
+    pub fn to_builtins(&self) -> Vec<Rc<dyn Builtin>> {
+        self.builtins.iter().map(|x| Rc::new(x.clone()) as Rc<dyn Builtin>).collect()
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn register_builtin(data: *mut c_void, name: *const c_char, builtin: PluginBuiltinFn, complete: Option<PluginCompleteFn>) {
+    if name.is_null() {
+        return;
+    }
+
+    // SAFETY: data is the `Plugins` being loaded into and name is a C string
+    let plugins = unsafe { &mut *(data as *mut Plugins) };
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+
+    plugins.builtins.push(PluginBuiltin { name, builtin, complete });
+}
+
+#[cfg(unix)]
+extern "C" fn register_prompt_segment(data: *mut c_void, segment: PluginPromptFn) {
+    // SAFETY: data is the `Plugins` being loaded into
+    let plugins = unsafe { &mut *(data as *mut Plugins) };
+    plugins.prompt_segments.push(segment);
+}
+
+#[cfg(unix)]
+mod dl {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub const RTLD_NOW: c_int = 2;
+
+    // part of libc on newer glibc and the BSDs, older glibc has it separate
+    #[link(name = "dl")]
+    extern "C" {
+        pub fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        pub fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        pub fn dlerror() -> *mut c_char;
+    }
+}
+
+#[cfg(unix)]
+fn dl_error() -> String {
+    // SAFETY: dlerror returns null or a valid C string
+    let err = unsafe { dl::dlerror() };
+    if err.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(err) }.to_string_lossy().into_owned()
+    }
+}
+
+/// Loads the plugin at `path` into `plugins`
+///
+/// # Safety
+///
+/// Loading the library runs its constructors and `rush_plugin_init`, and the
+/// functions it registers are called later, so the library has to be a rush
+/// plugin built for [`PLUGIN_ABI_VERSION`] that does what its functions are
+/// documented to do. Nothing of that can be checked here
+#[cfg(unix)]
+pub unsafe fn load(path: &Path, plugins: &mut Plugins) -> Result<(), String> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path_c = CString::new(path.as_os_str().as_bytes()).map_err(|x| x.to_string())?;
+
+    // SAFETY: the caller promises the library is a plugin
+    let handle = unsafe { dl::dlopen(path_c.as_ptr(), dl::RTLD_NOW) };
+    if handle.is_null() {
+        return Err(dl_error());
+    }
+
+    let symbol = |name: &CStr| {
+        // SAFETY: handle is a valid library
+        let symbol = unsafe { dl::dlsym(handle, name.as_ptr()) };
+        if symbol.is_null() {
+            Err(format!("{} not found", name.to_string_lossy()))
+        } else {
+            Ok(symbol)
+        }
+    };
+
+    let version = symbol(c"rush_plugin_abi_version")?;
+    let init = symbol(c"rush_plugin_init")?;
+
+    // SAFETY: the symbols have the documented signatures
+    let version: extern "C" fn() -> u32 = unsafe { std::mem::transmute(version) };
+    let init: extern "C" fn(*const PluginRegistrar) = unsafe { std::mem::transmute(init) };
+
+    let version = version();
+    if version != PLUGIN_ABI_VERSION {
+        return Err(format!("ABI version {} is not supported, expected {}", version, PLUGIN_ABI_VERSION));
+    }
+
+    let registrar = PluginRegistrar {
+        data: plugins as *mut Plugins as *mut c_void,
+        register_builtin,
+        register_prompt_segment,
+    };
+
+    init(&registrar);
+
+    Ok(())
+}
+
+/// Loads the plugin at `path` into `plugins`
+///
+/// # Safety
+///
+/// Always fails without loading anything, it is unsafe to match the one on
+/// unix
+#[cfg(not(unix))]
+pub unsafe fn load(_path: &Path, _plugins: &mut Plugins) -> Result<(), String> {
+    Err("plugins are only supported on unix".to_string())
+}
+
+/// `plugins` in the config directory, overridden by `$RUSH_PLUGIN_DIR`
+pub fn plugin_dir() -> Option<PathBuf> {
+    match std::env::var_os("RUSH_PLUGIN_DIR") {
+        Some(x) => Some(PathBuf::from(x)),
+        None => dirs::config_dir().map(|x| x.join("plugins")),
+    }
+}
+
+/// Loads all shared libraries in the plugin directory, in order of their
+/// names, errors are reported with the path and do not stop the loading
+///
+/// The libraries are trusted the same as the rc file, whoever can write to
+/// the plugin directory can run code in the shell anyway
+pub fn load_all() -> (Plugins, Vec<String>) {
+    let mut plugins = Plugins::default();
+    let mut errors = vec![];
+
+    let Some(entries) = plugin_dir().and_then(|x| std::fs::read_dir(x).ok()) else {
+        return (plugins, errors);
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| x.extension().is_some_and(|x| x == std::env::consts::DLL_EXTENSION))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        // SAFETY: the plugin directory is trusted, see above
+        if let Err(err) = unsafe { load(&path, &mut plugins) } {
+            errors.push(format!("{}: {}", path.display(), err));
+        }
+    }
+
+    (plugins, errors)
+}
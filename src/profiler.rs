@@ -0,0 +1,297 @@
+//! Profiler for scripts, started with `rush --profile script`
+//!
+//! Records wall time, CPU time and counts of every command by its location
+//! and of every function, then prints a report sorted by wall time to stderr
+//!
+//! CPU time is only measured on linux, it is the time of the shell and the
+//! commands it waited for
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::exec::{Hook, Shell};
+use crate::parser::SimpleCommand;
+use crate::symbol::Symbol;
+
+/// Rows shown in each table of the report
+const REPORT_ROWS: usize = 20;
+
+/// `struct rusage` is declared for the linux ABI of glibc and musl, x32 has
+/// 64 bit times with 32 bit longs so it is left out
+#[cfg(all(target_os = "linux", any(target_env = "gnu", target_env = "musl"), not(target_abi = "x32")))]
+mod rusage {
+    use std::ffi::{c_int, c_long};
+    use std::time::Duration;
+
+    const RUSAGE_SELF: c_int = 0;
+    const RUSAGE_CHILDREN: c_int = -1;
+
+    #[repr(C)]
+    struct Timeval {
+        tv_sec: c_long,
+        tv_usec: c_long,
+    }
+
+    /// Two timevals followed by 14 longs, as the kernel declares it
+    #[repr(C)]
+    struct Rusage {
+        ru_utime: Timeval,
+        ru_stime: Timeval,
+
+        // the other fields are not used
+        _rest: [c_long; 14],
+    }
+
+    extern "C" {
+        fn getrusage(who: c_int, usage: *mut Rusage) -> c_int;
+    }
+
+    fn cpu(who: c_int) -> Duration {
+        // SAFETY: getrusage only writes into the struct
+        let mut usage: Rusage = unsafe { std::mem::zeroed() };
+        if unsafe { getrusage(who, &mut usage) } != 0 {
+            return Duration::ZERO;
+        }
+
+        let time = |x: &Timeval| Duration::from_secs(x.tv_sec as u64) + Duration::from_micros(x.tv_usec as u64);
+        time(&usage.ru_utime) + time(&usage.ru_stime)
+    }
+
+    /// User and system time of the shell and its reaped children
+    pub fn cpu_time() -> Option<Duration> {
+        Some(cpu(RUSAGE_SELF) + cpu(RUSAGE_CHILDREN))
+    }
+}
+
+#[cfg(not(all(target_os = "linux", any(target_env = "gnu", target_env = "musl"), not(target_abi = "x32"))))]
+mod rusage {
+    pub fn cpu_time() -> Option<std::time::Duration> {
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    count: usize,
+    wall: Duration,
+    cpu: Option<Duration>,
+}
+
+impl Stats {
+    fn add(&mut self, wall: Duration, cpu: Option<Duration>) {
+        self.count += 1;
+        self.wall += wall;
+        self.cpu = cpu.map(|x| x + self.cpu.unwrap_or_default());
+    }
+}
+
+/// Function being run, with the times when it was entered
+struct Call {
+    start: Instant,
+    cpu: Option<Duration>,
+}
+
+pub struct Profiler {
+    /// Commands by file and byte index, with their line and text
+    commands: HashMap<(String, usize), (usize, String, Stats)>,
+
+    functions: HashMap<String, Stats>,
+    calls: Vec<Call>,
+
+    /// CPU time when the last command started or finished, commands of a
+    /// pipeline run at the same time but are reaped one by one so the time
+    /// since the last one is what the reaped command used
+    checkpoint: Option<Duration>,
+
+    start: Instant,
+}
+
+fn format_cpu(cpu: Option<Duration>) -> String {
+    match cpu {
+        Some(x) => format!("{:.3}s", x.as_secs_f64()),
+        None => "-".to_string(),
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            commands: HashMap::new(),
+            functions: HashMap::new(),
+            calls: vec![],
+            checkpoint: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Report sorted by wall time, the slowest first
+    pub fn report(&self) -> String {
+        let mut report = format!("profile: {:.3}s total\n\n", self.start.elapsed().as_secs_f64());
+
+        // ties are in the order of the source
+        let mut commands: Vec<_> = self.commands.iter().collect();
+        commands.sort_by_key(|((file, start), (_, _, stats))| (Reverse(stats.wall), file, *start));
+
+        report.push_str(&format!("{:>10} {:>10} {:>6}  command\n", "wall", "cpu", "count"));
+        for ((file, _), (line, text, stats)) in commands.into_iter().take(REPORT_ROWS) {
+            report.push_str(&format!(
+                "{:>9.3}s {:>10} {:>6}  {}:{}: {}\n",
+                stats.wall.as_secs_f64(),
+                format_cpu(stats.cpu),
+                stats.count,
+                file,
+                line,
+                text,
+            ));
+        }
+
+        if !self.functions.is_empty() {
+            let mut functions: Vec<_> = self.functions.iter().collect();
+            functions.sort_by_key(|(name, stats)| (Reverse(stats.wall), *name));
+
+            report.push_str(&format!("\n{:>10} {:>10} {:>6}  function\n", "wall", "cpu", "calls"));
+            for (name, stats) in functions.into_iter().take(REPORT_ROWS) {
+                report.push_str(&format!(
+                    "{:>9.3}s {:>10} {:>6}  {}\n",
+                    stats.wall.as_secs_f64(),
+                    format_cpu(stats.cpu),
+                    stats.count,
+                    name,
+                ));
+            }
+        }
+
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hook for Profiler {
+    fn before_command(&mut self, _shell: &mut Shell, _command: &SimpleCommand) {
+        self.checkpoint = rusage::cpu_time();
+    }
+
+    fn after_command(&mut self, shell: &mut Shell, command: &SimpleCommand, argv: &[String], elapsed: Duration, _status: i32) {
+        let now = rusage::cpu_time();
+        let cpu = now.zip(self.checkpoint).map(|(now, checkpoint)| now.saturating_sub(checkpoint));
+        self.checkpoint = now;
+
+        // function calls are counted with the functions
+        if argv.first().and_then(|x| Symbol::get(x)).is_some_and(|x| shell.functions.contains_key(&x)) {
+            return;
+        }
+
+        let source = shell.source();
+        let text = source.text.get(command.start..command.end).unwrap_or_default();
+        let text = text.lines().next().unwrap_or_default().to_string();
+
+        self.commands.entry((source.name.clone(), command.start))
+            .or_insert_with(|| (source.line(command.start), text, Stats::default()))
+            .2
+            .add(elapsed, cpu);
+    }
+
+    fn enter_function(&mut self, _shell: &mut Shell, _name: &str) {
+        self.calls.push(Call {
+            start: Instant::now(),
+            cpu: rusage::cpu_time(),
+        });
+    }
+
+    fn exit_function(&mut self, _shell: &mut Shell, name: &str, _status: i32) {
+        let Some(call) = self.calls.pop() else {
+            return;
+        };
+
+        let cpu = rusage::cpu_time().zip(call.cpu).map(|(now, start)| now.saturating_sub(start));
+        self.functions.entry(name.to_string()).or_default().add(call.start.elapsed(), cpu);
+    }
+
+    fn finish(&mut self, _shell: &Shell) {
+        eprint!("{}", self.report());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Gives the profiler to the shell while the test keeps it
+    struct Shared(Rc<RefCell<Profiler>>);
+
+    impl Hook for Shared {
+        fn before_command(&mut self, shell: &mut Shell, command: &SimpleCommand) {
+            self.0.borrow_mut().before_command(shell, command);
+        }
+
+        fn after_command(&mut self, shell: &mut Shell, command: &SimpleCommand, argv: &[String], elapsed: Duration, status: i32) {
+            self.0.borrow_mut().after_command(shell, command, argv, elapsed, status);
+        }
+
+        fn enter_function(&mut self, shell: &mut Shell, name: &str) {
+            self.0.borrow_mut().enter_function(shell, name);
+        }
+
+        fn exit_function(&mut self, shell: &mut Shell, name: &str, status: i32) {
+            self.0.borrow_mut().exit_function(shell, name, status);
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut shell = Shell::new(false);
+        shell.hook = Some(Box::new(Shared(profiler.clone())));
+        shell.run_str("f() { true; g; }\ng() { :; }\nf\nf\ntrue\n");
+
+        let profiler = profiler.borrow();
+        let mut commands: Vec<_> = profiler.commands.values().map(|(line, text, stats)| (*line, text.as_str(), stats.count)).collect();
+        commands.sort();
+
+        // the calls of f and g are counted as functions
+        assert_eq!(commands, [(1, "true", 2), (2, ":", 2), (5, "true", 1)]);
+        assert_eq!(profiler.functions["f"].count, 2);
+        assert_eq!(profiler.functions["g"].count, 2);
+    }
+
+    #[test]
+    fn stats() {
+        let mut stats = Stats::default();
+        stats.add(Duration::from_millis(2), Some(Duration::from_millis(1)));
+        stats.add(Duration::from_millis(3), Some(Duration::from_millis(1)));
+        assert_eq!((stats.count, stats.wall, stats.cpu), (2, Duration::from_millis(5), Some(Duration::from_millis(2))));
+
+        let mut stats = Stats::default();
+        stats.add(Duration::ZERO, None);
+        assert_eq!((stats.count, stats.cpu), (1, None));
+    }
+
+    #[test]
+    fn report() {
+        let mut profiler = Profiler::new();
+        for i in 0..25 {
+            let mut stats = Stats::default();
+            stats.add(Duration::from_millis(if i == 24 { 5 } else { 0 }), None);
+            profiler.commands.insert(("a.sh".to_string(), i), (i + 1, format!("cmd{}", i), stats));
+        }
+
+        profiler.functions.insert("f".to_string(), Stats { count: 3, wall: Duration::ZERO, cpu: Some(Duration::ZERO) });
+
+        let report = profiler.report();
+        let lines: Vec<&str> = report.lines().skip(2).collect();
+        assert_eq!(lines[0], "      wall        cpu  count  command");
+        assert_eq!(lines[1], "    0.005s          -      1  a.sh:25: cmd24");
+        assert_eq!(lines[2], "    0.000s          -      1  a.sh:1: cmd0");
+        assert_eq!(lines[20], "    0.000s          -      1  a.sh:19: cmd18");
+        assert_eq!(&lines[21..], ["", "      wall        cpu  calls  function", "    0.000s     0.000s      3  f"]);
+    }
+}
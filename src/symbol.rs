@@ -0,0 +1,134 @@
+//! Interned names, each is stored once and compared and hashed by its id
+//!
+//! Used for identifiers in the tokens, names in the syntax tree and keys of
+//! variables, functions and builtins so looking them up does not allocate
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// Id of an interned name, only valid on the thread that created it so it
+/// is neither `Send` nor `Sync`
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    id: u32,
+    thread: PhantomData<*const ()>,
+}
+
+#[derive(Default)]
+struct Interner {
+    /// Owned by the interner and freed with it when the thread exits
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, Symbol>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    /// Symbol of the name, adding it if it is new
+    pub fn intern(name: &str) -> Self {
+        INTERNER.with(|x| {
+            let mut interner = x.borrow_mut();
+            if let Some(symbol) = interner.ids.get(name) {
+                return *symbol;
+            }
+
+            let name: Rc<str> = name.into();
+            let symbol = Symbol {
+                id: interner.names.len() as u32,
+                thread: PhantomData,
+            };
+
+            interner.names.push(name.clone());
+            interner.ids.insert(name, symbol);
+
+            symbol
+        })
+    }
+
+    /// Symbol of the name if it was interned, nothing named by a name that
+    /// was never interned can exist
+    pub fn get(name: &str) -> Option<Self> {
+        INTERNER.with(|x| x.borrow().ids.get(name).copied())
+    }
+
+    pub fn as_str(&self) -> &str {
+        INTERNER.with(|x| {
+            let name: *const str = &*x.borrow().names[self.id as usize];
+
+            // SAFETY: names are never removed, the string does not move when
+            // the vector grows and it is only freed with the interner when
+            // the thread exits, the symbol can not leave the thread so the
+            // name outlives the borrow of it
+            unsafe { &*name }
+        })
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_once() {
+        let a = Symbol::intern("symbol_test_name");
+        assert_eq!(a, Symbol::intern("symbol_test_name"));
+        assert_ne!(a, Symbol::intern("symbol_test_other"));
+        assert_eq!(a.as_str(), "symbol_test_name");
+        assert_eq!(a, "symbol_test_name");
+        assert_eq!(Symbol::get("symbol_test_name"), Some(a));
+        assert_eq!(Symbol::get("symbol_test_never_interned"), None);
+    }
+
+    #[test]
+    fn names_stay_valid_as_more_are_added() {
+        let first = Symbol::intern("symbol_test_first");
+        let name = first.as_str();
+        for i in 0..1000 {
+            Symbol::intern(&format!("symbol_test_{}", i));
+        }
+
+        assert_eq!(name, "symbol_test_first");
+    }
+
+    #[test]
+    fn each_thread_has_its_own() {
+        let name = std::thread::spawn(|| Symbol::intern("symbol_test_thread").as_str().to_string());
+        assert_eq!(name.join().unwrap(), "symbol_test_thread");
+        assert_eq!(Symbol::get("symbol_test_thread"), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Copy-on-write map for the state of the shell
+//!
+//! Cloning a table is only a reference count, so a subshell starts with the
+//! variables and functions of its parent for free. The first change to either
+//! copy copies the table of pointers, entries are shared until they are
+//! changed themselves.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::rc::Rc;
+
+pub struct Table<K, V> {
+    map: Rc<HashMap<K, Rc<V>>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Table<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key).map(|x| x.as_ref())
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Entry to change, copied first if it is shared with another table
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        // not copying the table when there is nothing to change
+        self.map.get(key)?;
+        Rc::make_mut(&mut self.map).get_mut(key).map(Rc::make_mut)
+    }
+
+    /// Entry to change, inserted with `f` if there is none
+    pub fn get_or_insert_with(&mut self, key: K, f: impl FnOnce() -> V) -> &mut V {
+        let entry = Rc::make_mut(&mut self.map).entry(key).or_insert_with(|| Rc::new(f()));
+        Rc::make_mut(entry)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        Rc::make_mut(&mut self.map).insert(key, Rc::new(value));
+    }
+
+    /// Removes the entry, returns whether there was one
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if !self.map.contains_key(key) {
+            return false;
+        }
+
+        Rc::make_mut(&mut self.map).remove(key).is_some()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.map.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter().map(|(key, value)| (key, value.as_ref()))
+    }
+}
+
+// derives would require K and V to implement them
+impl<K, V> Clone for Table<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for Table<K, V> {
+    fn default() -> Self {
+        Self {
+            map: Rc::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Table<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_not_shared() {
+        let mut parent = Table::new();
+        parent.insert("a", 1);
+        parent.insert("b", 2);
+
+        let mut child = parent.clone();
+        *child.get_mut("a").unwrap() = 10;
+        child.insert("c", 3);
+        assert!(child.remove("b"));
+
+        assert_eq!(parent.get("a"), Some(&1));
+        assert_eq!(parent.get("b"), Some(&2));
+        assert!(!parent.contains_key("c"));
+
+        assert_eq!(child.get("a"), Some(&10));
+        assert!(!child.contains_key("b"));
+        assert_eq!(child.len(), 2);
+    }
+
+    #[test]
+    fn clones_share_until_changed() {
+        let mut parent = Table::new();
+        parent.insert("a", String::from("x"));
+
+        let mut child = parent.clone();
+        assert!(Rc::ptr_eq(&parent.map, &child.map));
+
+        // nothing to change so nothing is copied
+        assert!(child.get_mut("missing").is_none());
+        assert!(!child.remove("missing"));
+        assert!(Rc::ptr_eq(&parent.map, &child.map));
+
+        // untouched entries are still shared after the copy
+        child.insert("b", String::from("y"));
+        assert!(!Rc::ptr_eq(&parent.map, &child.map));
+        assert!(Rc::ptr_eq(&parent.map["a"], &child.map["a"]));
+    }
+
+    #[test]
+    fn get_or_insert_with() {
+        let mut table = Table::new();
+        table.get_or_insert_with("a", Vec::new).push(1);
+        table.get_or_insert_with("a", Vec::new).push(2);
+        assert_eq!(table.get("a"), Some(&vec![1, 2]));
+    }
+}
@@ -4,6 +4,8 @@ use strum::EnumString;
 use std::rc::Rc;
 use std::str::FromStr;
 
+use crate::symbol::Symbol;
+
 #[derive(Debug, PartialEq, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum Keyword {
@@ -18,9 +20,9 @@ pub enum Token {
     Keyword(Keyword),
 
     /// Identifier, [A-Za-z0-9_] basically
-    Identifier(String),
+    Identifier(Symbol),
 
-    /// Integer, always signed
+    /// Integer, always signed, digits that do not fit are a [`Token::Symbol`]
     Integer(i64),
 
     // /// Float, non posix compliant but eh
@@ -35,20 +37,24 @@ pub enum Token {
     /// Parentheses, {} [] ()
     Paren(char),
 
-    /// Any kind of operator, ==, ! ~ % ^ & > >>
+    /// Any kind of operator, ==, ! ~ % ^ & > >>, and other text that is
+    /// none of the above
     Symbol(String),
 
     /// Newline with line it was on
     Newline(usize),
+
+    /// Comment till the end of the line, without the `#`
+    Comment(String),
 }
 
 /// Token type which stores position of the token and reference to the buffer
 #[derive(Debug)]
 pub struct TokenWithInfo {
-    /// Index of start of the token
+    /// Byte index of start of the token
     pub start: usize,
 
-    /// Index of end of the token
+    /// Byte index of end of the token
     pub end: usize,
 
     /// Reference to original buffer where it was parsed
@@ -58,183 +64,356 @@ pub struct TokenWithInfo {
     pub token: Token,
 }
 
-pub fn tokenize(string: Rc<String>) -> Result<Vec<TokenWithInfo>, ()> {
-    let mut tokens: Vec<TokenWithInfo> = vec![];
-    let mut iter = string.chars().into_iter().enumerate().peekable();
-    let mut line = 1;
-    while let Some((i, ch)) = iter.next() {
-        match ch {
-            '\n' => {
-                tokens.push(TokenWithInfo {
-                    start: i,
-                    end: i + 1,
-                    buffer: string.clone(),
-                    token: Token::Newline(line),
-                });
-
-                line += 1;
-            },
+impl TokenWithInfo {
+    /// Text of the token as it was in the buffer
+    pub fn raw(&self) -> &str {
+        &self.buffer[self.start..self.end]
+    }
+}
 
-            // TODO support unicode maybe?
-            // start identifier if valid starting character
-            'a'..='z' | 'A'..='Z' | '_' => {
-                let mut identifier = ch.to_string();
+#[derive(Debug, Clone)]
+pub struct TokenizeError {
+    pub message: String,
 
-                // check next characters and build the identifier char by char
-                while let Some((_, next)) = iter.peek() {
-                    match next {
-                        'a'..='z' | 'A'..='Z' | '_' | '0'..='9' => {
-                            identifier.push(iter.next().unwrap().1);
-                        },
-                        _ => break,
-                    }
-                }
+    /// Byte index of start of the error
+    pub start: usize,
 
-                // test if it is a keyword otherwise save as an identifier
-                match Keyword::from_str(identifier.as_str()) {
-                    Ok(keyword) => tokens.push(TokenWithInfo {
-                        start: i,
-                        end: i + identifier.len(),
-                        buffer: string.clone(),
-                        token: Token::Keyword(keyword),
-                    }),
-                    Err(_) => tokens.push(TokenWithInfo {
-                        start: i,
-                        end: i + identifier.len(),
-                        buffer: string.clone(),
-                        token: Token::Identifier(identifier),
-                    }),
-                }
-            },
+    /// Byte index of end of the error
+    pub end: usize,
+}
 
-            // TODO support negative numbers
-            '0'..='9' => {
-                let mut raw = ch.to_string();
-                let mut integer: i64;
+/// Tokenizer reading one token at a time from the buffer, as they are asked
+/// for, stops after the first error
+pub struct Lexer {
+    buffer: Rc<String>,
+    posix: bool,
 
-                match iter.peek() {
-                    // hex
-                    Some((_, 'x')) => {
-                        raw.push(iter.next().unwrap().1);
+    /// Byte index of the next character
+    pos: usize,
 
-                        while let Some((_, next)) = iter.peek() {
-                            match next {
-                                '0'..='9' | 'a'..='f' | 'A'..='F' => raw.push(iter.next().unwrap().1),
-                                _ => break,
-                            }
-                        }
+    /// Line of the next character
+    line: usize,
+
+    /// End of the previous token and whether it was a newline
+    previous: Option<(usize, bool)>,
+}
 
-                        // it should not be possible to panic here
-                        // NOTE: from_str_radix does not allow 0x prefix
-                        integer = i64::from_str_radix(&raw[2..], 16).unwrap();
-                    },
+impl Lexer {
+    /// With `posix` set extensions are not recognized, so hex integers and
+    /// operators like `==` or `+=` are split into plain symbols like `sh`
+    /// would
+    pub fn new(buffer: Rc<String>, posix: bool) -> Self {
+        Self {
+            buffer,
+            posix,
+            pos: 0,
+            line: 1,
+            previous: None,
+        }
+    }
 
-                    // TODO binary
+    /// Whether extensions are left out
+    pub fn posix(&self) -> bool {
+        self.posix
+    }
 
-                    // decimal
-                    Some((_, '0'..='9')) => {
-                        while let Some((_, next)) = iter.peek() {
-                            match next {
-                                '0'..='9' => raw.push(iter.next().unwrap().1),
-                                _ => break,
-                            }
+    fn token(&mut self) -> Result<Option<TokenWithInfo>, TokenizeError> {
+        let string = self.buffer.clone();
+        let posix = self.posix;
+        let offset = self.pos;
+        let mut iter = string[offset..].char_indices().map(|(i, ch)| (offset + i, ch)).peekable();
+        while let Some((i, ch)) = iter.next() {
+            let (end, token) = match ch {
+                '\n' => {
+                    self.line += 1;
+                    (i + 1, Token::Newline(self.line - 1))
+                },
+
+                // TODO support unicode maybe?
+                // start identifier if valid starting character
+                'a'..='z' | 'A'..='Z' | '_' => {
+                    let mut identifier = ch.to_string();
+
+                    // check next characters and build the identifier char by char
+                    while let Some((_, next)) = iter.peek() {
+                        match next {
+                            'a'..='z' | 'A'..='Z' | '_' | '0'..='9' => {
+                                identifier.push(iter.next().unwrap().1);
+                            },
+                            _ => break,
                         }
+                    }
 
-                        integer = i64::from_str(&raw).unwrap();
-                    },
+                    // test if it is a keyword otherwise save as an identifier
+                    let token = match Keyword::from_str(identifier.as_str()) {
+                        Ok(keyword) => Token::Keyword(keyword),
+                        Err(_) => Token::Identifier(Symbol::intern(&identifier)),
+                    };
+
+                    (i + identifier.len(), token)
+                },
+
+                // TODO support negative numbers
+                '0'..='9' => {
+                    let mut raw = ch.to_string();
+                    let integer = match iter.peek() {
+                        // hex, not a thing in posix
+                        Some((_, 'x')) if !posix && ch == '0' => {
+                            raw.push(iter.next().unwrap().1);
 
-                    // basically single digit decimal
-                    _ => {
-                        integer = i64::from_str(&raw).unwrap();
-                    }
-                }
+                            while let Some((_, next)) = iter.peek() {
+                                match next {
+                                    '0'..='9' | 'a'..='f' | 'A'..='F' => raw.push(iter.next().unwrap().1),
+                                    _ => break,
+                                }
+                            }
 
-                // save the token with its value
-                tokens.push(TokenWithInfo {
-                    start: i,
-                    end: i + raw.len(),
-                    buffer: string.clone(),
-                    token: Token::Integer(integer)
-                });
-            },
+                            match raw.len() {
+                                // `0x` without digits is only text
+                                2 => Some(0),
 
-            // parens
-            '{' | '}' | '(' | ')' | '[' | ']' => {
-                tokens.push(TokenWithInfo {
-                    start: i,
-                    end: i + 1,
-                    buffer: string.clone(),
-                    token: Token::Paren(ch)
-                });
-            },
+                                // NOTE: from_str_radix does not allow 0x prefix
+                                _ => i64::from_str_radix(&raw[2..], 16).ok(),
+                            }
+                        },
 
-            // string, including backtick
-            '"' | '\'' | '`' => {
-                let mut raw = ch.to_string();
+                        // TODO binary
 
-                loop {
-                    match iter.peek() {
-                        // stop on newline TODO \ continouation
-                        // Some((_, '\n')) => break,
-                        // TODO should this be an error?
+                        // decimal
+                        Some((_, '0'..='9')) => {
+                            while let Some((_, next)) = iter.peek() {
+                                match next {
+                                    '0'..='9' => raw.push(iter.next().unwrap().1),
+                                    _ => break,
+                                }
+                            }
 
-                        // stop only on the same kind of quote
-                        Some((_, str_ch)) if *str_ch == ch => {
-                            raw.push(iter.next().unwrap().1);
-                            break;
+                            // can overflow
+                            i64::from_str(&raw).ok()
                         },
 
-                        // add other characters
-                        Some((_, _)) => {
-                            raw.push(iter.next().unwrap().1);
+                        // basically single digit decimal
+                        _ => {
+                            i64::from_str(&raw).ok()
                         }
+                    };
 
-                        // eof so just stop
-                        _ => break,
+                    // digits that do not fit are still a valid word
+                    let end = i + raw.len();
+                    match integer {
+                        Some(x) => (end, Token::Integer(x)),
+                        None => (end, Token::Symbol(raw)),
                     }
-                }
+                },
+
+                // parens
+                '{' | '}' | '(' | ')' | '[' | ']' => (i + 1, Token::Paren(ch)),
+
+                // string, including backtick
+                '"' | '\'' | '`' => {
+                    let mut raw = ch.to_string();
+
+                    loop {
+                        match iter.peek() {
+                            // stop on newline TODO \ continouation
+                            // Some((_, '\n')) => break,
+                            // TODO should this be an error?
+
+                            // stop only on the same kind of quote
+                            Some((_, str_ch)) if *str_ch == ch => {
+                                raw.push(iter.next().unwrap().1);
+                                break;
+                            },
+
+                            // escapes, the escaped character can not end the string
+                            Some((_, '\\')) if ch != '\'' => {
+                                raw.push(iter.next().unwrap().1);
+                                if let Some((_, escaped)) = iter.next() {
+                                    raw.push(escaped);
+                                }
+                            },
+
+                            // add other characters
+                            Some((_, _)) => {
+                                raw.push(iter.next().unwrap().1);
+                            }
 
-                tokens.push(TokenWithInfo {
-                    start: i,
-                    end: i + raw.len(),
-                    buffer: string.clone(),
-                    // only double quote string can use variable substitution
-                    token: Token::String(raw, ch),
-                });
-            }
-
-            // all symbols in ascii
-            '!'..='/' | ':'..='@' | '['..='`' | '{'..='~' => {
-                let mut symbol = ch.to_string();
-
-                // combine symbols like >> || && etc
-                // TODO match <<<
-                if let Some((_, next)) = iter.peek() {
-                    match (ch.to_string() + &next.to_string()).as_str() {
-                        ">>" | "<<" | "==" | "!=" | "<=" | ">=" | "&&" | "||" | "+=" | "-=" => {
-                            symbol.push(iter.next().unwrap().1);
-                        },
-                        _ => {},
+                            // eof so just stop
+                            _ => break,
+                        }
                     }
+
+                    // only double quote string can use variable substitution
+                    (i + raw.len(), Token::String(raw, ch))
                 }
 
-                tokens.push(TokenWithInfo {
-                    start: i,
-                    end: i + symbol.len(),
-                    buffer: string.clone(),
-                    token: Token::Symbol(symbol)
-                });
-            },
+                // comment only if it starts a word
+                '#' if self.previous.is_none_or(|(end, newline)| end != i || newline) => {
+                    let mut comment = String::new();
+
+                    while let Some((_, next)) = iter.peek() {
+                        match next {
+                            '\n' => break,
+                            _ => comment.push(iter.next().unwrap().1),
+                        }
+                    }
+
+                    (i + 1 + comment.len(), Token::Comment(comment))
+                },
+
+                // escaped character, escaped newline is a line continuation
+                '\\' => {
+                    let mut symbol = ch.to_string();
+                    if let Some((_, next)) = iter.next() {
+                        if next == '\n' {
+                            self.line += 1;
+                        }
+
+                        symbol.push(next);
+                    }
+
+                    (i + symbol.len(), Token::Symbol(symbol))
+                },
+
+                // all symbols in ascii
+                '!'..='/' | ':'..='@' | '['..='`' | '{'..='~' => {
+                    let mut symbol = ch.to_string();
+
+                    // combine symbols like >> || && etc
+                    // TODO match <<<
+                    if let Some((_, next)) = iter.peek() {
+                        match (ch.to_string() + &next.to_string()).as_str() {
+                            ">>" | "<<" | "&&" | "||" => {
+                                symbol.push(iter.next().unwrap().1);
+                            },
+
+                            // extensions
+                            "==" | "!=" | "<=" | ">=" | "+=" | "-=" if !posix => {
+                                symbol.push(iter.next().unwrap().1);
+                            },
+                            _ => {},
+                        }
+                    }
+
+                    (i + symbol.len(), Token::Symbol(symbol))
+                },
 
-            // ignore whitespace
-            ' ' | '\t' => {},
+                // ignore whitespace
+                ' ' | '\t' => continue,
 
-            _ => {
-                println!("Ignored '{}'", ch);
+                // anything else is kept as is so words stay intact
+                _ => (i + ch.len_utf8(), Token::Symbol(ch.to_string())),
+            };
+
+            self.pos = end;
+            self.previous = Some((end, matches!(token, Token::Newline(_))));
+
+            return Ok(Some(TokenWithInfo {
+                start: i,
+                end,
+                buffer: self.buffer.clone(),
+                token,
+            }));
+        }
+
+        self.pos = self.buffer.len();
+        Ok(None)
+    }
+}
+
+impl Iterator for Lexer {
+    type Item = Result<TokenWithInfo, TokenizeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.token() {
+            Ok(x) => x.map(Ok),
+            Err(err) => {
+                // nothing after an error
+                self.pos = self.buffer.len();
+                Some(Err(err))
             },
         }
     }
+}
+
+/// Tokenizes the whole buffer, see [`Lexer::new`]
+pub fn tokenize(string: Rc<String>, posix: bool) -> Result<Vec<TokenWithInfo>, TokenizeError> {
+    Lexer::new(string, posix).collect()
+}
+
+/// Line and column of the byte index in the buffer, both start at 1
+pub fn line_col(buffer: &str, index: usize) -> (usize, usize) {
+    let before = &buffer[..index.min(buffer.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
 
-    Ok(tokens)
+    (line, column)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str, posix: bool) -> Vec<Token> {
+        tokenize(Rc::new(source.to_string()), posix).unwrap().into_iter().map(|x| x.token).collect()
+    }
+
+    #[test]
+    fn identifiers_and_keywords() {
+        let tokens = tokens("if foo_1 fi", false);
+        assert!(matches!(tokens[0], Token::Keyword(Keyword::If)));
+        assert!(matches!(&tokens[1], Token::Identifier(x) if x == "foo_1"));
+        assert!(matches!(tokens[2], Token::Keyword(Keyword::Fi)));
+    }
+
+    #[test]
+    fn integers() {
+        let tokens = tokens("7 42 0x1f", false);
+        assert!(matches!(tokens[0], Token::Integer(7)));
+        assert!(matches!(tokens[1], Token::Integer(42)));
+        assert!(matches!(tokens[2], Token::Integer(31)));
+    }
+
+    #[test]
+    fn too_large_integers_are_words() {
+        let tokens = tokens("12345678901234567890 0xffffffffffffffffff", false);
+        assert!(matches!(&tokens[0], Token::Symbol(x) if x == "12345678901234567890"));
+        assert!(matches!(&tokens[1], Token::Symbol(x) if x == "0xffffffffffffffffff"));
+    }
+
+    #[test]
+    fn hex_only_outside_posix() {
+        let tokens = tokens("0x1f", true);
+        assert!(matches!(tokens[0], Token::Integer(0)));
+        assert!(matches!(&tokens[1], Token::Identifier(x) if x == "x1f"));
+    }
+
+    #[test]
+    fn extended_operators() {
+        assert!(matches!(&tokens("a==b", false)[1], Token::Symbol(x) if x == "=="));
+        assert!(matches!(&tokens("a==b", true)[1], Token::Symbol(x) if x == "="));
+        assert!(matches!(&tokens("a && b", true)[1], Token::Symbol(x) if x == "&&"));
+    }
+
+    #[test]
+    fn strings_keep_quotes_and_escapes() {
+        let tokens = tokens(r#"'a\' "b\"c""#, false);
+        assert!(matches!(&tokens[0], Token::String(x, '\'') if x == r"'a\'"));
+        assert!(matches!(&tokens[1], Token::String(x, '"') if x == r#""b\"c""#));
+    }
+
+    #[test]
+    fn comments_only_start_words() {
+        let tokens = tokens("a#b # c\nd", false);
+        assert!(matches!(&tokens[1], Token::Symbol(x) if x == "#"));
+        assert!(matches!(&tokens[3], Token::Comment(x) if x == " c"));
+        assert!(matches!(tokens[4], Token::Newline(1)));
+    }
+
+    #[test]
+    fn line_col_counts_from_one() {
+        assert_eq!(line_col("ab\ncd", 0), (1, 1));
+        assert_eq!(line_col("ab\ncd", 4), (2, 2));
+        assert_eq!(line_col("ab\ncd", 100), (2, 3));
+    }
+}
@@ -0,0 +1,407 @@
+//! Subset of TOML read by the configuration file, tables of keys with
+//! strings, integers and booleans
+//!
+//! Arrays, inline and nested tables, floats, dates and multi-line strings are
+//! errors that say so, instead of being read differently than TOML would
+
+use crate::diagnostic::Diagnostic;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl Value {
+    /// Name of the type with an article, for error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+        }
+    }
+}
+
+/// `key = value` with where both are in the source
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub key_span: (usize, usize),
+    pub value_span: (usize, usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    /// Empty for the keys before the first header
+    pub name: String,
+
+    /// Span of the header, empty at the start for the keys before it
+    pub span: (usize, usize),
+
+    /// In the order they were written
+    pub entries: Vec<Entry>,
+}
+
+/// Parses the whole document, the first table has the keys before any header
+/// and is there even if there are none
+pub fn parse(source: &str) -> Result<Vec<Table>, Diagnostic> {
+    let mut parser = Parser { source, pos: 0 };
+    let mut tables = vec![Table {
+        name: String::new(),
+        span: (0, 0),
+        entries: vec![],
+    }];
+
+    while parser.pos < source.len() {
+        parser.skip_whitespace();
+
+        match parser.peek() {
+            Some('\n') | Some('\r') | Some('#') => {},
+            Some('[') => {
+                let table = parser.header()?;
+                if tables.iter().any(|x| x.name == table.name && !x.name.is_empty()) {
+                    return Err(Diagnostic::error(format!("table `[{}]` is defined twice", table.name), table.span.0, table.span.1));
+                }
+
+                tables.push(table);
+            },
+            Some(_) => {
+                let entry = parser.entry()?;
+                let table = tables.last_mut().expect("there is always a table");
+                if table.entries.iter().any(|x| x.key == entry.key) {
+                    return Err(Diagnostic::error(format!("`{}` is defined twice", entry.key), entry.key_span.0, entry.key_span.1));
+                }
+
+                table.entries.push(entry);
+            },
+            None => break,
+        }
+
+        parser.end_of_line()?;
+    }
+
+    Ok(tables)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+
+    /// Byte index of the next character
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>, start: usize) -> Result<T, Diagnostic> {
+        Err(Diagnostic::error(message, start, self.pos.max(start + 1)))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    /// Spaces and tabs, newlines end keys and values
+    fn skip_whitespace(&mut self) {
+        while let Some(ch @ (' ' | '\t')) = self.peek() {
+            self.pos += ch.len_utf8();
+        }
+    }
+
+    /// Skips a comment and the newline, anything else left on the line is an
+    /// error
+    fn end_of_line(&mut self) -> Result<(), Diagnostic> {
+        self.skip_whitespace();
+        if self.peek() == Some('#') {
+            self.pos += self.rest().find('\n').unwrap_or(self.rest().len());
+        }
+
+        if self.rest().starts_with("\r\n") {
+            self.pos += 2;
+        } else if self.peek() == Some('\n') {
+            self.pos += 1;
+        } else if self.peek().is_some() {
+            let start = self.pos;
+            self.pos += self.rest().find(['\n', '#']).unwrap_or(self.rest().len());
+            return self.error("expected the end of the line", start);
+        }
+
+        Ok(())
+    }
+
+    /// `[name]`
+    fn header(&mut self) -> Result<Table, Diagnostic> {
+        let start = self.pos;
+        if self.rest().starts_with("[[") {
+            self.pos += 2;
+            return self.error("arrays of tables are not supported", start);
+        }
+
+        self.pos += 1;
+        self.skip_whitespace();
+        let (name, _) = self.key()?;
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(']') => self.pos += 1,
+            Some('.') => return self.error("nested tables are not supported", start),
+            _ => return self.error("expected `]`", self.pos),
+        }
+
+        Ok(Table {
+            name,
+            span: (start, self.pos),
+            entries: vec![],
+        })
+    }
+
+    /// `key = value`
+    fn entry(&mut self) -> Result<Entry, Diagnostic> {
+        let (key, key_span) = self.key()?;
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('=') => self.pos += 1,
+            Some('.') => return self.error("dotted keys are not supported", key_span.0),
+            _ => return self.error(format!("expected `=` after `{}`", key), self.pos),
+        }
+
+        self.skip_whitespace();
+        let start = self.pos;
+        let value = self.value()?;
+
+        Ok(Entry {
+            key,
+            value,
+            key_span,
+            value_span: (start, self.pos),
+        })
+    }
+
+    /// Bare or quoted key with its span
+    fn key(&mut self) -> Result<(String, (usize, usize)), Diagnostic> {
+        let start = self.pos;
+        let key = match self.peek() {
+            Some('"') => self.basic_string()?,
+            Some('\'') => self.literal_string()?,
+            _ => {
+                let len = self.rest().find(|x: char| !(x.is_ascii_alphanumeric() || x == '_' || x == '-')).unwrap_or(self.rest().len());
+                if len == 0 {
+                    return self.error("expected a key", start);
+                }
+
+                self.pos += len;
+                self.source[start..self.pos].to_string()
+            },
+        };
+
+        Ok((key, (start, self.pos)))
+    }
+
+    fn value(&mut self) -> Result<Value, Diagnostic> {
+        let start = self.pos;
+        let word_len = self.rest().find(|x: char| x.is_whitespace() || x == '#').unwrap_or(self.rest().len());
+        let word = &self.source[start..start + word_len];
+
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.error("arrays are not supported", start),
+            Some('{') => self.error("inline tables are not supported", start),
+            _ if word == "true" || word == "false" => {
+                self.pos += word_len;
+                Ok(Value::Boolean(word == "true"))
+            },
+            Some('+' | '-' | '0'..='9') => {
+                self.pos += word_len;
+                self.integer(word, start)
+            },
+            Some('\n' | '\r' | '#') | None => self.error("expected a value", start),
+            Some(_) => {
+                self.pos += word_len;
+                self.error(format!("`{}` is not a value, strings have to be quoted", word), start)
+            },
+        }
+    }
+
+    /// Decimal integer with optional sign and underscores between digits
+    fn integer(&self, word: &str, start: usize) -> Result<Value, Diagnostic> {
+        if word.contains(['.', 'e', 'E']) && !word.starts_with("0x") {
+            return self.error("floats are not supported", start);
+        }
+
+        if word.contains([':']) || word[1..].contains('-') {
+            return self.error("dates are not supported", start);
+        }
+
+        let digits = word.trim_start_matches(['+', '-']);
+        let valid = !digits.is_empty()
+            && !digits.starts_with('_')
+            && !digits.ends_with('_')
+            && !digits.contains("__")
+            && (digits == "0" || !digits.starts_with('0'))
+            && digits.chars().all(|x| x.is_ascii_digit() || x == '_');
+
+        if !valid {
+            return self.error(format!("`{}` is not a valid integer", word), start);
+        }
+
+        match word.replace('_', "").parse() {
+            Ok(x) => Ok(Value::Integer(x)),
+            Err(_) => self.error("integer is too large", start),
+        }
+    }
+
+    /// Characters of strings, control characters other than tabs are not
+    /// allowed in them
+    fn string_char(&mut self, start: usize) -> Result<char, Diagnostic> {
+        match self.peek() {
+            Some('\n' | '\r') | None => self.error("unterminated string", start),
+            Some(ch) if ch.is_control() && ch != '\t' => {
+                let at = self.pos;
+                self.pos += ch.len_utf8();
+                self.error("control characters have to be escaped", at)
+            },
+            Some(ch) => {
+                self.pos += ch.len_utf8();
+                Ok(ch)
+            },
+        }
+    }
+
+    /// `"..."` with escapes
+    fn basic_string(&mut self) -> Result<String, Diagnostic> {
+        let start = self.pos;
+        if self.rest().starts_with("\"\"\"") {
+            self.pos += 3;
+            return self.error("multi-line strings are not supported", start);
+        }
+
+        self.pos += 1;
+        let mut string = String::new();
+
+        loop {
+            let escape = self.pos;
+            match self.string_char(start)? {
+                '"' => return Ok(string),
+                '\\' => match self.string_char(start)? {
+                    '"' => string.push('"'),
+                    '\\' => string.push('\\'),
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    'e' => string.push('\u{1b}'),
+                    kind @ ('u' | 'U') => {
+                        let len = if kind == 'u' { 4 } else { 8 };
+                        let code = self.rest().get(..len)
+                            .filter(|x| x.chars().all(|x| x.is_ascii_hexdigit()))
+                            .and_then(|x| u32::from_str_radix(x, 16).ok())
+                            .and_then(char::from_u32);
+
+                        match code {
+                            Some(x) => {
+                                self.pos += len;
+                                string.push(x);
+                            },
+                            None => return self.error("invalid unicode escape", escape),
+                        }
+                    },
+                    _ => return self.error("invalid escape", escape),
+                },
+                ch => string.push(ch),
+            }
+        }
+    }
+
+    /// `'...'` taken as it is
+    fn literal_string(&mut self) -> Result<String, Diagnostic> {
+        let start = self.pos;
+        if self.rest().starts_with("'''") {
+            self.pos += 3;
+            return self.error("multi-line strings are not supported", start);
+        }
+
+        self.pos += 1;
+        let mut string = String::new();
+
+        loop {
+            match self.string_char(start)? {
+                '\'' => return Ok(string),
+                ch => string.push(ch),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        parse(source).unwrap_err().message
+    }
+
+    #[test]
+    fn tables_and_values() {
+        let tables = parse("top = 1\n\n[a] # comment\ns = \"x\\ty\\u00e9\"\nl = 'c:\\n' \nb = true\ni = -1_000\n\"quoted key\" = false\n").unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].name, "");
+        assert_eq!(tables[0].entries[0].value, Value::Integer(1));
+
+        let values: Vec<(&str, &Value)> = tables[1].entries.iter().map(|x| (x.key.as_str(), &x.value)).collect();
+        assert_eq!(values, [
+            ("s", &Value::String("x\ty\u{e9}".to_string())),
+            ("l", &Value::String("c:\\n".to_string())),
+            ("b", &Value::Boolean(true)),
+            ("i", &Value::Integer(-1000)),
+            ("quoted key", &Value::Boolean(false)),
+        ]);
+    }
+
+    #[test]
+    fn spans() {
+        let source = "[t]\nkey = \"value\"\n";
+        let tables = parse(source).unwrap();
+        assert_eq!(&source[tables[1].span.0..tables[1].span.1], "[t]");
+
+        let entry = &tables[1].entries[0];
+        assert_eq!(&source[entry.key_span.0..entry.key_span.1], "key");
+        assert_eq!(&source[entry.value_span.0..entry.value_span.1], "\"value\"");
+    }
+
+    #[test]
+    fn unsupported() {
+        assert_eq!(error("a = [1]"), "arrays are not supported");
+        assert_eq!(error("a = {b = 1}"), "inline tables are not supported");
+        assert_eq!(error("[[a]]"), "arrays of tables are not supported");
+        assert_eq!(error("[a.b]"), "nested tables are not supported");
+        assert_eq!(error("a.b = 1"), "dotted keys are not supported");
+        assert_eq!(error("a = 1.5"), "floats are not supported");
+        assert_eq!(error("a = 1979-05-27"), "dates are not supported");
+        assert_eq!(error("a = \"\"\"x\"\"\""), "multi-line strings are not supported");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(error("a = 1\na = 2"), "`a` is defined twice");
+        assert_eq!(error("[a]\n[a]"), "table `[a]` is defined twice");
+        assert_eq!(error("a = yes"), "`yes` is not a value, strings have to be quoted");
+        assert_eq!(error("a = 01"), "`01` is not a valid integer");
+        assert_eq!(error("a = 1__0"), "`1__0` is not a valid integer");
+        assert_eq!(error("a = 99999999999999999999"), "integer is too large");
+        assert_eq!(error("a = \"x"), "unterminated string");
+        assert_eq!(error("a = \"\\q\""), "invalid escape");
+        assert_eq!(error("a = \"\\ud800\""), "invalid unicode escape");
+        assert_eq!(error("a = \"\u{7}\""), "control characters have to be escaped");
+        assert_eq!(error("a = 1 2"), "expected the end of the line");
+        assert_eq!(error("a ="), "expected a value");
+        assert_eq!(error("a"), "expected `=` after `a`");
+    }
+}
@@ -0,0 +1,49 @@
+//! Tools of the command line against scripts with the same name
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rush(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("running rush");
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn tools() {
+    let dir = scratch_dir("cli-tools");
+
+    let output = rush(&dir, &["fmt"], "echo   a\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "echo a\n");
+
+    let output = rush(&dir, &["lint"], "echo $1\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn scripts_win_over_tools() {
+    let dir = scratch_dir("cli-scripts");
+    std::fs::write(dir.join("lint"), "echo lint script $1\n").unwrap();
+    std::fs::write(dir.join("fmt"), "echo fmt script $1\n").unwrap();
+
+    let output = rush(&dir, &["lint", "x"], "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "lint script x\n");
+
+    let output = rush(&dir, &["--posix", "fmt", "x"], "echo   a\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "fmt script x\n");
+}
@@ -0,0 +1,235 @@
+//! Random scripts from the grammar, limited to what behaves the same in every
+//! POSIX shell, so any difference is a bug in rush
+//!
+//! Words never start with `-` and have no backslashes as `echo` differs
+//! between shells there, functions only call the ones defined before them so
+//! nothing recurses, and the only files read are ones the script may have
+//! written with errors thrown away
+
+/// xorshift64*, good enough to pick from the grammar and the same everywhere
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // zero would stay zero forever
+        Self(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// True `percent` of the time
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+/// Characters of unquoted words, nothing special to the shell
+const LITERAL: &[char] = &['a', 'b', 'c', 'x', 'y', 'z', '0', '1', '9', '_', '.', ',', ':', '+', '%', '/', '='];
+
+/// Extra characters that are safe inside single quotes
+const SINGLE_QUOTED: &[char] = &[' ', '$', '"', '*', '?', '|', ';', '&', '<', '>', '(', ')', '#', '~'];
+
+/// Extra characters that are safe inside double quotes
+const DOUBLE_QUOTED: &[char] = &[' ', '\'', '*', '?', '|', ';', '&', '<', '>', '(', ')', '#', '~'];
+
+const NAMES: &[&str] = &["a", "b", "c", "long_name", "X1"];
+
+/// Values of `IFS` besides the default, whitespace and not
+const IFS: &[&str] = &["", " ", ":", " :", ",."];
+
+const FILES: &[&str] = &["out", "log", "two words"];
+
+/// Commands after the first in a pipeline, they read all of stdin so the
+/// commands before them never write to a closed pipe
+const FILTERS: &[&str] = &["cat", "tr a-m n-z", "tr -d ' '", "sort", "sort -r", "sed -n 1p", "tail -n 1", "wc -l", "uniq"];
+
+struct Generator {
+    rng: Rng,
+
+    /// Functions defined so far, named `f0`, `f1` and on
+    functions: usize,
+    in_function: bool,
+}
+
+impl Generator {
+    fn literal(&mut self, len: usize) -> String {
+        (0..len).map(|_| self.rng.pick(LITERAL)).collect()
+    }
+
+    fn variable(&mut self) -> String {
+        let name = match self.rng.below(10) {
+            0 => "1",
+            1 => "#",
+            2 => "?",
+            _ => self.rng.pick(NAMES),
+        };
+
+        match self.rng.chance(30) {
+            true => format!("${{{}}}", name),
+            false => format!("${}", name),
+        }
+    }
+
+    fn part(&mut self) -> String {
+        match self.rng.below(10) {
+            0..=3 => {
+                let len = 1 + self.rng.below(4);
+                self.literal(len)
+            },
+            4 | 5 => {
+                let text: String = (0..self.rng.below(6))
+                    .map(|_| match self.rng.chance(30) {
+                        true => self.rng.pick(SINGLE_QUOTED),
+                        false => self.rng.pick(LITERAL),
+                    })
+                    .collect();
+
+                format!("'{}'", text)
+            },
+            6 | 7 => {
+                let mut text = String::new();
+                for _ in 0..self.rng.below(4) {
+                    match self.rng.below(3) {
+                        0 => text.push_str(&self.variable()),
+                        1 => text.push(self.rng.pick(DOUBLE_QUOTED)),
+                        _ => text.push(self.rng.pick(LITERAL)),
+                    }
+                }
+
+                format!("\"{}\"", text)
+            },
+            _ => self.variable(),
+        }
+    }
+
+    fn word(&mut self) -> String {
+        (0..1 + self.rng.below(3)).map(|_| self.part()).collect()
+    }
+
+    fn words(&mut self, max: usize) -> String {
+        (0..self.rng.below(max + 1)).map(|_| format!(" {}", self.word())).collect()
+    }
+
+    fn file(&mut self) -> String {
+        format!("'{}'", self.rng.pick(FILES))
+    }
+
+    fn assignment(&mut self) -> String {
+        match self.rng.chance(10) {
+            true => format!("IFS='{}'", self.rng.pick(IFS)),
+            false => format!("{}={}", self.rng.pick(NAMES), self.word()),
+        }
+    }
+
+    /// Simple command, `first` if it is the first of its pipeline
+    fn command(&mut self, first: bool) -> String {
+        if !first {
+            return self.rng.pick(FILTERS).to_string();
+        }
+
+        let command = match self.rng.below(20) {
+            0..=4 => format!("echo{}", self.words(4)),
+            5 => format!("printf '[%s]\\n'{}", self.words(4)),
+            6 => format!("echo{} >&2", self.words(2)),
+            7 => format!("echo{} > {}", self.words(3), self.file()),
+            8 => format!("echo{} >> {}", self.words(3), self.file()),
+            9 => format!("cat {} 2>/dev/null", self.file()),
+            10 | 11 => self.assignment(),
+            12 => {
+                let name = self.rng.pick(NAMES);
+                format!("{}={} printenv {}", name, self.word(), name)
+            },
+            13 => format!("export {}", self.assignment()),
+            14 => format!("unset {}", self.rng.pick(NAMES)),
+            15 => format!("set --{}", self.words(3)),
+            16 | 17 => self.rng.pick(&["true", "false"]).to_string(),
+            18 | 19 if self.functions > 0 => format!("f{}{}", self.rng.below(self.functions), self.words(3)),
+            _ => format!("echo{}", self.words(2)),
+        };
+
+        command
+    }
+
+    fn pipeline(&mut self) -> String {
+        // shells differ on how they negate or pipe these
+        if self.in_function && self.rng.chance(5) {
+            return format!("return {}", self.rng.below(4));
+        }
+
+        if self.rng.chance(1) {
+            return format!("exit {}", self.rng.below(4));
+        }
+
+        let mut pipeline = String::new();
+        if self.rng.chance(10) {
+            pipeline.push_str("! ");
+        }
+
+        pipeline.push_str(&self.command(true));
+        for _ in 0..self.rng.below(3).saturating_sub(1) {
+            pipeline.push_str(" | ");
+            pipeline.push_str(&self.command(false));
+        }
+
+        pipeline
+    }
+
+    fn and_or(&mut self) -> String {
+        let mut and_or = self.pipeline();
+        for _ in 0..self.rng.below(4).saturating_sub(2) {
+            and_or.push_str(self.rng.pick(&[" && ", " || "]));
+            and_or.push_str(&self.pipeline());
+        }
+
+        and_or
+    }
+
+    fn function(&mut self) -> String {
+        self.in_function = true;
+        let body: Vec<String> = (0..1 + self.rng.below(3)).map(|_| self.and_or()).collect();
+        self.in_function = false;
+
+        // defined after the body so it can not call itself
+        let name = format!("f{}", self.functions);
+        self.functions += 1;
+
+        format!("{}() {{ {}; }}", name, body.join("; "))
+    }
+
+    fn line(&mut self) -> String {
+        match self.rng.chance(15) {
+            true => self.function(),
+            false => self.and_or(),
+        }
+    }
+}
+
+/// Top level commands of a random script, one per line
+pub fn script(seed: u64) -> Vec<String> {
+    let mut generator = Generator {
+        rng: Rng::new(seed),
+        functions: 0,
+        in_function: false,
+    };
+
+    let len = 1 + generator.rng.below(12);
+    (0..len).map(|_| generator.line()).collect()
+}
+
+pub fn join(lines: &[String]) -> String {
+    lines.iter().map(|x| format!("{}\n", x)).collect()
+}
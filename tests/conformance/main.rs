@@ -0,0 +1,205 @@
+//! Differential tests, scripts are run by rush and by a reference shell and
+//! have to print the same and exit with the same status
+//!
+//! The reference is `/bin/sh` unless `RUSH_REFERENCE_SHELL` is set. Scripts
+//! in `scripts/` are run as they are, a `# stderr: nonempty` line in one
+//! means the error messages differ between shells so only whether there was
+//! one is compared. Random scripts from the grammar are also run, the seed of
+//! the first one and how many are set with `RUSH_CONFORMANCE_SEED` and
+//! `RUSH_CONFORMANCE_CASES`
+//!
+//! ```sh
+//! cargo test --features conformance
+//! ```
+
+mod generate;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Scripts are killed after this, nothing in the grammar loops
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a shell did with a script
+#[derive(Debug)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+
+    /// `None` if it was killed by a signal or timed out
+    status: Option<i32>,
+}
+
+fn reference() -> PathBuf {
+    std::env::var_os("RUSH_REFERENCE_SHELL").map_or_else(|| PathBuf::from("/bin/sh"), PathBuf::from)
+}
+
+fn rush() -> PathBuf {
+    PathBuf::from(env!("CARGO_BIN_EXE_rush"))
+}
+
+/// Empty directory for a single run, tests run on multiple threads
+fn scratch_dir() -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!("rush-conformance-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("creating the scratch directory");
+
+    dir
+}
+
+/// Runs the script as `script.sh` in an empty directory with a minimal
+/// environment, so the shells only differ in the shell itself
+fn run(shell: &Path, args: &[&str], script: &str) -> Outcome {
+    let dir = scratch_dir();
+    std::fs::write(dir.join("script.sh"), script).expect("writing the script");
+
+    let mut child = Command::new(shell)
+        .args(args)
+        .arg("script.sh")
+        .current_dir(&dir)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .env("HOME", &dir)
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|err| panic!("{}: {}", shell.display(), err));
+
+    // read on threads so a full pipe does not block the shell
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    let mut stderr = child.stderr.take().expect("piped stderr");
+    let stdout = std::thread::spawn(move || std::io::read_to_string(&mut stdout).unwrap_or_default());
+    let stderr = std::thread::spawn(move || std::io::read_to_string(&mut stderr).unwrap_or_default());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("waiting for the shell") {
+            break status.code();
+        }
+
+        if started.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+
+        std::thread::sleep(Duration::from_millis(1));
+    };
+
+    let outcome = Outcome {
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+        status,
+    };
+
+    let _ = std::fs::remove_dir_all(&dir);
+    outcome
+}
+
+/// How the outcomes of rush and the reference differ, empty if they do not,
+/// with `exact_stderr` false only whether something was printed to stderr
+/// is compared
+fn differences(rush: &Outcome, reference: &Outcome, exact_stderr: bool) -> Vec<String> {
+    let mut differences = vec![];
+    if rush.status != reference.status {
+        differences.push(format!("status: rush {:?}, reference {:?}", rush.status, reference.status));
+    }
+
+    if rush.stdout != reference.stdout {
+        differences.push(format!("stdout:\n  rush:      {:?}\n  reference: {:?}", rush.stdout, reference.stdout));
+    }
+
+    let stderr_differs = match exact_stderr {
+        true => rush.stderr != reference.stderr,
+        false => rush.stderr.is_empty() != reference.stderr.is_empty(),
+    };
+
+    if stderr_differs {
+        differences.push(format!("stderr:\n  rush:      {:?}\n  reference: {:?}", rush.stderr, reference.stderr));
+    }
+
+    differences
+}
+
+fn compare(script: &str, exact_stderr: bool) -> Vec<String> {
+    // compared as `sh`, without the extensions of rush
+    differences(&run(&rush(), &["--posix"], script), &run(&reference(), &[], script), exact_stderr)
+}
+
+#[test]
+fn scripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/scripts");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .expect("reading the scripts")
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| x.extension().is_some_and(|x| x == "sh"))
+        .collect();
+    paths.sort();
+
+    let mut failures = vec![];
+    for path in &paths {
+        let script = std::fs::read_to_string(path).expect("reading the script");
+        let exact_stderr = !script.lines().any(|x| x.trim() == "# stderr: nonempty");
+
+        let differences = compare(&script, exact_stderr);
+        if !differences.is_empty() {
+            failures.push(format!("{}\n{}", path.display(), differences.join("\n")));
+        }
+    }
+
+    assert!(!paths.is_empty(), "no scripts in {}", dir.display());
+    assert!(failures.is_empty(), "{} of {} scripts differ\n\n{}", failures.len(), paths.len(), failures.join("\n\n"));
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(x) => x.parse().unwrap_or_else(|_| panic!("{}: {}: not a number", name, x)),
+        Err(_) => default,
+    }
+}
+
+/// Drops lines of the script while it still differs, lines that are needed
+/// by later ones like function definitions only make later commands fail so
+/// the messages are not compared here
+fn shrink(mut lines: Vec<String>) -> Vec<String> {
+    let mut i = 0;
+    while i < lines.len() {
+        let mut shorter = lines.clone();
+        shorter.remove(i);
+
+        if shorter.is_empty() || compare(&generate::join(&shorter), false).is_empty() {
+            i += 1;
+        } else {
+            lines = shorter;
+        }
+    }
+
+    lines
+}
+
+#[test]
+fn generated() {
+    let seed = env_number("RUSH_CONFORMANCE_SEED", 1);
+    let cases = env_number("RUSH_CONFORMANCE_CASES", 200);
+
+    for seed in seed..seed + cases {
+        let lines = generate::script(seed);
+        if compare(&generate::join(&lines), false).is_empty() {
+            continue;
+        }
+
+        let script = generate::join(&shrink(lines));
+        panic!(
+            "generated script differs, seed {}, smallest script that still differs:\n\n{}\n{}",
+            seed,
+            script,
+            compare(&script, false).join("\n"),
+        );
+    }
+}
@@ -0,0 +1,11 @@
+true && echo and
+false && echo not
+false || echo or
+true || echo not
+false && echo not || echo fallback
+true && false || echo both
+echo $?
+true &&
+echo continued
+false ||
+echo continued
@@ -0,0 +1,6 @@
+# comment on its own line
+echo a # trailing comment
+echo b#not-a-comment
+echo '# quoted'
+    # indented comment
+echo c
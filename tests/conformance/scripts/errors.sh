@@ -0,0 +1,6 @@
+# stderr: nonempty
+no_such_command
+echo $?
+f() { no_such_command_either; }
+f
+echo $?
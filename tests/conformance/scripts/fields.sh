@@ -0,0 +1,14 @@
+# unquoted expansions are split on IFS, quoted ones never are
+x=' a  b	c '
+printf '[%s]\n' $x
+printf '[%s]\n' "$x"
+IFS=:
+y=a:b::c
+printf '[%s]\n' $y
+IFS=' :'
+z='a : b:c  d'
+printf '[%s]\n' $z
+IFS=
+printf '[%s]\n' $x
+empty=
+printf '[%s]\n' $empty "$empty"
@@ -0,0 +1,16 @@
+greet() { echo "hello $1, $# arguments"; }
+greet world
+greet "two words" b c
+status() { return 3; }
+status
+echo $?
+outer() { inner() { echo inner; }; echo outer; }
+outer
+inner
+set -- a b
+args() { echo "$1" $#; }
+args x
+echo "$1" $#
+assign() { global=set; }
+assign
+echo $global
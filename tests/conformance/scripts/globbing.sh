@@ -0,0 +1,11 @@
+echo x > b.txt
+echo x > a.txt
+echo x > .hidden
+echo *.txt
+echo '*.txt' "*".txt
+echo ?.txt
+echo [ab].txt
+echo nothing*
+echo .h*
+pattern='*.txt'
+echo $pattern "$pattern"
@@ -0,0 +1,14 @@
+echo b a c | tr ' ' '\n' | sort
+echo hello | tr a-z A-Z | cat
+false | true
+echo $?
+true | false
+echo $?
+! true
+echo $?
+! false | false
+echo $?
+x=1 | cat
+echo "[$x]"
+f() { echo from function; }
+f | tr a-z A-Z
@@ -0,0 +1,19 @@
+# "$@" is a field per parameter, "$*" joins them with the first character of IFS
+set -- 'a b' '' c
+printf '[%s]\n' "$@"
+printf '[%s]\n' $@
+printf '[%s]\n' "$*"
+printf '[%s]\n' $*
+printf '[%s]\n' "x$@y"
+IFS=:
+printf '[%s]\n' "$*"
+printf '[%s]\n' $*
+IFS=
+printf '[%s]\n' "$*"
+unset IFS
+set --
+printf '[%s]\n' "$@"
+printf '[%s]\n' "x$@y"
+printf '[%s]\n' "$*"
+f() { printf '<%s>\n' "$@"; echo $#; }
+f "1 2" 3
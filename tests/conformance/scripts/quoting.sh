@@ -0,0 +1,11 @@
+# single quotes keep everything, double quotes only expand variables
+x='a  b'
+echo 'single $x "double"'
+echo "double $x 'single'"
+echo "$x" $x
+echo 'it''s' "a"'b'c
+echo "" x ''
+echo "a'b" 'c"d'
+echo \$x \"
+# digits are only words, even when they do not fit an integer
+echo 12345678901234567890 0x1f 0xffffffffffffffffffff
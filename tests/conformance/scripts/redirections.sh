@@ -0,0 +1,12 @@
+echo first > out
+echo second >> out
+cat < out
+cat out 'missing' 2>/dev/null
+echo $?
+echo to stderr >&2
+echo also 1>&2
+f() { echo err >&2; echo out; }
+f 2>log >'two words'
+cat log 'two words'
+echo a 2>&1 > out
+cat out
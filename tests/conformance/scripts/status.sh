@@ -0,0 +1,11 @@
+true
+echo $?
+false
+echo $?
+f() { false; return; }
+f
+echo $?
+cat missing 2>/dev/null
+echo $?
+exit 4
+echo never
@@ -0,0 +1,17 @@
+a=1
+b="$a 2"
+echo $a $b "${a}x" ${b}y
+a=
+echo "[$a]"
+unset b
+echo "[$b]"
+c=one d=two
+echo $c $d
+x=outer
+y=$x
+x=changed
+echo $y $x
+X=1 printenv X
+echo "[$X]"
+export E=exported
+printenv E
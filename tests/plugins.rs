@@ -0,0 +1,83 @@
+//! Loading the example plugin into scripts, `-c` and `--posix`
+//!
+//! The plugin is built with its own target directory as the one of the tests
+//! is locked while they run
+
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::OnceLock;
+
+/// Plugin directory with only the example plugin in it, built once
+fn plugin_dir() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+    DIR.get_or_init(|| {
+        let target = Path::new(env!("CARGO_TARGET_TMPDIR")).join("plugin");
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--quiet", "--example", "plugin", "--target-dir"])
+            .arg(&target)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .status()
+            .expect("running cargo");
+        assert!(status.success(), "building the example plugin failed");
+
+        let name = format!("libplugin.{}", std::env::consts::DLL_EXTENSION);
+        let dir = target.join("plugins");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy(target.join("debug/examples").join(&name), dir.join(&name)).unwrap();
+
+        dir
+    })
+}
+
+fn rush(plugins: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(args)
+        .env("RUSH_PLUGIN_DIR", plugins)
+        .output()
+        .expect("running rush")
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+#[test]
+fn builtins_of_plugins() {
+    let script = Path::new(env!("CARGO_TARGET_TMPDIR")).join("plugin-script.sh");
+    std::fs::write(&script, "hello\nhello rush | cat\n").unwrap();
+
+    let output = rush(plugin_dir(), &[script.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "hello world\nhello rush\n", "{}", stderr(&output));
+
+    let output = rush(plugin_dir(), &["-c", "hello there; echo $?"]);
+    assert_eq!(stdout(&output), "hello there\n0\n", "{}", stderr(&output));
+}
+
+#[test]
+fn not_in_posix_mode() {
+    let output = rush(plugin_dir(), &["--posix", "-c", "hello"]);
+    assert_eq!(output.status.code(), Some(127));
+    assert!(stderr(&output).contains("hello: command not found"), "{}", stderr(&output));
+}
+
+#[test]
+fn loaded_only_for_unknown_commands() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("broken-plugins");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(format!("broken.{}", std::env::consts::DLL_EXTENSION)), "not a library").unwrap();
+
+    // builtins and commands in PATH never open the plugins
+    let output = rush(&dir, &["-c", "set -- a; true; echo $1"]);
+    assert_eq!((stdout(&output), stderr(&output)), ("a\n", ""));
+
+    let output = rush(&dir, &["-c", "rush-no-such-command"]);
+    assert!(stderr(&output).contains("rush: plugin: "), "{}", stderr(&output));
+    assert!(stderr(&output).contains("rush-no-such-command: command not found"), "{}", stderr(&output));
+}